@@ -1,17 +1,31 @@
 use axum::{
     Json, Router,
-    extract::{Path, State},
-    http::StatusCode,
+    extract::{Path, Query, Request, State},
+    http::{StatusCode, header},
+    middleware::{self, Next},
+    response::{
+        IntoResponse, Response,
+        sse::{self, KeepAlive, Sse},
+    },
     routing::{delete, get, post, put},
 };
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
 use std::sync::Arc;
+use subtle::ConstantTimeEq;
+use tokio::sync::{broadcast, mpsc};
+use tokio_rustls::TlsAcceptor;
+use tokio_stream::{Stream, StreamExt, wrappers::BroadcastStream};
 use tower_http::cors::{Any, CorsLayer};
 use tower_http::services::ServeDir;
 
-use crate::Server;
 use crate::config::EnvConfig;
-use crate::model::{Health, Notification};
-use crate::sources::{SourceConfig, SourceInfo};
+use crate::model::{Health, Notification, Post, PostEvent, VacuumResult, Version, WebhookTest};
+use crate::sources::{BulkResult, SourceConfig, SourceDebug, SourceInfo, SourceStats};
+use crate::tls::{self, TlsListener};
+use crate::webhook::breaker::TargetStatus;
+use crate::websocket::{self, Message};
+use crate::{Server, SourceError, openapi};
 
 /// # Web API and dashboard for managing [Server] sources.
 ///
@@ -23,9 +37,16 @@ use crate::sources::{SourceConfig, SourceInfo};
 /// |--------|------|---------|
 /// | `GET` | `/sources` | [get_all_sources] |
 /// | `POST` | `/sources` | [add_source] |
+/// | `POST` | `/sources/bulk` | [add_sources] |
 /// | `GET` | `/sources/{id}` | [get_source] |
 /// | `PUT` | `/sources/{id}` | [update_source] |
 /// | `DELETE` | `/sources/{id}` | [remove_source] |
+/// | `POST` | `/sources/{id}/resume` | [resume_source] |
+/// | `POST` | `/sources/{id}/restart` | [restart_source] |
+/// | `POST` | `/sources/{id}/backfill?pages=` | [backfill_source] |
+/// | `GET` | `/sources/{id}/stats` | [get_source_stats] |
+/// | `POST` | `/sources/{id}/test-webhook` | [test_webhook] |
+/// | `GET` | `/sources/{id}/debug?html=` | [debug_source] |
 ///
 /// ### Notifications
 ///
@@ -34,60 +55,160 @@ use crate::sources::{SourceConfig, SourceInfo};
 /// | `GET` | `/notifications` | [get_notifications] |
 /// | `POST` | `/notification/{id}` | [reply_notification] |
 ///
+/// ### Config
+///
+/// | Method | Path | Handler |
+/// |--------|------|---------|
+/// | `GET` | `/config` | [get_config] |
+/// | `PUT` | `/config` | [update_config] |
+///
 /// ### Other
 ///
 /// | Method | Path | Handler |
 /// |--------|------|---------|
+/// | `GET` | `/webhooks` | [get_webhook_status] |
+/// | `GET` | `/search?q=&limit=` | [search_posts] |
+/// | `POST` | `/admin/vacuum` | [vacuum] |
+/// | `GET` | `/events` | [events] |
+/// | `GET` | `/ws` | [ws] |
 /// | `GET` | `/health` | [health] |
+/// | `GET` | `/openapi.json` | [openapi] |
+///
+/// When `api_token` is set, all endpoints except `/health`, `/openapi.json`
+/// and the dashboard require an `Authorization: Bearer <token>` header,
+/// see [auth].
+///
+/// The api is served over HTTPS when `tls_cert_path` and `tls_key_path`
+/// are set, see [crate::tls].
 pub struct Api {
-    env: EnvConfig,
     router: Router,
     server: Arc<Server>,
+    tls: Option<TlsAcceptor>,
 }
 
 impl Api {
     /// Create a new instance of [Api]
     pub async fn new(server: Arc<Server>) -> anyhow::Result<Self> {
-        let env = EnvConfig::from_dotenv()?;
-        tracing::info!("starting web api on port {}", env.port);
-        let cors = CorsLayer::new()
-            .allow_origin(Any)
-            .allow_methods(Any)
-            .allow_headers(Any);
-
-        let router = Router::new()
-            .route("/sources/types", get(get_source_types))
-            .route("/sources", get(get_all_sources))
-            .route("/sources", post(add_source))
-            .route("/sources/{id}", get(get_source))
-            .route("/sources/{id}", put(update_source))
-            .route("/sources/{id}", delete(remove_source))
-            .route("/notifications", get(get_notifications))
-            .route("/notifications/{id}", post(reply_notification))
-            .route("/health", get(health))
-            .fallback_service(ServeDir::new("static"))
-            .layer(cors)
-            .with_state(Arc::clone(&server));
+        let env = server.env.get();
+        let tls = match (&env.tls_cert_path, &env.tls_key_path) {
+            (Some(cert), Some(key)) => Some(tls::acceptor(cert, key)?),
+            _ => None,
+        };
+        let addr = env.bind_addr()?;
+        tracing::info!(
+            "starting web api on {addr} ({})",
+            if tls.is_some() { "https" } else { "http" }
+        );
+        if addr.ip().is_unspecified() && env.api_token.is_none() {
+            tracing::warn!(
+                "web api is reachable on all interfaces without api_token, set BIND_ADDRESS=127.0.0.1 to only allow local access"
+            );
+        }
+
         Ok(Self {
-            env,
-            router,
+            router: router(Arc::clone(&server)),
             server,
+            tls,
         })
     }
 
     /// Run [Api]
     pub async fn run(&self) -> anyhow::Result<()> {
-        let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{}", self.env.port)).await?;
+        let addr = self.server.env.get().bind_addr()?;
+        let listener = tokio::net::TcpListener::bind(addr).await?;
+        let shutdown = self.server.shutdown.clone().cancelled_owned();
 
-        axum::serve(listener, self.router.clone())
-            .with_graceful_shutdown(self.server.shutdown.clone().cancelled_owned())
-            .await?;
+        match &self.tls {
+            Some(acceptor) => {
+                let listener = TlsListener::new(listener, acceptor.clone())?;
+                axum::serve(listener, self.router.clone())
+                    .with_graceful_shutdown(shutdown)
+                    .await?;
+            }
+            None => {
+                axum::serve(listener, self.router.clone())
+                    .with_graceful_shutdown(shutdown)
+                    .await?;
+            }
+        }
 
         tracing::info!("web api stopped");
         Ok(())
     }
 }
 
+/// Build [Router] with all endpoints and the dashboard, unless
+/// `serve_dashboard` is disabled.
+pub fn router(server: Arc<Server>) -> Router {
+    let cors = CorsLayer::new()
+        .allow_origin(Any)
+        .allow_methods(Any)
+        .allow_headers(Any);
+
+    let router = Router::new()
+        .route("/sources/types", get(get_source_types))
+        .route("/sources", get(get_all_sources))
+        .route("/sources", post(add_source))
+        .route("/sources/bulk", post(add_sources))
+        .route("/sources/{id}", get(get_source))
+        .route("/sources/{id}", put(update_source))
+        .route("/sources/{id}", delete(remove_source))
+        .route("/sources/{id}/resume", post(resume_source))
+        .route("/sources/{id}/restart", post(restart_source))
+        .route("/sources/{id}/backfill", post(backfill_source))
+        .route("/sources/{id}/stats", get(get_source_stats))
+        .route("/sources/{id}/test-webhook", post(test_webhook))
+        .route("/sources/{id}/debug", get(debug_source))
+        .route("/notifications", get(get_notifications))
+        .route("/notifications/{id}", post(reply_notification))
+        .route("/config", get(get_config))
+        .route("/config", put(update_config))
+        .route("/webhooks", get(get_webhook_status))
+        .route("/search", get(search_posts))
+        .route("/admin/vacuum", post(vacuum))
+        .route("/events", get(events))
+        .route("/ws", get(ws))
+        .route_layer(middleware::from_fn_with_state(server.clone(), auth))
+        .route("/health", get(health))
+        .route("/version", get(version))
+        .route("/openapi.json", get(openapi));
+
+    let env = server.env.get();
+    let router = if env.serve_dashboard {
+        if !std::path::Path::new(&env.static_dir).is_dir() {
+            tracing::warn!(
+                "dashboard directory {:?} not found, set STATIC_DIR or SERVE_DASHBOARD=false",
+                env.static_dir
+            );
+        }
+        router.fallback_service(ServeDir::new(&env.static_dir))
+    } else {
+        router
+    };
+
+    router.layer(cors).with_state(server)
+}
+
+/// Require `Authorization: Bearer <token>` header when `api_token` is set.
+pub async fn auth(
+    State(server): State<Arc<Server>>,
+    req: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    if let Some(token) = &server.env.get().api_token {
+        let expected = format!("Bearer {token}");
+        let provided = req.headers().get(header::AUTHORIZATION);
+
+        // Compared in constant time, so the time taken doesn't leak the token
+        let valid = provided.is_some_and(|v| v.as_bytes().ct_eq(expected.as_bytes()).into());
+        if !valid {
+            return Err(StatusCode::UNAUTHORIZED);
+        }
+    }
+
+    Ok(next.run(req).await)
+}
+
 pub async fn get_all_sources(
     State(server): State<Arc<Server>>,
 ) -> (StatusCode, Json<Vec<SourceInfo>>) {
@@ -112,15 +233,33 @@ pub async fn get_source_types(
     }
 }
 
+/// Create a source, responds with `201 Created` and its `Location`.
 pub async fn add_source(
     State(server): State<Arc<Server>>,
     Json(body): Json<SourceConfig>,
-) -> StatusCode {
-    if let Err(e) = server.add_source(&body).await {
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let source = server.add_source(&body).await.map_err(|e| {
         tracing::error!("failed to add source: {e}");
-        return StatusCode::INTERNAL_SERVER_ERROR;
-    }
-    StatusCode::OK
+        (source_error_status(&e), e.to_string())
+    })?;
+    let location = format!("/sources/{}", source.id);
+
+    Ok((
+        StatusCode::CREATED,
+        [(header::LOCATION, location)],
+        Json(source),
+    ))
+}
+
+/// Create many sources at once, with a result for each of them.
+pub async fn add_sources(
+    State(server): State<Arc<Server>>,
+    Json(body): Json<Vec<SourceConfig>>,
+) -> Result<Json<Vec<BulkResult>>, (StatusCode, String)> {
+    server.add_sources(body).await.map(Json).map_err(|e| {
+        tracing::error!("failed to import sources: {e}");
+        (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+    })
 }
 
 pub async fn get_source(
@@ -128,7 +267,8 @@ pub async fn get_source(
     Path(id): Path<String>,
 ) -> (StatusCode, Json<Option<SourceInfo>>) {
     match server.get_source(&id).await {
-        Ok(s) => (StatusCode::OK, Json(s)),
+        Ok(Some(s)) => (StatusCode::OK, Json(Some(s))),
+        Ok(None) => (StatusCode::NOT_FOUND, Json(None)),
         Err(e) => {
             tracing::error!("failed to get source: {e}");
             (StatusCode::INTERNAL_SERVER_ERROR, Json(None))
@@ -136,15 +276,41 @@ pub async fn get_source(
     }
 }
 
+pub async fn get_source_stats(
+    State(server): State<Arc<Server>>,
+    Path(id): Path<String>,
+) -> (StatusCode, Json<Option<SourceStats>>) {
+    match server.source_stats(&id).await {
+        Ok(Some(s)) => (StatusCode::OK, Json(Some(s))),
+        Ok(None) => (StatusCode::NOT_FOUND, Json(None)),
+        Err(e) => {
+            tracing::error!("failed to get source stats: {e}");
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(None))
+        }
+    }
+}
+
 pub async fn update_source(
     State(server): State<Arc<Server>>,
     Json(body): Json<SourceConfig>,
-) -> StatusCode {
-    if let Err(e) = server.update_source(&body).await {
+) -> Result<Json<SourceInfo>, (StatusCode, String)> {
+    server.update_source(&body).await.map(Json).map_err(|e| {
         tracing::error!("failed to update source: {e}");
-        return StatusCode::INTERNAL_SERVER_ERROR;
+        (source_error_status(&e), e.to_string())
+    })
+}
+
+/// Map [SourceError] to status code, other errors are `500`.
+fn source_error_status(e: &anyhow::Error) -> StatusCode {
+    match e.downcast_ref() {
+        Some(SourceError::Exists(_) | SourceError::Collision { .. }) => StatusCode::CONFLICT,
+        Some(SourceError::Invalid(_)) => StatusCode::BAD_REQUEST,
+        Some(SourceError::Spawn(_)) => StatusCode::BAD_GATEWAY,
+        Some(SourceError::NotFound(_)) => StatusCode::NOT_FOUND,
+        Some(SourceError::NotRunning(_) | SourceError::Running(_)) => StatusCode::CONFLICT,
+        Some(SourceError::ShuttingDown) => StatusCode::SERVICE_UNAVAILABLE,
+        None => StatusCode::INTERNAL_SERVER_ERROR,
     }
-    StatusCode::OK
 }
 
 pub async fn remove_source(
@@ -158,6 +324,121 @@ pub async fn remove_source(
     StatusCode::OK
 }
 
+pub async fn resume_source(
+    State(server): State<Arc<Server>>,
+    Path(id): Path<String>,
+) -> Result<(), (StatusCode, String)> {
+    server.resume_source(&id).await.map_err(|e| {
+        tracing::error!("failed to resume source: {e}");
+        (source_error_status(&e), e.to_string())
+    })
+}
+
+/// Stop and start a running source with a new client, keeps its stored posts.
+pub async fn restart_source(
+    State(server): State<Arc<Server>>,
+    Path(id): Path<String>,
+) -> Result<(), (StatusCode, String)> {
+    server.restart_source(&id).await.map_err(|e| {
+        tracing::error!("failed to restart source: {e}");
+        (source_error_status(&e), e.to_string())
+    })
+}
+
+#[derive(Deserialize)]
+pub struct BackfillQuery {
+    #[serde(default = "default_backfill_pages")]
+    pages: u32,
+}
+
+fn default_backfill_pages() -> u32 {
+    5
+}
+
+/// Import older posts of a running source, returns the number of imported posts.
+pub async fn backfill_source(
+    State(server): State<Arc<Server>>,
+    Path(id): Path<String>,
+    Query(query): Query<BackfillQuery>,
+) -> (StatusCode, Json<Option<usize>>) {
+    match server.backfill_source(&id, query.pages).await {
+        Ok(count) => (StatusCode::OK, Json(Some(count))),
+        Err(e) => {
+            tracing::error!("failed to backfill source: {e}");
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(None))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct SearchQuery {
+    q: String,
+    #[serde(default = "default_search_limit")]
+    limit: u32,
+}
+
+fn default_search_limit() -> u32 {
+    20
+}
+
+/// Search stored posts by text, best matches first.
+pub async fn search_posts(
+    State(server): State<Arc<Server>>,
+    Query(query): Query<SearchQuery>,
+) -> (StatusCode, Json<Vec<Post>>) {
+    match server.search_posts(&query.q, query.limit.min(100)).await {
+        Ok(posts) => (StatusCode::OK, Json(posts)),
+        Err(e) => {
+            tracing::error!("failed to search posts: {e}");
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(Vec::new()))
+        }
+    }
+}
+
+/// Compact the database file, writes pause until it's done.
+pub async fn vacuum(
+    State(server): State<Arc<Server>>,
+) -> Result<Json<VacuumResult>, (StatusCode, String)> {
+    server.vacuum_db().await.map(Json).map_err(|e| {
+        tracing::error!("failed to vacuum database: {e}");
+        (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+    })
+}
+
+/// Send a sample post to the webhooks of a running source, returns the responses.
+pub async fn test_webhook(
+    State(server): State<Arc<Server>>,
+    Path(id): Path<String>,
+) -> Result<Json<Vec<WebhookTest>>, (StatusCode, String)> {
+    match server.test_webhook(&id).await {
+        Ok(Some(results)) => Ok(Json(results)),
+        Ok(None) => Err((StatusCode::NOT_FOUND, format!("source {id} is not running"))),
+        Err(e) => {
+            tracing::error!("failed to test webhook: {e}");
+            Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct DebugQuery {
+    #[serde(default)]
+    html: bool,
+}
+
+/// Last page fetched by a running source with `debug_capture` enabled.
+pub async fn debug_source(
+    State(server): State<Arc<Server>>,
+    Path(id): Path<String>,
+    Query(query): Query<DebugQuery>,
+) -> Result<Json<SourceDebug>, (StatusCode, String)> {
+    match server.debug_source(&id, query.html).await {
+        Ok(Some(debug)) => Ok(Json(debug)),
+        Ok(None) => Err((StatusCode::NOT_FOUND, format!("source {id} is not running"))),
+        Err(e) => Err((StatusCode::BAD_REQUEST, e.to_string())),
+    }
+}
+
 pub async fn get_notifications(
     State(server): State<Arc<Server>>,
 ) -> (StatusCode, Json<Vec<Notification>>) {
@@ -177,6 +458,181 @@ pub async fn reply_notification(
     StatusCode::OK
 }
 
+pub async fn get_config(State(server): State<Arc<Server>>) -> (StatusCode, Json<EnvConfig>) {
+    let env = server.get_config().await;
+    (StatusCode::OK, Json(EnvConfig::clone(&env)))
+}
+
+/// Replace the global config, returns `400` if it fails validation.
+///
+/// Secrets omitted from the body keep their current values.
+pub async fn update_config(
+    State(server): State<Arc<Server>>,
+    Json(mut body): Json<EnvConfig>,
+) -> StatusCode {
+    let current = server.get_config().await;
+    if body.webhook_secret.is_none() {
+        body.webhook_secret = current.webhook_secret.clone();
+    }
+    if body.api_token.is_none() {
+        body.api_token = current.api_token.clone();
+    }
+
+    if let Err(e) = server.update_config(body).await {
+        tracing::error!("failed to update config: {e}");
+        return StatusCode::BAD_REQUEST;
+    }
+    StatusCode::OK
+}
+
+/// Circuit breaker status of failing webhook targets.
+pub async fn get_webhook_status(
+    State(server): State<Arc<Server>>,
+) -> (StatusCode, Json<Vec<TargetStatus>>) {
+    (StatusCode::OK, Json(server.get_webhook_status()))
+}
+
+/// Stream new posts as Server-Sent Events.
+pub async fn events(
+    State(server): State<Arc<Server>>,
+) -> Sse<impl Stream<Item = Result<sse::Event, Infallible>>> {
+    let stream = BroadcastStream::new(server.subscribe_posts()).filter_map(|res| match res {
+        Ok(event) => sse::Event::default()
+            .event("post")
+            .json_data(&event)
+            .ok()
+            .map(Ok),
+        Err(e) => {
+            tracing::warn!("sse subscriber lagged: {e}");
+            None
+        }
+    });
+
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+/// Commands accepted over [ws]
+#[derive(Deserialize)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+pub enum WsCommand {
+    Add { source: SourceConfig },
+    Remove { id: String },
+    PollNow { id: String },
+}
+
+/// Messages sent over [ws]
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum WsMessage<'a> {
+    Post(&'a PostEvent),
+    Ack { ok: bool, error: Option<String> },
+}
+
+/// WebSocket that pushes new posts and accepts [WsCommand]s as JSON text frames.
+///
+/// Every command is answered with an `ack` message.
+pub async fn ws(State(server): State<Arc<Server>>, mut req: Request) -> Response {
+    let Some(accept) = websocket::accept_key(req.headers()) else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+
+    let on_upgrade = hyper::upgrade::on(&mut req);
+    tokio::spawn(async move {
+        match on_upgrade.await {
+            Ok(upgraded) => {
+                if let Err(e) = handle_ws(server, upgraded).await {
+                    tracing::warn!("websocket closed with error: {e}");
+                }
+            }
+            Err(e) => tracing::error!("failed to upgrade websocket: {e}"),
+        }
+    });
+
+    (
+        StatusCode::SWITCHING_PROTOCOLS,
+        [
+            (header::UPGRADE, "websocket".to_string()),
+            (header::CONNECTION, "upgrade".to_string()),
+            (header::SEC_WEBSOCKET_ACCEPT, accept),
+        ],
+    )
+        .into_response()
+}
+
+async fn handle_ws(server: Arc<Server>, upgraded: hyper::upgrade::Upgraded) -> anyhow::Result<()> {
+    let (mut reader, mut writer) = websocket::split(upgraded);
+    let mut posts = server.subscribe_posts();
+
+    // Reading is not cancel safe, so frames are read in a separate task
+    let (msg_tx, mut msg_rx) = mpsc::channel(16);
+    let read_task = tokio::spawn(async move {
+        loop {
+            let msg = reader.recv().await;
+            let done = !matches!(msg, Ok(Message::Text(_) | Message::Ping(_)));
+            if msg_tx.send(msg).await.is_err() || done {
+                break;
+            }
+        }
+    });
+
+    let res = loop {
+        tokio::select! {
+            _ = server.shutdown.cancelled() => break Ok(()),
+            post = posts.recv() => match post {
+                Ok(event) => {
+                    let msg = serde_json::to_string(&WsMessage::Post(&event))?;
+                    if let Err(e) = writer.send_text(&msg).await {
+                        break Err(e);
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    tracing::warn!("websocket subscriber lagged, skipped {n} posts");
+                }
+                Err(broadcast::error::RecvError::Closed) => break Ok(()),
+            },
+            msg = msg_rx.recv() => match msg {
+                Some(Ok(Message::Text(text))) => {
+                    let ack = match ws_command(&server, &text).await {
+                        Ok(_) => WsMessage::Ack { ok: true, error: None },
+                        Err(e) => WsMessage::Ack { ok: false, error: Some(e.to_string()) },
+                    };
+                    if let Err(e) = writer.send_text(&serde_json::to_string(&ack)?).await {
+                        break Err(e);
+                    }
+                }
+                Some(Ok(Message::Ping(payload))) => {
+                    if let Err(e) = writer.pong(&payload).await {
+                        break Err(e);
+                    }
+                }
+                Some(Ok(Message::Close)) | None => break Ok(()),
+                Some(Err(e)) => break Err(e),
+            },
+        }
+    };
+
+    writer.close().await.ok();
+    read_task.abort();
+    res
+}
+
+async fn ws_command(server: &Server, text: &str) -> anyhow::Result<()> {
+    match serde_json::from_str(text)? {
+        WsCommand::Add { source } => server.add_source(&source).await.map(drop),
+        WsCommand::Remove { id } => server.remove_source(&id).await,
+        WsCommand::PollNow { id } => server.poll_source(&id).await,
+    }
+}
+
+pub async fn version(State(server): State<Arc<Server>>) -> Json<Version> {
+    Json(server.version())
+}
+
+/// OpenAPI description of this API.
+pub async fn openapi() -> Json<serde_json::Value> {
+    Json(openapi::spec())
+}
+
 pub async fn health(State(server): State<Arc<Server>>) -> (StatusCode, Json<Health>) {
     match server.health().await {
         Ok(h) => (StatusCode::OK, Json(h)),
@@ -187,8 +643,105 @@ pub async fn health(State(server): State<Arc<Server>>) -> (StatusCode, Json<Heal
                 Json(Health {
                     ok: false,
                     sources: 0,
+                    open_webhooks: 0,
                 }),
             )
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_missing_source() {
+        let env = envy::from_iter([("DB_PATH".to_string(), ":memory:".to_string())]).unwrap();
+        let server = Arc::new(Server::with_config(env).await.unwrap());
+
+        let res = router(server)
+            .oneshot(
+                Request::get("/sources/does-not-exist")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_auth_token() {
+        let env = envy::from_iter([
+            ("DB_PATH".to_string(), ":memory:".to_string()),
+            ("API_TOKEN".to_string(), "secret".to_string()),
+        ])
+        .unwrap();
+        let server = Arc::new(Server::with_config(env).await.unwrap());
+        let status = async |auth: Option<&str>| {
+            let mut req = Request::get("/sources/does-not-exist");
+            if let Some(auth) = auth {
+                req = req.header(header::AUTHORIZATION, auth);
+            }
+            router(Arc::clone(&server))
+                .oneshot(req.body(Body::empty()).unwrap())
+                .await
+                .unwrap()
+                .status()
+        };
+
+        assert_eq!(status(None).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(Some("Bearer wrong")).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(Some("Bearer secret")).await, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_resume_missing_source() {
+        let env = envy::from_iter([("DB_PATH".to_string(), ":memory:".to_string())]).unwrap();
+        let server = Arc::new(Server::with_config(env).await.unwrap());
+
+        let res = router(server)
+            .oneshot(
+                Request::post("/sources/does-not-exist/resume")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_events_stream() {
+        let env = envy::from_iter([("DB_PATH".to_string(), ":memory:".to_string())]).unwrap();
+        let server = Arc::new(Server::with_config(env).await.unwrap());
+
+        let res = router(server.clone())
+            .oneshot(Request::get("/events").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[header::CONTENT_TYPE], "text/event-stream");
+
+        server
+            .post_tx
+            .send(crate::model::PostEvent {
+                channel: "test".to_string(),
+                post: crate::model::Post {
+                    id: "test/1".to_string(),
+                    ..Default::default()
+                },
+            })
+            .unwrap();
+        let mut body = res.into_body().into_data_stream();
+        let frame = body.next().await.unwrap().unwrap();
+        let frame = std::str::from_utf8(&frame).unwrap();
+
+        assert!(frame.starts_with("event: post\ndata: {"));
+        assert!(frame.contains(r#""id":"test/1""#));
+    }
+}
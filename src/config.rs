@@ -1,47 +1,425 @@
-use serde::Deserialize;
-use std::sync::OnceLock;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
 
-pub static ENV: OnceLock<EnvConfig> = OnceLock::new();
+use crate::db::PoolOptions;
+use crate::sources::SourceConfig;
+use crate::sources::proxy::ProxyStrategy;
+use crate::sources::telegram::KIND_SCRAPER;
 
-pub fn init_env(cfg: EnvConfig) {
-    ENV.set(cfg).expect("environment already initialized");
+/// Config of one [crate::Server], replaced when it's reloaded
+#[derive(Debug, Clone)]
+pub struct EnvHandle(Arc<RwLock<Arc<EnvConfig>>>);
+
+impl EnvHandle {
+    pub fn new(cfg: EnvConfig) -> Self {
+        Self(Arc::new(RwLock::new(Arc::new(cfg))))
+    }
+
+    /// Current config, later changes don't affect the returned one.
+    pub fn get(&self) -> Arc<EnvConfig> {
+        self.0.read().expect("environment lock poisoned").clone()
+    }
+
+    /// Replace the config, returns the previous one.
+    pub fn set(&self, cfg: EnvConfig) -> Arc<EnvConfig> {
+        let mut env = self.0.write().expect("environment lock poisoned");
+        std::mem::replace(&mut *env, Arc::new(cfg))
+    }
 }
 
-pub fn get_env() -> &'static EnvConfig {
-    ENV.get().expect("environment not initialized")
+impl Default for EnvHandle {
+    fn default() -> Self {
+        Self::new(EnvConfig::default())
+    }
 }
 
 /// Litehook server configuration
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
 pub struct EnvConfig {
     #[serde(default = "default_port")]
     pub port: u16,
 
-    #[serde(default = "default_db_path")]
-    pub db_path: String,
+    /// IP address of the interface the api listens on, `0.0.0.0` for all
+    #[serde(default = "default_bind_address")]
+    pub bind_address: String,
+
+    /// Directory for the database and other files of litehook
+    #[serde(default = "default_data_dir")]
+    pub data_dir: String,
+    /// Database file name inside `data_dir`
+    #[serde(default = "default_db_filename")]
+    pub db_filename: String,
+    /// Full database path overriding `data_dir` and `db_filename`,
+    /// `:memory:` keeps the database in memory
+    pub db_path: Option<String>,
+    /// Most open database connections
+    #[serde(default = "default_db_max_connections")]
+    pub db_max_connections: u32,
+    /// Database connections kept open when idle
+    #[serde(default)]
+    pub db_min_connections: u32,
+    /// Seconds a query waits for a free database connection
+    #[serde(default = "default_db_acquire_timeout")]
+    pub db_acquire_timeout: u64,
+
+    /// Minimum poll interval in seconds, smaller intervals are clamped
+    #[serde(default = "default_min_poll_interval")]
+    pub min_poll_interval: u64,
+
+    /// Timeout for fetching sources in seconds
+    #[serde(default = "default_request_timeout")]
+    pub request_timeout: u64,
+
+    /// Delay the first poll of the sources started with the server by a
+    /// random part of their interval, at most a minute, so they don't
+    /// poll at the same time
+    #[serde(default = "default_poll_jitter")]
+    pub poll_jitter: bool,
+
+    /// Sources with the same client options share one HTTP client and proxy
+    #[serde(default)]
+    pub share_clients: bool,
 
+    /// Maximum requests per minute through one shared client
+    pub fetch_rate_limit: Option<u32>,
+
+    /// Timeout for a single webhook request in seconds
+    #[serde(default = "default_webhook_timeout")]
+    pub webhook_timeout: u64,
+
+    /// Maximum number of webhook requests in flight at once
+    #[serde(default = "default_max_concurrent_webhooks")]
+    pub max_concurrent_webhooks: usize,
+
+    /// Seconds a batch of webhooks may take before the rest is queued
+    /// for the next poll of the source, `0` disables the budget
+    #[serde(default = "default_webhook_budget_secs")]
+    pub webhook_budget_secs: u64,
+
+    /// Send a `HEAD` request to the webhook urls of every source on
+    /// startup and log the ones that can't be reached
+    #[serde(default)]
+    pub verify_webhooks_on_start: bool,
+
+    /// Seconds to wait for in-flight webhooks on shutdown
+    #[serde(default = "default_drain_timeout")]
+    pub drain_timeout: u64,
+
+    /// Seconds the API keeps serving while stopping on a shutdown signal,
+    /// also bounds the drain. `0` shuts down right away
+    #[serde(default)]
+    pub shutdown_grace_secs: u64,
+
+    /// Secrets are never serialized, so they don't leak through `GET /config`
+    #[serde(skip_serializing)]
     pub webhook_secret: Option<String>,
     pub proxy_list_url: Option<String>,
+    /// How clients pick an entry of the proxy list
+    #[serde(default)]
+    pub proxy_strategy: ProxyStrategy,
+    #[serde(skip_serializing)]
+    pub api_token: Option<String>,
+
+    /// Serve the dashboard for paths not matching an endpoint, 404 otherwise
+    #[serde(default = "default_serve_dashboard")]
+    pub serve_dashboard: bool,
+    /// Directory with the dashboard files
+    #[serde(default = "default_static_dir")]
+    pub static_dir: String,
+
+    /// PEM certificate chain, the api is served over HTTPS when set with `tls_key_path`
+    pub tls_cert_path: Option<String>,
+    /// PEM private key for `tls_cert_path`
+    pub tls_key_path: Option<String>,
+
+    /// JSON array of source configs created on startup, see [EnvConfig::seed_sources].
+    /// Not serialized, as webhook urls may contain tokens
+    #[serde(skip_serializing)]
+    pub channels_json: Option<String>,
+}
+
+impl Default for EnvConfig {
+    fn default() -> Self {
+        Self {
+            port: default_port(),
+            bind_address: default_bind_address(),
+            data_dir: default_data_dir(),
+            db_filename: default_db_filename(),
+            db_path: None,
+            db_max_connections: default_db_max_connections(),
+            db_min_connections: 0,
+            db_acquire_timeout: default_db_acquire_timeout(),
+            min_poll_interval: default_min_poll_interval(),
+            request_timeout: default_request_timeout(),
+            poll_jitter: default_poll_jitter(),
+            share_clients: false,
+            fetch_rate_limit: None,
+            webhook_timeout: default_webhook_timeout(),
+            max_concurrent_webhooks: default_max_concurrent_webhooks(),
+            webhook_budget_secs: default_webhook_budget_secs(),
+            verify_webhooks_on_start: false,
+            drain_timeout: default_drain_timeout(),
+            shutdown_grace_secs: 0,
+            webhook_secret: None,
+            proxy_list_url: None,
+            proxy_strategy: ProxyStrategy::default(),
+            api_token: None,
+            serve_dashboard: default_serve_dashboard(),
+            static_dir: default_static_dir(),
+            tls_cert_path: None,
+            tls_key_path: None,
+            channels_json: None,
+        }
+    }
 }
 
 impl EnvConfig {
     pub fn from_dotenv() -> anyhow::Result<Self> {
         dotenvy::dotenv().ok();
-        Ok(envy::from_env()?)
+        Self::from_env()
+    }
+
+    /// Same as [EnvConfig::from_dotenv], but values from `.env` file
+    /// override the ones already loaded into the process environment.
+    pub fn reload_dotenv() -> anyhow::Result<Self> {
+        dotenvy::dotenv_override().ok();
+        Self::from_env()
+    }
+
+    fn from_env() -> anyhow::Result<Self> {
+        envy::from_env().map_err(|e| anyhow::anyhow!("invalid environment variable: {e}"))
     }
 
+    /// Check the config, the error lists every problem found.
     pub fn validate(&self) -> anyhow::Result<()> {
+        let mut problems = Vec::new();
+        if self.min_poll_interval == 0 {
+            problems.push("min_poll_interval must be greater than 0".to_string());
+        }
+        if self.request_timeout == 0 {
+            problems.push("request_timeout must be greater than 0".to_string());
+        }
+        if self.fetch_rate_limit == Some(0) {
+            problems.push("fetch_rate_limit must be greater than 0".to_string());
+        }
+        if self.webhook_timeout == 0 {
+            problems.push("webhook_timeout must be greater than 0".to_string());
+        }
+        if self.max_concurrent_webhooks == 0 {
+            problems.push("max_concurrent_webhooks must be greater than 0".to_string());
+        }
+        if let Some(url) = &self.proxy_list_url
+            && url::Url::parse(url).is_err()
+        {
+            problems.push(format!("proxy_list_url {url:?} is not a valid url"));
+        }
+        if self.db_path.is_none() && self.db_filename.trim().is_empty() {
+            problems.push("db_filename must not be empty".to_string());
+        }
+        if self.db_max_connections == 0 {
+            problems.push("db_max_connections must be greater than 0".to_string());
+        }
+        if self.db_min_connections > self.db_max_connections {
+            problems.push("db_min_connections must not be above db_max_connections".to_string());
+        }
+        if self.db_acquire_timeout == 0 {
+            problems.push("db_acquire_timeout must be greater than 0".to_string());
+        }
+        if let Err(e) = self.bind_addr() {
+            problems.push(e.to_string());
+        }
+        if self.tls_cert_path.is_some() != self.tls_key_path.is_some() {
+            problems.push("tls_cert_path and tls_key_path must be set together".to_string());
+        }
+        if let Err(e) = self.seed_sources() {
+            problems.push(e.to_string());
+        }
+        if !problems.is_empty() {
+            anyhow::bail!("invalid config:\n  - {}", problems.join("\n  - "));
+        }
+
         if self.webhook_secret.is_none() {
             tracing::warn!("webhook_secret is not set");
         }
+        if self.api_token.is_none() {
+            tracing::warn!("api_token is not set, management api is unauthenticated");
+        }
         Ok(())
     }
+
+    /// Path of a file in `data_dir`, relative to the working directory
+    /// unless `data_dir` is absolute.
+    pub fn data_path(&self, name: &str) -> PathBuf {
+        Path::new(&self.data_dir).join(name)
+    }
+
+    /// Path of the database, `db_path` or `db_filename` in `data_dir`.
+    pub fn database_path(&self) -> String {
+        match &self.db_path {
+            Some(path) => path.clone(),
+            None => self
+                .data_path(&self.db_filename)
+                .to_string_lossy()
+                .into_owned(),
+        }
+    }
+
+    /// Connection pool settings of the database.
+    pub fn pool_options(&self) -> PoolOptions {
+        PoolOptions {
+            max_connections: self.db_max_connections,
+            min_connections: self.db_min_connections,
+            acquire_timeout: Duration::from_secs(self.db_acquire_timeout),
+        }
+    }
+
+    /// Address the api listens on, from `bind_address` and `port`.
+    pub fn bind_addr(&self) -> anyhow::Result<SocketAddr> {
+        let ip: IpAddr = self.bind_address.parse().map_err(|_| {
+            anyhow::anyhow!(
+                "bind_address {:?} is not a valid ip address",
+                self.bind_address
+            )
+        })?;
+        Ok(SocketAddr::new(ip, self.port))
+    }
+
+    /// Parse `channels_json` into [SourceConfig]s.
+    ///
+    /// Entries are flat source configs, `kind` defaults to the Telegram
+    /// scraper and `id` is derived from the channel when missing.
+    pub fn seed_sources(&self) -> anyhow::Result<Vec<SourceConfig>> {
+        let Some(json) = &self.channels_json else {
+            return Ok(Vec::new());
+        };
+        let entries: Vec<serde_json::Map<String, serde_json::Value>> =
+            serde_json::from_str(json)
+                .map_err(|e| anyhow::anyhow!("channels_json must be an array of objects: {e}"))?;
+
+        entries
+            .into_iter()
+            .map(|mut raw| {
+                let kind = match raw.remove("kind") {
+                    Some(serde_json::Value::String(kind)) => kind,
+                    None => KIND_SCRAPER.to_string(),
+                    Some(kind) => anyhow::bail!("invalid kind in channels_json: {kind}"),
+                };
+                let id = raw
+                    .get("id")
+                    .and_then(|id| id.as_str())
+                    .unwrap_or_default()
+                    .to_string();
+                Ok(SourceConfig {
+                    id,
+                    kind,
+                    raw: raw.into(),
+                    created_at: None,
+                    updated_at: None,
+                    active: true,
+                    error: None,
+                })
+            })
+            .collect()
+    }
 }
 
 fn default_port() -> u16 {
     4101
 }
 
-fn default_db_path() -> String {
-    "data/litehook.db".to_string()
+fn default_bind_address() -> String {
+    "0.0.0.0".to_string()
+}
+
+fn default_data_dir() -> String {
+    "data".to_string()
+}
+
+fn default_db_max_connections() -> u32 {
+    32
+}
+
+fn default_db_acquire_timeout() -> u64 {
+    30
+}
+
+fn default_db_filename() -> String {
+    "litehook.db".to_string()
+}
+
+fn default_min_poll_interval() -> u64 {
+    30
+}
+
+fn default_request_timeout() -> u64 {
+    30
+}
+
+fn default_poll_jitter() -> bool {
+    true
+}
+
+fn default_serve_dashboard() -> bool {
+    true
+}
+
+fn default_static_dir() -> String {
+    "static".to_string()
+}
+
+fn default_webhook_timeout() -> u64 {
+    10
+}
+
+fn default_max_concurrent_webhooks() -> usize {
+    10
+}
+
+fn default_webhook_budget_secs() -> u64 {
+    60
+}
+
+fn default_drain_timeout() -> u64 {
+    15
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_lists_problems() {
+        let env: EnvConfig = envy::from_iter([
+            ("MIN_POLL_INTERVAL".to_string(), "0".to_string()),
+            ("PROXY_LIST_URL".to_string(), "not a url".to_string()),
+            ("PROXY_STRATEGY".to_string(), "round_robin".to_string()),
+            ("TLS_CERT_PATH".to_string(), "cert.pem".to_string()),
+            ("BIND_ADDRESS".to_string(), "localhost".to_string()),
+        ])
+        .unwrap();
+
+        assert_eq!(env.proxy_strategy, ProxyStrategy::RoundRobin);
+        let err = env.validate().unwrap_err().to_string();
+        assert!(err.contains("min_poll_interval"));
+        assert!(err.contains("proxy_list_url"));
+        assert!(err.contains("tls_key_path"));
+        assert!(err.contains("bind_address"));
+    }
+
+    #[test]
+    fn test_database_path() {
+        let mut env = EnvConfig::default();
+        assert_eq!(env.database_path(), "data/litehook.db");
+
+        env.data_dir = "/var/lib/litehook".to_string();
+        env.db_filename = "posts.db".to_string();
+        assert_eq!(env.database_path(), "/var/lib/litehook/posts.db");
+
+        env.db_path = Some(":memory:".to_string());
+        assert_eq!(env.database_path(), ":memory:");
+    }
 }
@@ -1,23 +1,72 @@
-use sqlx::sqlite::SqlitePoolOptions;
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions, SqliteSynchronous};
 use sqlx::types::Json;
+use std::str::FromStr;
+use std::time::Duration;
 
-use crate::model::{Post, PostRow};
+use crate::model::{ChannelInfo, Post, PostRow, VacuumResult};
 use crate::sources::SourceConfig;
+use crate::webhook::Payload;
 
 /// SQLite database
 #[derive(Clone)]
 pub struct Db {
     /// SQLite connection pool
     pub pool: sqlx::SqlitePool,
+    /// Whether `posts_fts` is available, searches fall back to `LIKE` otherwise
+    fts: bool,
+}
+
+/// Columns selected to build a [Post] from a [PostRow]
+const POST_COLUMNS: &str = "id, url, author, text, media, media_items, reactions, views, date, service, edited, sponsored, reply_to, album_size";
+
+/// Upsert of a source, `created_at` is kept and the source made active again
+const INSERT_SOURCE: &str = "INSERT INTO sources
+    (id, kind, raw, created_at, updated_at)
+    VALUES (?, ?, ?, strftime('%Y-%m-%dT%H:%M:%SZ', 'now'), strftime('%Y-%m-%dT%H:%M:%SZ', 'now'))
+    ON CONFLICT(id) DO UPDATE SET
+        kind = excluded.kind,
+        raw = excluded.raw,
+        created_at = COALESCE(sources.created_at, excluded.created_at),
+        updated_at = excluded.updated_at,
+        active = 1,
+        error = NULL";
+
+/// Connection pool settings of a file database
+#[derive(Debug, Clone)]
+pub struct PoolOptions {
+    pub max_connections: u32,
+    pub min_connections: u32,
+    /// How long a query waits for a free connection
+    pub acquire_timeout: Duration,
+}
+
+impl Default for PoolOptions {
+    fn default() -> Self {
+        Self {
+            max_connections: 32,
+            min_connections: 0,
+            acquire_timeout: Duration::from_secs(30),
+        }
+    }
 }
 
 impl Db {
-    /// Create a new instance of [Db].
+    /// Create a new instance of [Db] with the default [PoolOptions].
     ///
-    /// Creates tables if they don't exist.
+    /// Creates tables if they don't exist. Use `:memory:` as the path
+    /// for an in-memory database.
     pub async fn new(path: &str) -> anyhow::Result<Self> {
+        Self::with_options(path, &PoolOptions::default()).await
+    }
+
+    /// Same as [Db::new] with custom pool options, an in-memory
+    /// database always uses a single connection.
+    pub async fn with_options(path: &str, opts: &PoolOptions) -> anyhow::Result<Self> {
+        // `:memory:` keeps everything in a single connection, nothing is written to disk
+        let in_memory = matches!(path, ":memory:" | "memory");
+
         // Ensure path exists
-        if path != ":memory:" {
+        if !in_memory {
             let path_ = std::path::Path::new(path);
             if let Some(parent) = path_.parent() {
                 tokio::fs::create_dir_all(parent).await?;
@@ -31,27 +80,49 @@ impl Db {
         }
 
         // Configure connection pool
-        let (url, conns) = if path == "memory" {
-            (":memory:".to_string(), 1)
+        let pool = if in_memory {
+            // Closing the only connection would drop the database
+            SqlitePoolOptions::new()
+                .max_connections(1)
+                .idle_timeout(None)
+                .max_lifetime(None)
+                .connect("sqlite::memory:")
+                .await?
         } else {
-            (format!("sqlite://{}", path), 32)
+            // WAL lets the api read while sources write, writers wait
+            // for the lock instead of failing with "database is locked"
+            let options = SqliteConnectOptions::from_str(&format!("sqlite://{}", path))?
+                .journal_mode(SqliteJournalMode::Wal)
+                .synchronous(SqliteSynchronous::Normal)
+                .busy_timeout(Duration::from_secs(5));
+            SqlitePoolOptions::new()
+                .max_connections(opts.max_connections)
+                .min_connections(opts.min_connections)
+                .acquire_timeout(opts.acquire_timeout)
+                .connect_with(options)
+                .await?
         };
 
-        let pool = SqlitePoolOptions::new()
-            .max_connections(conns)
-            .connect(&url)
-            .await?;
-
         // Create tables
         sqlx::query(
             "CREATE TABLE IF NOT EXISTS posts (
                 id TEXT PRIMARY KEY,
+                url TEXT,
                 author TEXT,
                 text TEXT,
                 media TEXT,
+                media_items TEXT,
                 reactions TEXT,
                 views TEXT,
-                date TEXT
+                date TEXT,
+                service TEXT,
+                edited INTEGER NOT NULL DEFAULT 0,
+                sponsored INTEGER NOT NULL DEFAULT 0,
+                reply_to TEXT,
+                channel TEXT,
+                first_seen_at TEXT,
+                content_hash TEXT,
+                album_size INTEGER
             )",
         )
         .execute(&pool)
@@ -62,30 +133,184 @@ impl Db {
             "CREATE TABLE IF NOT EXISTS sources (
                 id TEXT PRIMARY KEY,
                 kind TEXT,
-                raw TEXT
+                raw TEXT,
+                created_at TEXT,
+                updated_at TEXT,
+                active INTEGER NOT NULL DEFAULT 1,
+                error TEXT
             )",
         )
         .execute(&pool)
         .await
         .unwrap();
 
-        Ok(Self { pool })
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS channels (
+                id TEXT PRIMARY KEY,
+                name TEXT,
+                description TEXT,
+                image TEXT,
+                updated_at TEXT
+            )",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS webhook_queue (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                source TEXT NOT NULL,
+                payload TEXT NOT NULL,
+                created_at TEXT
+            )",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        sqlx::query("CREATE INDEX IF NOT EXISTS webhook_queue_source ON webhook_queue (source)")
+            .execute(&pool)
+            .await?;
+
+        let mut db = Self { pool, fts: false };
+
+        // Migrate tables created by older versions
+        db.add_column("posts", "service", "TEXT").await?;
+        db.add_column("posts", "edited", "INTEGER NOT NULL DEFAULT 0")
+            .await?;
+        db.add_column("posts", "sponsored", "INTEGER NOT NULL DEFAULT 0")
+            .await?;
+        db.add_column("posts", "reply_to", "TEXT").await?;
+        db.add_column("posts", "url", "TEXT").await?;
+        db.add_column("posts", "media_items", "TEXT").await?;
+        db.add_column("posts", "first_seen_at", "TEXT").await?;
+        db.add_column("posts", "content_hash", "TEXT").await?;
+        db.add_column("posts", "album_size", "INTEGER").await?;
+        // Rows of older versions have no url and are sent to every url
+        db.add_column("webhook_queue", "url", "TEXT").await?;
+        if db.add_column("posts", "channel", "TEXT").await? {
+            // Channel is the part of the post id before the message number
+            sqlx::query(
+                "UPDATE posts SET channel = substr(id, 1, instr(id, '/') - 1)
+                WHERE instr(id, '/') > 0",
+            )
+            .execute(&db.pool)
+            .await?;
+        }
+        sqlx::query("CREATE INDEX IF NOT EXISTS posts_channel ON posts (channel COLLATE NOCASE)")
+            .execute(&db.pool)
+            .await?;
+        sqlx::query("CREATE INDEX IF NOT EXISTS posts_content_hash ON posts (content_hash)")
+            .execute(&db.pool)
+            .await?;
+        db.add_column("sources", "created_at", "TEXT").await?;
+        db.add_column("sources", "updated_at", "TEXT").await?;
+        db.add_column("sources", "active", "INTEGER NOT NULL DEFAULT 1")
+            .await?;
+        db.add_column("sources", "error", "TEXT").await?;
+
+        match db.create_fts().await {
+            Ok(()) => db.fts = true,
+            Err(e) => tracing::warn!("full-text search is unavailable, using LIKE: {e}"),
+        }
+
+        Ok(db)
+    }
+
+    /// Create `posts_fts` index of the post text, kept in sync by triggers.
+    ///
+    /// Fails if SQLite is built without FTS5.
+    async fn create_fts(&self) -> anyhow::Result<()> {
+        let exists: Option<(String,)> =
+            sqlx::query_as("SELECT name FROM sqlite_master WHERE name = 'posts_fts'")
+                .fetch_optional(&self.pool)
+                .await?;
+        if exists.is_some() {
+            return Ok(());
+        }
+
+        let mut tx = self.pool.begin().await?;
+        for statement in [
+            "CREATE VIRTUAL TABLE posts_fts USING fts5(text, content = 'posts', content_rowid = 'rowid')",
+            "CREATE TRIGGER posts_fts_insert AFTER INSERT ON posts BEGIN
+                INSERT INTO posts_fts (rowid, text) VALUES (new.rowid, new.text);
+            END",
+            "CREATE TRIGGER posts_fts_delete AFTER DELETE ON posts BEGIN
+                INSERT INTO posts_fts (posts_fts, rowid, text) VALUES ('delete', old.rowid, old.text);
+            END",
+            "CREATE TRIGGER posts_fts_update AFTER UPDATE OF text ON posts BEGIN
+                INSERT INTO posts_fts (posts_fts, rowid, text) VALUES ('delete', old.rowid, old.text);
+                INSERT INTO posts_fts (rowid, text) VALUES (new.rowid, new.text);
+            END",
+            // Index posts stored before the table existed
+            "INSERT INTO posts_fts (posts_fts) VALUES ('rebuild')",
+        ] {
+            sqlx::query(statement).execute(&mut *tx).await?;
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Add a column to the table if it doesn't exist yet, returns true if it was added
+    async fn add_column(&self, table: &str, column: &str, kind: &str) -> anyhow::Result<bool> {
+        let exists: Option<(String,)> = sqlx::query_as(&format!(
+            "SELECT name FROM pragma_table_info('{table}') WHERE name = ?"
+        ))
+        .bind(column)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        if exists.is_none() {
+            sqlx::query(&format!("ALTER TABLE {table} ADD COLUMN {column} {kind}"))
+                .execute(&self.pool)
+                .await?;
+        }
+
+        Ok(exists.is_none())
     }
 
-    /// Insert a post into the database
+    /// Insert a post into the database, or update the content of a stored one
+    ///
+    /// `channel` and `first_seen_at` are only set when the post is inserted.
     pub async fn insert_post(&self, post: &Post) -> anyhow::Result<()> {
         sqlx::query(
-            "INSERT OR REPLACE INTO posts 
-            (id, author, text, media, reactions, views, date)
-            VALUES (?, ?, ?, ?, ?, ?, ?)",
+            "INSERT INTO posts
+            (id, url, author, text, media, media_items, reactions, views, date, service, edited, sponsored, reply_to, album_size, channel, content_hash, first_seen_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, strftime('%Y-%m-%dT%H:%M:%SZ', 'now'))
+            ON CONFLICT(id) DO UPDATE SET
+                url = excluded.url,
+                author = excluded.author,
+                text = excluded.text,
+                media = excluded.media,
+                media_items = excluded.media_items,
+                reactions = excluded.reactions,
+                views = excluded.views,
+                date = excluded.date,
+                service = excluded.service,
+                edited = excluded.edited,
+                sponsored = excluded.sponsored,
+                reply_to = excluded.reply_to,
+                album_size = excluded.album_size,
+                content_hash = excluded.content_hash",
         )
         .bind(&post.id)
+        .bind(&post.url)
         .bind(&post.author)
         .bind(&post.text)
         .bind(Json(&post.media))
+        .bind(post.media_items.as_ref().map(Json))
         .bind(Json(&post.reactions))
         .bind(&post.views)
         .bind(&post.date)
+        .bind(&post.service)
+        .bind(post.edited)
+        .bind(post.sponsored)
+        .bind(post.reply_to.as_ref().map(Json))
+        .bind(post.album_size)
+        .bind(post.id.rsplit_once('/').map(|(channel, _)| channel))
+        .bind(post.content_hash())
         .execute(&self.pool)
         .await?;
 
@@ -94,35 +319,301 @@ impl Db {
 
     /// Select a post from the database
     pub async fn get_posts(&self, id: &str) -> anyhow::Result<Option<Post>> {
-        let row: Option<PostRow> = sqlx::query_as(
-            "SELECT id, author, text, media, reactions, views, date 
-            FROM posts WHERE id = ?",
+        let row: Option<PostRow> =
+            sqlx::query_as(&format!("SELECT {POST_COLUMNS} FROM posts WHERE id = ?"))
+                .bind(id)
+                .fetch_optional(&self.pool)
+                .await?;
+
+        Ok(row.map(Into::into))
+    }
+
+    /// Rebuild the database file to reclaim the space of deleted rows.
+    ///
+    /// `VACUUM` holds the write lock until it's done, writes of sources
+    /// wait for it up to the busy timeout.
+    pub async fn vacuum(&self) -> anyhow::Result<VacuumResult> {
+        let size_before = self.size().await?;
+        sqlx::query("VACUUM").execute(&self.pool).await?;
+        let size_after = self.size().await?;
+
+        Ok(VacuumResult {
+            size_before,
+            size_after,
+            freed: size_before - size_after,
+        })
+    }
+
+    /// Size of the database in bytes.
+    async fn size(&self) -> anyhow::Result<i64> {
+        let (size,): (i64,) = sqlx::query_as(
+            "SELECT page_count * page_size FROM pragma_page_count(), pragma_page_size()",
         )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(size)
+    }
+
+    /// Search posts containing all words of `query`, best matches first.
+    ///
+    /// Without FTS5 the posts containing the whole query are returned,
+    /// newest first.
+    pub async fn search_posts(&self, query: &str, limit: u32) -> anyhow::Result<Vec<Post>> {
+        let rows: Vec<PostRow> = if self.fts {
+            // Quote every word, so the query can't use FTS5 syntax
+            let terms: Vec<String> = query
+                .split_whitespace()
+                .map(|word| format!("\"{}\"", word.replace('"', "\"\"")))
+                .collect();
+            if terms.is_empty() {
+                return Ok(Vec::new());
+            }
+
+            sqlx::query_as(&format!(
+                "SELECT {POST_COLUMNS} FROM posts JOIN (
+                    SELECT rowid AS match_id, rank FROM posts_fts
+                    WHERE posts_fts MATCH ? ORDER BY rank LIMIT ?
+                ) ON posts.rowid = match_id
+                ORDER BY rank"
+            ))
+            .bind(terms.join(" "))
+            .bind(limit)
+            .fetch_all(&self.pool)
+            .await?
+        } else {
+            let pattern = query
+                .trim()
+                .replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_");
+            if pattern.is_empty() {
+                return Ok(Vec::new());
+            }
+
+            sqlx::query_as(&format!(
+                "SELECT {POST_COLUMNS} FROM posts WHERE text LIKE ? ESCAPE '\\'
+                ORDER BY date DESC LIMIT ?"
+            ))
+            .bind(format!("%{pattern}%"))
+            .bind(limit)
+            .fetch_all(&self.pool)
+            .await?
+        };
+
+        Ok(rows.into_iter().map(Into::into).collect())
+    }
+
+    /// Id of another post of the channel with the same [Post::content_hash],
+    /// first seen at most `hours` ago.
+    pub async fn find_duplicate(
+        &self,
+        channel: &str,
+        id: &str,
+        hash: &str,
+        hours: u32,
+    ) -> anyhow::Result<Option<String>> {
+        let row: Option<(String,)> = sqlx::query_as(
+            "SELECT id FROM posts
+            WHERE content_hash = ? AND channel = ? COLLATE NOCASE AND id != ?
+                AND first_seen_at >= strftime('%Y-%m-%dT%H:%M:%SZ', 'now', ?)
+            LIMIT 1",
+        )
+        .bind(hash)
+        .bind(channel)
         .bind(id)
+        .bind(format!("-{hours} hours"))
         .fetch_optional(&self.pool)
         .await?;
 
-        Ok(row.map(Into::into))
+        Ok(row.map(|(id,)| id))
     }
 
-    pub async fn insert_source(&self, cfg: &SourceConfig) -> anyhow::Result<()> {
+    /// Grouped id of a stored album of the channel sharing message
+    /// numbers with `ids`.
+    ///
+    /// Albums cut by a page boundary are parsed with only some of their
+    /// media, under the id of their first visible message.
+    pub async fn find_album(
+        &self,
+        channel: &str,
+        ids: std::ops::Range<u64>,
+    ) -> anyhow::Result<Option<String>> {
+        let row: Option<(String,)> = sqlx::query_as(
+            "SELECT id FROM posts
+            WHERE channel = ? COLLATE NOCASE AND album_size IS NOT NULL
+                AND CAST(substr(id, length(channel) + 2) AS INTEGER) < ?
+                AND CAST(substr(id, length(channel) + 2) AS INTEGER) + album_size > ?
+            LIMIT 1",
+        )
+        .bind(channel)
+        .bind(ids.end as i64)
+        .bind(ids.start as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|(id,)| id))
+    }
+
+    /// Number of stored posts of a channel
+    pub async fn count_posts_by_channel(&self, channel: &str) -> anyhow::Result<i64> {
+        let (count,): (i64,) =
+            sqlx::query_as("SELECT COUNT(*) FROM posts WHERE channel = ? COLLATE NOCASE")
+                .bind(channel)
+                .fetch_one(&self.pool)
+                .await?;
+
+        Ok(count)
+    }
+
+    /// Date of the newest stored post of a channel
+    pub async fn latest_post_date(&self, channel: &str) -> anyhow::Result<Option<String>> {
+        let (date,): (Option<String>,) =
+            sqlx::query_as("SELECT MAX(date) FROM posts WHERE channel = ? COLLATE NOCASE")
+                .bind(channel)
+                .fetch_one(&self.pool)
+                .await?;
+
+        Ok(date)
+    }
+
+    /// Last channel info seen by a source, or of a channel for sources
+    /// without an id
+    pub async fn get_channel_info(&self, id: &str) -> anyhow::Result<Option<ChannelInfo>> {
+        let row: Option<ChannelInfo> =
+            sqlx::query_as("SELECT name, description, image FROM channels WHERE id = ?")
+                .bind(id)
+                .fetch_optional(&self.pool)
+                .await?;
+
+        Ok(row)
+    }
+
+    pub async fn set_channel_info(&self, id: &str, info: &ChannelInfo) -> anyhow::Result<()> {
         sqlx::query(
-            "INSERT OR REPLACE INTO sources
-            (id, kind, raw)
-            VALUES (?, ?, ?)",
+            "INSERT OR REPLACE INTO channels (id, name, description, image, updated_at)
+            VALUES (?, ?, ?, ?, strftime('%Y-%m-%dT%H:%M:%SZ', 'now'))",
         )
-        .bind(&cfg.id)
-        .bind(&cfg.kind)
-        .bind(&cfg.raw)
+        .bind(id)
+        .bind(&info.name)
+        .bind(&info.description)
+        .bind(&info.image)
         .execute(&self.pool)
         .await?;
 
         Ok(())
     }
 
+    /// Queue payloads of a source to be sent to each of the urls later,
+    /// like after quiet hours.
+    pub async fn queue_payloads(
+        &self,
+        source: &str,
+        urls: &[String],
+        payloads: &[Payload],
+    ) -> anyhow::Result<()> {
+        let mut tx = self.pool.begin().await?;
+        for payload in payloads {
+            for url in urls {
+                sqlx::query(
+                    "INSERT INTO webhook_queue (source, url, payload, created_at)
+                    VALUES (?, ?, ?, strftime('%Y-%m-%dT%H:%M:%SZ', 'now'))",
+                )
+                .bind(source)
+                .bind(url)
+                .bind(Json(payload))
+                .execute(&mut *tx)
+                .await?;
+            }
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Check if a url of a source has payloads waiting in the queue.
+    pub async fn has_queued_payloads(&self, source: &str, url: &str) -> anyhow::Result<bool> {
+        let queued: bool = sqlx::query_scalar(
+            "SELECT EXISTS(SELECT 1 FROM webhook_queue WHERE source = ? AND url = ?)",
+        )
+        .bind(source)
+        .bind(url)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(queued)
+    }
+
+    /// Urls of a source with queued payloads, `None` for the rows of
+    /// older versions meant for every url.
+    pub async fn queued_urls(&self, source: &str) -> anyhow::Result<Vec<Option<String>>> {
+        let urls = sqlx::query_scalar(
+            "SELECT url FROM webhook_queue WHERE source = ? GROUP BY url ORDER BY MIN(id)",
+        )
+        .bind(source)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(urls)
+    }
+
+    /// Remove and return the queued payloads of a url, oldest first.
+    pub async fn take_queued_payloads(
+        &self,
+        source: &str,
+        url: Option<&str>,
+    ) -> anyhow::Result<Vec<Payload>> {
+        let mut tx = self.pool.begin().await?;
+        let rows: Vec<(Json<Payload>,)> = sqlx::query_as(
+            "SELECT payload FROM webhook_queue WHERE source = ? AND url IS ? ORDER BY id",
+        )
+        .bind(source)
+        .bind(url)
+        .fetch_all(&mut *tx)
+        .await?;
+        sqlx::query("DELETE FROM webhook_queue WHERE source = ? AND url IS ?")
+            .bind(source)
+            .bind(url)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(rows.into_iter().map(|(payload,)| payload.0).collect())
+    }
+
+    /// Insert or update a source.
+    ///
+    /// `created_at` is set on first insert, `updated_at` on every call.
+    /// Updating a source makes it active again.
+    pub async fn insert_source(&self, cfg: &SourceConfig) -> anyhow::Result<()> {
+        sqlx::query(INSERT_SOURCE)
+            .bind(&cfg.id)
+            .bind(&cfg.kind)
+            .bind(&cfg.raw)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Insert or update several sources in one transaction, either all
+    /// of them are stored or none.
+    pub async fn insert_sources(&self, cfgs: &[SourceConfig]) -> anyhow::Result<()> {
+        let mut tx = self.pool.begin().await?;
+        for cfg in cfgs {
+            sqlx::query(INSERT_SOURCE)
+                .bind(&cfg.id)
+                .bind(&cfg.kind)
+                .bind(&cfg.raw)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+
     pub async fn get_source(&self, id: &str) -> anyhow::Result<Option<SourceConfig>> {
         let row: Option<SourceConfig> = sqlx::query_as(
-            "SELECT id, kind, raw
+            "SELECT id, kind, raw, created_at, updated_at, active, error
             FROM sources WHERE id = ?",
         )
         .bind(id)
@@ -134,7 +625,7 @@ impl Db {
 
     pub async fn get_all_sources(&self) -> anyhow::Result<Vec<SourceConfig>> {
         let rows: Vec<SourceConfig> = sqlx::query_as(
-            "SELECT id, kind, raw
+            "SELECT id, kind, raw, created_at, updated_at, active, error
             FROM sources",
         )
         .fetch_all(&self.pool)
@@ -143,11 +634,30 @@ impl Db {
         Ok(rows)
     }
 
+    /// Mark source as inactive with the reason it stopped
+    pub async fn set_source_inactive(&self, id: &str, error: &str) -> anyhow::Result<()> {
+        sqlx::query("UPDATE sources SET active = 0, error = ? WHERE id = ?")
+            .bind(error)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
     pub async fn delete_source(&self, id: &str) -> anyhow::Result<()> {
         sqlx::query("DELETE FROM sources WHERE id = ?")
             .bind(id)
             .execute(&self.pool)
             .await?;
+        sqlx::query("DELETE FROM webhook_queue WHERE source = ?")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        sqlx::query("DELETE FROM channels WHERE id = ?")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
 
         Ok(())
     }
@@ -155,28 +665,46 @@ impl Db {
 
 #[cfg(test)]
 mod tests {
-    use crate::model::PostReaction;
+    use crate::model::{Media, MediaKind, PostReaction, Reply};
 
     use super::*;
 
     fn sample_post(id: &str) -> Post {
         Post {
             id: id.to_string(),
+            url: crate::model::post_url(id),
             author: Some("Author".to_string()),
             text: Some("This is a test!".to_string()),
             media: Some(vec!["https://example.com/image.png".to_string()]),
+            media_items: Some(vec![Media {
+                url: "https://example.com/image.png".to_string(),
+                kind: MediaKind::Photo,
+                duration: None,
+                filename: None,
+            }]),
             reactions: Some(vec![
                 PostReaction {
                     emoji: Some("👍".to_string()),
                     count: Some("5.7K".to_string()),
+                    image: None,
                 },
                 PostReaction {
                     emoji: Some("🩷".to_string()),
                     count: Some("39".to_string()),
+                    image: None,
                 },
             ]),
             views: Some("1.5K".to_string()),
+            views_count: Some(1500),
             date: Some("2026-02-14T15:45:21+00:00".to_string()),
+            service: None,
+            edited: true,
+            sponsored: false,
+            reply_to: Some(Reply {
+                id: "test/0".to_string(),
+                text: Some("Original post".to_string()),
+            }),
+            album_size: Some(1),
         }
     }
 
@@ -191,6 +719,228 @@ mod tests {
         assert_eq!(fetched, Some(post));
     }
 
+    #[tokio::test]
+    async fn test_source_timestamps() {
+        let db = Db::new(":memory:").await.unwrap();
+        let cfg = SourceConfig {
+            id: "test".to_string(),
+            kind: "telegram_scraper".to_string(),
+            raw: serde_json::json!({}),
+            created_at: None,
+            updated_at: None,
+            active: true,
+            error: None,
+        };
+
+        db.insert_source(&cfg).await.unwrap();
+        sqlx::query("UPDATE sources SET created_at = '2000-01-01T00:00:00Z'")
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        db.insert_source(&cfg).await.unwrap();
+
+        let fetched = db.get_source("test").await.unwrap().unwrap();
+        assert_eq!(fetched.created_at.as_deref(), Some("2000-01-01T00:00:00Z"));
+        assert_ne!(fetched.updated_at, fetched.created_at);
+    }
+
+    #[tokio::test]
+    async fn test_webhook_queue() {
+        let db = Db::new(":memory:").await.unwrap();
+        let payloads = vec![
+            Payload::Json(serde_json::json!({ "id": "test/1" })),
+            Payload::Bot {
+                method: "sendMessage".into(),
+                params: serde_json::json!({ "text": "Hello" }),
+            },
+        ];
+
+        let urls = ["https://a.test".to_string(), "https://b.test".to_string()];
+
+        db.queue_payloads("test", &urls, &payloads).await.unwrap();
+        assert!(db.has_queued_payloads("test", &urls[0]).await.unwrap());
+        assert!(!db.has_queued_payloads("other", &urls[0]).await.unwrap());
+        assert_eq!(
+            db.queued_urls("test").await.unwrap(),
+            urls.clone().map(Some)
+        );
+        assert_eq!(
+            db.take_queued_payloads("test", Some(&urls[0]))
+                .await
+                .unwrap(),
+            payloads
+        );
+        assert!(!db.has_queued_payloads("test", &urls[0]).await.unwrap());
+        // Taking one url keeps the payloads of the other
+        assert!(db.has_queued_payloads("test", &urls[1]).await.unwrap());
+        assert!(
+            db.take_queued_payloads("test", None)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_vacuum() {
+        let db = Db::new(":memory:").await.unwrap();
+        db.insert_post(&sample_post("test/1")).await.unwrap();
+
+        let res = db.vacuum().await.unwrap();
+        assert!(res.size_before > 0);
+        assert_eq!(res.freed, res.size_before - res.size_after);
+    }
+
+    #[tokio::test]
+    async fn test_insert_sources() {
+        let db = Db::new(":memory:").await.unwrap();
+        let cfgs: Vec<_> = ["a", "b"]
+            .into_iter()
+            .map(|id| SourceConfig {
+                id: id.to_string(),
+                kind: "telegram_scraper".to_string(),
+                raw: serde_json::json!({}),
+                created_at: None,
+                updated_at: None,
+                active: true,
+                error: None,
+            })
+            .collect();
+
+        db.insert_sources(&cfgs).await.unwrap();
+        assert_eq!(db.get_all_sources().await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_post_upsert() {
+        let db = Db::new(":memory:").await.unwrap();
+        let mut post = sample_post("test/1");
+
+        db.insert_post(&post).await.unwrap();
+        sqlx::query("UPDATE posts SET first_seen_at = '2000-01-01T00:00:00Z'")
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        post.text = Some("Edited".to_string());
+        post.edited = true;
+        db.insert_post(&post).await.unwrap();
+
+        let (first_seen_at,): (String,) = sqlx::query_as("SELECT first_seen_at FROM posts")
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(first_seen_at, "2000-01-01T00:00:00Z");
+        assert_eq!(db.get_posts(&post.id).await.unwrap(), Some(post));
+    }
+
+    #[tokio::test]
+    async fn test_search_posts() {
+        let mut db = Db::new(":memory:").await.unwrap();
+        let mut post = sample_post("test/1");
+        db.insert_post(&post).await.unwrap();
+        db.insert_post(&Post {
+            text: Some("Another test, with a longer text about something".to_string()),
+            ..sample_post("test/2")
+        })
+        .await
+        .unwrap();
+
+        let ids = |posts: Vec<Post>| posts.into_iter().map(|p| p.id).collect::<Vec<_>>();
+        assert!(db.fts);
+        assert_eq!(
+            ids(db.search_posts("test", 10).await.unwrap()),
+            ["test/1", "test/2"]
+        );
+        assert_eq!(
+            ids(db.search_posts("\"about", 10).await.unwrap()),
+            ["test/2"]
+        );
+
+        // Edited text replaces the indexed one
+        post.text = Some("Edited".to_string());
+        db.insert_post(&post).await.unwrap();
+        assert_eq!(ids(db.search_posts("test", 10).await.unwrap()), ["test/2"]);
+        assert_eq!(
+            ids(db.search_posts("edited", 10).await.unwrap()),
+            ["test/1"]
+        );
+
+        db.fts = false;
+        assert_eq!(
+            ids(db.search_posts("a longer", 10).await.unwrap()),
+            ["test/2"]
+        );
+        assert!(db.search_posts("100%", 10).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_find_duplicate() {
+        let db = Db::new(":memory:").await.unwrap();
+        let post = sample_post("test/1");
+        let hash = post.content_hash().unwrap();
+        db.insert_post(&post).await.unwrap();
+        db.insert_post(&sample_post("other/1")).await.unwrap();
+
+        let duplicate = db
+            .find_duplicate("test", "test/2", &hash, 24)
+            .await
+            .unwrap();
+        assert_eq!(duplicate.as_deref(), Some("test/1"));
+        assert!(
+            db.find_duplicate("test", "test/1", &hash, 24)
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        // Older posts are not duplicates
+        sqlx::query("UPDATE posts SET first_seen_at = '2000-01-01T00:00:00Z'")
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        assert!(
+            db.find_duplicate("test", "test/2", &hash, 24)
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_posts_by_channel() {
+        let db = Db::new(":memory:").await.unwrap();
+        db.insert_post(&sample_post("test/1")).await.unwrap();
+        db.insert_post(&Post {
+            date: Some("2026-03-01T00:00:00+00:00".to_string()),
+            ..sample_post("Test/2")
+        })
+        .await
+        .unwrap();
+        db.insert_post(&sample_post("other/1")).await.unwrap();
+
+        assert_eq!(db.count_posts_by_channel("test").await.unwrap(), 2);
+        assert_eq!(
+            db.latest_post_date("test").await.unwrap().as_deref(),
+            Some("2026-03-01T00:00:00+00:00")
+        );
+        assert_eq!(db.count_posts_by_channel("none").await.unwrap(), 0);
+        assert!(db.latest_post_date("none").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_channel_info() {
+        let db = Db::new(":memory:").await.unwrap();
+        let info = ChannelInfo {
+            name: Some("Test".to_string()),
+            description: None,
+            image: Some("https://example.com/avatar.jpg".to_string()),
+        };
+
+        assert!(db.get_channel_info("test").await.unwrap().is_none());
+        db.set_channel_info("test", &info).await.unwrap();
+        assert_eq!(db.get_channel_info("test").await.unwrap(), Some(info));
+    }
+
     #[tokio::test]
     async fn test_nonexistent_post() {
         let db = Db::new(":memory:").await.unwrap();
@@ -198,4 +948,38 @@ mod tests {
 
         assert!(post.is_none());
     }
+
+    #[tokio::test]
+    async fn test_concurrent_access() {
+        let path = std::env::temp_dir().join(format!("litehook-{}.db", uuid::Uuid::new_v4()));
+        let db = Db::new(path.to_str().unwrap()).await.unwrap();
+
+        let tasks: Vec<_> = (0..32)
+            .map(|i| {
+                let db = db.clone();
+                tokio::spawn(async move {
+                    let id = format!("test/{i}");
+                    db.insert_post(&sample_post(&id)).await?;
+                    db.get_posts(&id).await
+                })
+            })
+            .collect();
+        for task in tasks {
+            assert!(task.await.unwrap().unwrap().is_some());
+        }
+
+        db.pool.close().await;
+        for ext in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{ext}", path.display()));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_memory_alias() {
+        let db = Db::new("memory").await.unwrap();
+        db.insert_post(&sample_post("test/2")).await.unwrap();
+
+        assert!(db.get_posts("test/2").await.unwrap().is_some());
+        assert!(!std::path::Path::new("memory").exists());
+    }
 }
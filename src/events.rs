@@ -1,45 +1,115 @@
+use futures_util::future::join_all;
 use reqwest::Client;
-use tokio::sync::{mpsc, oneshot};
-use tokio::time::{Duration, sleep};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{Semaphore, broadcast, mpsc, oneshot};
+use tokio::time::{Duration, Instant, sleep};
 use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
 
-use super::config;
+use crate::config::EnvHandle;
 use crate::db::Db;
-use crate::model::{Channel, Notification, NtfMap, Page, Post, WebhookPayload};
+use crate::model::{
+    Channel, ChannelInfo, Notification, NtfMap, Page, Post, PostEvent, WebhookTest,
+};
+use crate::webhook::breaker::CircuitBreakers;
+use crate::webhook::{self, Payload, WebhookConfig, telegram_bot};
 
 /// Event type
 #[derive(Debug)]
 pub enum Event {
-    NewPosts(Box<Page>, String),
-    NewMessage(String, Post),
+    NewPosts(Box<Page>, WebhookConfig),
+    NewMessage(WebhookConfig, Box<Post>),
     Notification(String),
     InputRequest(String, oneshot::Sender<String>),
+    /// Store posts without sending webhooks, replies with the number of new posts
+    Backfill(Box<Page>, oneshot::Sender<usize>),
+    /// Send payload once to every url, replies with the responses
+    TestWebhook(WebhookConfig, Payload, oneshot::Sender<Vec<WebhookTest>>),
+    /// Send the payloads queued for the webhook source
+    FlushQueued(WebhookConfig),
+    /// Store the new `channel_url` of a source whose channel was renamed
+    ChannelRenamed {
+        source: String,
+        channel_url: String,
+    },
 }
 
+/// Characters of the response body kept in [WebhookTest]
+const TEST_BODY_LEN: usize = 500;
+/// How long a post counts as the original of posts with the same content
+const DEDUP_WINDOW_HOURS: u32 = 24;
+
 pub struct EventHandler {
     rx: mpsc::Receiver<Event>,
     db: Db,
     ntf: NtfMap,
-    client: Client,
+    post_tx: broadcast::Sender<PostEvent>,
+    sender: Arc<WebhookSender>,
+    /// Ordered background delivery of each rate limited source
+    workers: Workers,
+    /// Deliveries waiting for the rate limit of their source
+    limited: TaskTracker,
     shutdown: CancellationToken,
 }
 
+/// Payloads handed to the delivery worker of a source
+type Delivery = (WebhookConfig, Vec<Payload>);
+/// Delivery workers by source, a worker removes itself once it's idle
+type Workers = Arc<Mutex<HashMap<String, mpsc::UnboundedSender<Delivery>>>>;
+
+/// Sends webhook requests, shared with rate limited deliveries
+struct WebhookSender {
+    client: Client,
+    /// Payloads for an open circuit are queued here
+    db: Db,
+    /// Caps concurrent webhook requests across all sources
+    permits: Semaphore,
+    breakers: Arc<CircuitBreakers>,
+    delivered: AtomicUsize,
+    env: EnvHandle,
+}
+
 impl EventHandler {
-    pub fn new(rx: mpsc::Receiver<Event>, db: Db, ntf: NtfMap) -> Self {
+    pub fn new(
+        rx: mpsc::Receiver<Event>,
+        db: Db,
+        ntf: NtfMap,
+        post_tx: broadcast::Sender<PostEvent>,
+        breakers: Arc<CircuitBreakers>,
+        env: EnvHandle,
+    ) -> Self {
         Self {
             rx,
-            db,
+            db: db.clone(),
             ntf,
-            client: Client::new(),
+            post_tx,
+            sender: Arc::new(WebhookSender {
+                client: Client::new(),
+                db,
+                permits: Semaphore::new(env.get().max_concurrent_webhooks),
+                breakers,
+                delivered: AtomicUsize::new(0),
+                env,
+            }),
+            workers: Arc::default(),
+            limited: TaskTracker::new(),
             shutdown: CancellationToken::new(),
         }
     }
 
+    /// Token that stops the handler after draining the queued events.
+    pub fn shutdown_token(&self) -> CancellationToken {
+        self.shutdown.clone()
+    }
+
     pub async fn run(mut self) {
         loop {
             tokio::select! {
                 _ = self.shutdown.cancelled() => {
-                    self.rx.close();
+                    self.drain().await;
                     return;
                 }
                 Some(event) = self.rx.recv() => {
@@ -51,19 +121,61 @@ impl EventHandler {
         }
     }
 
+    /// Stop receiving new events and handle the already queued ones,
+    /// including deliveries waiting for a rate limit.
+    async fn drain(&mut self) {
+        self.rx.close();
+        let before = self.sender.delivered.load(Ordering::Relaxed);
+
+        while let Some(event) = self.rx.recv().await {
+            if let Err(e) = self.handle_event(event).await {
+                tracing::error!("error while handling event: {e}");
+            }
+        }
+        // Workers stop once their pending deliveries are sent
+        self.workers.lock().expect("workers lock poisoned").clear();
+        self.limited.close();
+        self.limited.wait().await;
+
+        let flushed = self.sender.delivered.load(Ordering::Relaxed) - before;
+        tracing::info!("event queue drained, flushed {flushed} webhooks");
+    }
+
     pub async fn handle_event(&mut self, event: Event) -> anyhow::Result<()> {
         match event {
-            Event::NewPosts(page, cfg) => self.handle_new_posts(&page, &cfg).await?,
-            Event::NewMessage(url, post) => self.handle_new_post(&url, &post).await?,
+            Event::NewPosts(page, webhook) => self.handle_new_posts(&page, &webhook).await?,
+            Event::NewMessage(webhook, post) => self.handle_new_post(&webhook, &post).await?,
             Event::Notification(id) => self.handle_notification(&id, None).await?,
             Event::InputRequest(msg, tx) => self.handle_notification(&msg, Some(tx)).await?,
+            Event::Backfill(page, tx) => {
+                let count = self.handle_backfill(&page).await?;
+                tx.send(count).ok();
+            }
+            Event::FlushQueued(webhook) => self.handle_flush_queued(&webhook).await?,
+            Event::ChannelRenamed {
+                source,
+                channel_url,
+            } => self.handle_channel_renamed(&source, channel_url).await?,
+            Event::TestWebhook(webhook, payload, tx) => {
+                // Don't hold up other events while waiting for the target
+                let sender = self.sender.clone();
+                tokio::spawn(async move {
+                    tx.send(sender.test(&webhook, &payload).await).ok();
+                });
+            }
         }
 
         Ok(())
     }
 
-    pub async fn handle_new_post(&self, url: &str, post: &Post) -> anyhow::Result<()> {
-        self.send_webhook_raw_retry(url, &post, 5).await?;
+    pub async fn handle_new_post(
+        &self,
+        webhook: &WebhookConfig,
+        post: &Post,
+    ) -> anyhow::Result<()> {
+        self.publish(post.channel(), post);
+        self.deliver(webhook, webhook.post_payloads(post)?).await;
+
         Ok(())
     }
 
@@ -86,94 +198,485 @@ impl EventHandler {
         Ok(())
     }
 
-    pub async fn handle_new_posts(&self, page: &Page, webhook_url: &str) -> anyhow::Result<()> {
+    pub async fn handle_new_posts(
+        &self,
+        page: &Page,
+        webhook: &WebhookConfig,
+    ) -> anyhow::Result<()> {
+        // Posts of a channel seen for the first time are a baseline, not new
+        if webhook.seed_silently && self.is_new_channel(&page.channel.id, webhook).await? {
+            let count = self.handle_backfill(page).await?;
+            tracing::info!(
+                "stored {count} existing posts of {} without sending webhooks",
+                page.channel.id
+            );
+            return self.handle_channel_info(&page.channel, webhook).await;
+        }
+
         let mut new_posts = Vec::new();
+        let mut updated_posts = Vec::new();
 
         // Filter for new posts
         for post in &page.posts {
-            if self.db.get_posts(&post.id).await?.is_none() {
+            let stored = self.db.get_posts(&post.id).await?;
+            if let Some(old) = stored {
+                // Track engagement of known posts
+                if webhook.track_stats && post.service.is_none() && post.stats_changed(&old) {
+                    self.db.insert_post(post).await?;
+                    updated_posts.push(post.clone());
+                }
+            } else if let Some(album) = self.stored_album(post).await? {
+                tracing::info!("skipping post {}, part of the album {album}", post.id);
+            } else {
                 tracing::info!("new post: {}", post.id);
                 self.db.insert_post(post).await?;
+
+                // Service messages are stored, but not sent
+                if post.service.is_some() {
+                    tracing::info!("skipping service message: {}", post.id);
+                    continue;
+                }
+                if webhook.skip_sponsored && post.sponsored {
+                    tracing::info!("skipping sponsored post: {}", post.id);
+                    continue;
+                }
+                // Reposts and reassigned ids get a new id for the same content
+                if webhook.dedup_by_content
+                    && let Some(hash) = post.content_hash()
+                    && let Some(original) = self
+                        .db
+                        .find_duplicate(&page.channel.id, &post.id, &hash, DEDUP_WINDOW_HOURS)
+                        .await?
+                {
+                    tracing::info!("skipping post {} with the content of {original}", post.id);
+                    continue;
+                }
+
+                self.publish(&page.channel.id, post);
                 new_posts.push(post.clone());
             }
         }
 
-        // Send webhook
+        // Send webhook, oldest post first
         if !new_posts.is_empty() {
-            self.send_webhook_retry(webhook_url, &page.channel, &new_posts, 5)
+            sort_by_date(&mut new_posts);
+            // After downtime only the newest posts are sent
+            if let Some(limit) = webhook.catchup_limit
+                && new_posts.len() > limit
+            {
+                let skipped = new_posts.len() - limit;
+                tracing::info!(
+                    "{} new posts in {}, storing the {skipped} oldest without sending webhooks",
+                    new_posts.len(),
+                    page.channel.id
+                );
+                new_posts.drain(..skipped);
+            }
+            let payloads = webhook.payloads(&page.channel, &new_posts)?;
+            self.deliver(webhook, payloads).await;
+        }
+
+        // Send stats updates
+        if !updated_posts.is_empty() {
+            tracing::info!("stats changed for {} posts", updated_posts.len());
+            let payload = webhook.stats_payload(&page.channel.id, &updated_posts)?;
+            self.deliver(webhook, vec![payload]).await;
+        }
+
+        self.handle_channel_info(&page.channel, webhook).await
+    }
+
+    /// Send the payloads queued during quiet hours, after the webhook
+    /// budget was spent or while a circuit was open, as one batch per url.
+    ///
+    /// Each url only gets its own queued payloads, urls whose circuit is
+    /// still open keep them.
+    async fn handle_flush_queued(&self, webhook: &WebhookConfig) -> anyhow::Result<()> {
+        for url in self.db.queued_urls(&webhook.source).await? {
+            let target = match &url {
+                Some(url) if !webhook.urls.contains(url) => {
+                    let dropped = self
+                        .db
+                        .take_queued_payloads(&webhook.source, Some(url))
+                        .await?;
+                    tracing::warn!(
+                        "{url} is no longer a webhook of {}, dropped {} queued webhooks",
+                        webhook.source,
+                        dropped.len()
+                    );
+                    continue;
+                }
+                Some(url) if self.sender.breakers.is_open(url) => {
+                    tracing::debug!("circuit open for webhook {url}, keeping queued webhooks");
+                    continue;
+                }
+                Some(url) => WebhookConfig {
+                    urls: vec![url.clone()],
+                    ..webhook.clone()
+                },
+                None => webhook.clone(),
+            };
+            let payloads = self
+                .db
+                .take_queued_payloads(&webhook.source, url.as_deref())
                 .await?;
+            tracing::info!(
+                "sending {} queued webhooks of {}",
+                payloads.len(),
+                webhook.source
+            );
+            self.deliver(&target, payloads).await;
         }
 
         Ok(())
     }
 
-    async fn send_webhook_raw<T>(&self, url: &str, data: T) -> anyhow::Result<reqwest::Response>
-    where
-        T: serde::Serialize,
-    {
-        let res = self
-            .client
-            .post(url)
-            .header(
-                "x-secret",
-                &config::get_env().webhook_secret.clone().unwrap_or_default(),
-            )
-            .json(&data)
-            .send()
-            .await?;
+    /// Update `channel_url` in the stored config of a source.
+    async fn handle_channel_renamed(
+        &self,
+        source: &str,
+        channel_url: String,
+    ) -> anyhow::Result<()> {
+        let Some(mut cfg) = self.db.get_source(source).await? else {
+            return Ok(());
+        };
+        cfg.raw["channel_url"] = channel_url.into();
+        self.db.insert_source(&cfg).await
+    }
 
-        if !res.status().is_success() {
-            return Err(anyhow::anyhow!(res.status().to_string()));
+    /// Check if a channel has neither stored info for the source nor posts.
+    async fn is_new_channel(&self, channel: &str, webhook: &WebhookConfig) -> anyhow::Result<bool> {
+        Ok(self
+            .db
+            .get_channel_info(info_key(channel, webhook))
+            .await?
+            .is_none()
+            && self.db.count_posts_by_channel(channel).await? == 0)
+    }
+
+    /// Send a webhook when name, description or photo of the channel changed.
+    ///
+    /// Info is stored per source, so every source of a channel sends
+    /// the change. Info seen for the first time is only stored.
+    async fn handle_channel_info(
+        &self,
+        channel: &Channel,
+        webhook: &WebhookConfig,
+    ) -> anyhow::Result<()> {
+        let key = info_key(&channel.id, webhook);
+        let new = ChannelInfo::from(channel);
+        let old = self.db.get_channel_info(key).await?;
+        if old.as_ref() == Some(&new) {
+            return Ok(());
         }
+        self.db.set_channel_info(key, &new).await?;
 
-        Ok(res)
+        if let Some(old) = old {
+            tracing::info!("channel info of {} changed", channel.id);
+            let payload = webhook.channel_payload(&channel.id, &old, &new)?;
+            self.deliver(webhook, vec![payload]).await;
+        }
+
+        Ok(())
     }
 
-    async fn send_webhook_raw_retry<T>(
-        &self,
-        url: &str,
-        data: T,
-        max_retries: u64,
-    ) -> anyhow::Result<reqwest::Response>
-    where
-        T: serde::Serialize + Clone,
-    {
-        for att in 1..=max_retries {
-            match self.send_webhook_raw(url, data.clone()).await {
-                Ok(res) => return Ok(res),
-                Err(e) if att < max_retries => {
-                    tracing::error!("failed to send webhook: {e}, retrying in 5s");
-                    sleep(Duration::from_secs(5)).await;
+    /// Store posts that are not in the database yet.
+    pub async fn handle_backfill(&self, page: &Page) -> anyhow::Result<usize> {
+        let mut count = 0;
+        for post in &page.posts {
+            if self.db.get_posts(&post.id).await?.is_none()
+                && self.stored_album(post).await?.is_none()
+            {
+                self.db.insert_post(post).await?;
+                count += 1;
+            }
+        }
+
+        Ok(count)
+    }
+
+    /// Grouped id of the stored album an album post is a part of.
+    ///
+    /// An album on the edge of a page is parsed under another id than on
+    /// the page holding all of it, this keeps it from being sent again.
+    async fn stored_album(&self, post: &Post) -> anyhow::Result<Option<String>> {
+        match post.album_ids() {
+            Some(ids) => self.db.find_album(post.channel(), ids).await,
+            None => Ok(None),
+        }
+    }
+
+    /// Publish post to live subscribers, does nothing if there are none.
+    fn publish(&self, channel: &str, post: &Post) {
+        self.post_tx
+            .send(PostEvent {
+                channel: channel.to_string(),
+                post: post.clone(),
+            })
+            .ok();
+    }
+
+    /// Send payloads to every webhook target independently.
+    ///
+    /// Failed targets are logged and don't affect the others,
+    /// in dry run mode the payloads are only logged. Payloads of rate
+    /// limited sources go to a background worker of the source, so
+    /// waiting for the limit doesn't hold up other sources. During quiet
+    /// hours the payloads are stored until [Event::FlushQueued].
+    ///
+    /// Other payloads are sent right away, once they took longer than
+    /// `webhook_budget_secs` the rest is stored for the next poll too,
+    /// so a failing target doesn't hold up the events of all sources.
+    ///
+    /// Payloads of a source are delivered in the order they were
+    /// detected, each one after the retries of the previous one. While
+    /// a url has stored payloads, new ones for it are stored behind them.
+    async fn deliver(&self, webhook: &WebhookConfig, payloads: Vec<Payload>) {
+        if webhook.dry_run {
+            for payload in &payloads {
+                tracing::info!("dry run, not sending webhook: {payload}");
+            }
+            return;
+        }
+
+        if let Some(end) = webhook
+            .quiet_hours
+            .as_ref()
+            .and_then(|q| q.window_end(chrono::Utc::now()))
+        {
+            match self
+                .db
+                .queue_payloads(&webhook.source, &webhook.urls, &payloads)
+                .await
+            {
+                Ok(()) => {
+                    tracing::info!(
+                        "quiet hours until {end}, queued {} webhooks",
+                        payloads.len()
+                    );
+                    return;
                 }
-                Err(e) => return Err(e),
+                Err(e) => tracing::error!("failed to queue webhooks, sending them now: {e}"),
             }
         }
 
-        Err(anyhow::anyhow!("failed to send webhook"))
+        // Once a source had a worker, it keeps sending through it, so
+        // payloads can't overtake the ones still waiting there
+        let has_worker = self
+            .workers
+            .lock()
+            .expect("workers lock poisoned")
+            .contains_key(&webhook.source);
+        if webhook.rate_limit.is_none() && !has_worker {
+            let budget = Duration::from_secs(self.sender.env.get().webhook_budget_secs);
+            let started = Instant::now();
+            for (i, payload) in payloads.iter().enumerate() {
+                if !budget.is_zero() && !webhook.source.is_empty() && started.elapsed() >= budget {
+                    let rest = &payloads[i..];
+                    match self
+                        .db
+                        .queue_payloads(&webhook.source, &webhook.urls, rest)
+                        .await
+                    {
+                        Ok(()) => tracing::warn!(
+                            "webhook budget of {}s spent, queued {} webhooks for the next poll",
+                            budget.as_secs(),
+                            rest.len()
+                        ),
+                        Err(e) => tracing::error!("failed to queue webhooks, dropping them: {e}"),
+                    }
+                    return;
+                }
+                self.sender.send_all(webhook, payload).await;
+            }
+            return;
+        }
+
+        let mut workers = self.workers.lock().expect("workers lock poisoned");
+        let tx = workers
+            .entry(webhook.source.clone())
+            .or_insert_with(|| self.spawn_worker(&webhook.source));
+        if let Err(mpsc::error::SendError((webhook, payloads))) =
+            tx.send((webhook.clone(), payloads))
+        {
+            tracing::error!(
+                "delivery worker of {} stopped, dropping {} webhooks",
+                webhook.source,
+                payloads.len()
+            );
+        }
     }
 
-    async fn send_webhook(
+    /// Start a task sending the deliveries of one source in order.
+    ///
+    /// The task stops and removes itself from the workers once all
+    /// deliveries are sent, so removed sources don't keep a worker.
+    fn spawn_worker(&self, source: &str) -> mpsc::UnboundedSender<Delivery> {
+        let (tx, mut rx) = mpsc::unbounded_channel::<Delivery>();
+        let sender = Arc::clone(&self.sender);
+        let workers = Arc::clone(&self.workers);
+        let source = source.to_string();
+        let worker_tx = tx.clone();
+        self.limited.spawn(async move {
+            while let Some((webhook, payloads)) = rx.recv().await {
+                for payload in &payloads {
+                    if let Some(limiter) = &webhook.rate_limit {
+                        limiter.acquire().await;
+                    }
+                    sender.send_all(&webhook, payload).await;
+                }
+
+                // Deliveries are sent while holding the lock, so none
+                // can arrive between the check and the removal
+                let mut workers = workers.lock().expect("workers lock poisoned");
+                if rx.is_empty() {
+                    if workers
+                        .get(&source)
+                        .is_some_and(|tx| tx.same_channel(&worker_tx))
+                    {
+                        workers.remove(&source);
+                    }
+                    break;
+                }
+            }
+        });
+        tx
+    }
+
+    pub async fn stop(mut self) {
+        self.shutdown.cancel();
+        self.rx.close();
+    }
+}
+
+impl WebhookSender {
+    /// Send payload to all urls of the webhook at once, recording the
+    /// latency of successful deliveries.
+    ///
+    /// While the circuit of a url is open or it has queued payloads, the
+    /// payload is queued for that url only and sent with
+    /// [Event::FlushQueued], the other urls get it right away.
+    async fn send_all(&self, webhook: &WebhookConfig, payload: &Payload) {
+        let body = match Body::new(payload, webhook.compress) {
+            Ok(body) => body,
+            Err(e) => {
+                tracing::error!("failed to encode webhook, dropping it: {e}");
+                return;
+            }
+        };
+
+        let body = &body;
+        let sends = webhook.urls.iter().map(|url| async move {
+            if self.must_queue(webhook, url).await {
+                self.queue(webhook, url, payload).await;
+                return;
+            }
+            match self.send_webhook_retry(url, body, webhook, 5).await {
+                Ok(_) => {
+                    self.delivered.fetch_add(1, Ordering::Relaxed);
+                }
+                Err(e) if e.is::<CircuitOpen>() => self.queue(webhook, url, payload).await,
+                Err(e) => tracing::error!("failed to deliver webhook to {url}: {e}"),
+            }
+        });
+        join_all(sends).await;
+    }
+
+    /// Check if payloads for url have to wait, because its circuit is
+    /// open or earlier payloads are still queued for it.
+    async fn must_queue(&self, webhook: &WebhookConfig, url: &str) -> bool {
+        if self.breakers.is_open(url) {
+            return true;
+        }
+        if webhook.source.is_empty() {
+            return false;
+        }
+        self.db
+            .has_queued_payloads(&webhook.source, url)
+            .await
+            .unwrap_or_else(|e| {
+                tracing::error!("failed to check queued webhooks: {e}");
+                false
+            })
+    }
+
+    /// Store a payload for url until [Event::FlushQueued].
+    async fn queue(&self, webhook: &WebhookConfig, url: &str, payload: &Payload) {
+        if webhook.source.is_empty() {
+            tracing::error!("circuit open for webhook {url}, dropping payload");
+            return;
+        }
+        match self
+            .db
+            .queue_payloads(
+                &webhook.source,
+                &[url.to_string()],
+                std::slice::from_ref(payload),
+            )
+            .await
+        {
+            Ok(()) => tracing::warn!(
+                "webhook {url} of {} is paused, queued the payload",
+                webhook.source
+            ),
+            Err(e) => tracing::error!("failed to queue webhook, dropping it: {e}"),
+        }
+    }
+
+    /// Send payload to a single url, waits for a free permit first.
+    ///
+    /// Permits are only held by this handler and released after every
+    /// request, so waiting for one can't block the drain on shutdown.
+    /// Unsuccessful responses are returned as well.
+    /// `webhook_auth` of the source replaces the global `x-secret` header.
+    async fn post_webhook(
         &self,
         url: &str,
-        channel: &Channel,
-        new_posts: &[Post],
+        body: &Body<'_>,
+        webhook: &WebhookConfig,
     ) -> anyhow::Result<reqwest::Response> {
-        let payload = WebhookPayload { channel, new_posts };
-
-        let res = self
-            .client
-            .post(url)
-            .header(
-                "x-secret",
-                &config::get_env()
-                    .webhook_secret
-                    .clone()
-                    .unwrap_or("".to_string()),
-            )
-            .json(&payload)
+        let _permit = self.permits.acquire().await?;
+        let env = self.env.get();
+        let secret = env.webhook_secret.as_deref().unwrap_or_default();
+        let req = match body {
+            Body::Encoded {
+                content_type,
+                bytes,
+                gzip,
+            } => {
+                let mut req = webhook
+                    .auth
+                    .apply(self.client.post(url), secret)
+                    .header(reqwest::header::CONTENT_TYPE, *content_type);
+                if *gzip {
+                    req = req.header(reqwest::header::CONTENT_ENCODING, "gzip");
+                }
+                req.body(bytes.clone())
+            }
+            // The secret is not sent to Telegram, the url holds the bot token
+            Body::Bot { method, params } => {
+                let (url, chat_id) = telegram_bot::method_url(url, method)?;
+                let mut params = (*params).clone();
+                params["chat_id"] = chat_id.into();
+                self.client.post(url).json(&params)
+            }
+        };
+        let res = req
+            .timeout(Duration::from_secs(env.webhook_timeout))
             .send()
             .await?;
 
+        Ok(res)
+    }
+
+    /// Same as [WebhookSender::post_webhook], but fails on unsuccessful status.
+    async fn send_webhook(
+        &self,
+        url: &str,
+        body: &Body<'_>,
+        webhook: &WebhookConfig,
+    ) -> anyhow::Result<reqwest::Response> {
+        let res = self.post_webhook(url, body, webhook).await?;
         if !res.status().is_success() {
             return Err(anyhow::anyhow!(res.status()));
         }
@@ -184,19 +687,40 @@ impl EventHandler {
     async fn send_webhook_retry(
         &self,
         url: &str,
-        channel: &Channel,
-        new_posts: &[Post],
+        body: &Body<'_>,
+        webhook: &WebhookConfig,
         max_retries: u64,
     ) -> anyhow::Result<reqwest::Response> {
         for att in 1..=max_retries {
-            match self.send_webhook(url, channel, new_posts).await {
+            if !self.breakers.allow(url) {
+                return Err(CircuitOpen(url.to_string()).into());
+            }
+
+            let started = Instant::now();
+            let res = self.send_webhook(url, body, webhook).await;
+            match &res {
+                Ok(_) => {
+                    self.breakers.record_success(url);
+                    if let Some(latency) = &webhook.latency {
+                        latency.record(started.elapsed(), att);
+                    }
+                }
+                Err(_) => self.breakers.record_failure(url),
+            }
+
+            match res {
                 Ok(res) => return Ok(res),
                 Err(e) if att < max_retries => {
-                    tracing::warn!("webhook failed ({}/{}): {}", att, max_retries, e);
-                    sleep(Duration::from_secs(1)).await;
+                    tracing::warn!("webhook {} failed ({}/{}): {}", url, att, max_retries, e);
+                    sleep(webhook.retry_delay.unwrap_or(Duration::from_secs(1))).await;
                 }
                 Err(e) => {
-                    tracing::error!("webhook failed after {} attempts: {}", max_retries, e);
+                    tracing::error!(
+                        "webhook {} failed after {} attempts: {}",
+                        url,
+                        max_retries,
+                        e
+                    );
                     return Err(e);
                 }
             }
@@ -205,8 +729,489 @@ impl EventHandler {
         Err(anyhow::anyhow!("webhook failed"))
     }
 
-    pub async fn stop(mut self) {
-        self.shutdown.cancel();
-        self.rx.close();
+    /// Send payload to each url once, without retries or circuit breakers.
+    async fn test(&self, webhook: &WebhookConfig, payload: &Payload) -> Vec<WebhookTest> {
+        let body = &Body::new(payload, webhook.compress);
+        let tests = webhook.urls.iter().map(|url| async move {
+            let res = async {
+                let body = body.as_ref().map_err(|e| anyhow::anyhow!("{e}"))?;
+                let res = self.post_webhook(url, body, webhook).await?;
+                let status = res.status().as_u16();
+                let body: String = res.text().await?.chars().take(TEST_BODY_LEN).collect();
+                anyhow::Ok((status, body))
+            }
+            .await;
+
+            match res {
+                Ok((status, body)) => WebhookTest {
+                    url: url.clone(),
+                    status: Some(status),
+                    body: Some(body),
+                    error: None,
+                },
+                Err(e) => WebhookTest {
+                    url: url.clone(),
+                    status: None,
+                    body: None,
+                    error: Some(e.to_string()),
+                },
+            }
+        });
+
+        join_all(tests).await
+    }
+}
+
+/// Request refused because the circuit of the url is open
+#[derive(Debug)]
+struct CircuitOpen(String);
+
+impl fmt::Display for CircuitOpen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "circuit open for webhook {}", self.0)
+    }
+}
+
+impl std::error::Error for CircuitOpen {}
+
+/// Request body of a payload, encoded once for every url and retry
+enum Body<'a> {
+    /// JSON or raw template body, gzipped if `gzip` is set
+    Encoded {
+        content_type: &'a str,
+        bytes: Vec<u8>,
+        gzip: bool,
+    },
+    /// Bot API call, completed with the chat id of each url
+    Bot {
+        method: &'a str,
+        params: &'a serde_json::Value,
+    },
+}
+
+impl<'a> Body<'a> {
+    /// Encode the payload, gzipped if `compress` is set and the body
+    /// is at least [webhook::COMPRESS_MIN_LEN] bytes.
+    fn new(payload: &'a Payload, compress: bool) -> anyhow::Result<Self> {
+        let (content_type, bytes) = match payload {
+            Payload::Json(value) => ("application/json", serde_json::to_vec(value)?),
+            Payload::Raw { body, content_type } => {
+                (content_type.as_str(), body.clone().into_bytes())
+            }
+            Payload::Bot { method, params } => return Ok(Self::Bot { method, params }),
+        };
+        let gzip = compress && bytes.len() >= webhook::COMPRESS_MIN_LEN;
+        let bytes = if gzip { webhook::gzip(&bytes)? } else { bytes };
+
+        Ok(Self::Encoded {
+            content_type,
+            bytes,
+            gzip,
+        })
+    }
+}
+
+/// Key of the stored channel info, the source id if the webhook has one.
+fn info_key<'a>(channel: &'a str, webhook: &'a WebhookConfig) -> &'a str {
+    if webhook.source.is_empty() {
+        channel
+    } else {
+        &webhook.source
+    }
+}
+
+/// Sort posts from oldest to newest, posts without a valid date first.
+///
+/// Pages list posts in the order of the page, which doesn't always
+/// match their dates, like after a post was edited. The sort is stable,
+/// so posts with the same date keep their order.
+fn sort_by_date(posts: &mut [Post]) {
+    posts.sort_by_key(Post::parsed_date);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::EnvConfig;
+    use crate::webhook::rate_limit::RateLimiter;
+    use axum::http::StatusCode;
+
+    fn handler(db: &Db, breakers: Arc<CircuitBreakers>) -> EventHandler {
+        EventHandler::new(
+            mpsc::channel(1).1,
+            db.clone(),
+            Arc::default(),
+            broadcast::channel(1).0,
+            breakers,
+            EnvHandle::default(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_idle_worker_removed() {
+        let app = axum::Router::new().route("/hook", axum::routing::post(|| async { "ok" }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let db = Db::new(":memory:").await.unwrap();
+        let handler = handler(&db, Arc::default());
+        let webhook = WebhookConfig {
+            source: "test".to_string(),
+            urls: vec![url],
+            rate_limit: Some(Arc::new(RateLimiter::new(6000))),
+            ..Default::default()
+        };
+        let payload = Payload::Json(serde_json::json!({ "id": "test/1" }));
+        handler.deliver(&webhook, vec![payload]).await;
+        assert!(handler.workers.lock().unwrap().contains_key("test"));
+
+        handler.limited.close();
+        handler.limited.wait().await;
+        assert!(handler.workers.lock().unwrap().is_empty());
+        assert_eq!(handler.sender.delivered.load(Ordering::Relaxed), 1);
+    }
+
+    /// Start a webhook target answering with `status`, returns its url
+    /// and the number of requests it got.
+    async fn target(status: StatusCode) -> (String, Arc<AtomicUsize>) {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&hits);
+        let app = axum::Router::new().route(
+            "/hook",
+            axum::routing::post(move || async move {
+                counter.fetch_add(1, Ordering::Relaxed);
+                status
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (url, hits)
+    }
+
+    /// Start a webhook target keeping the JSON bodies it got.
+    async fn recorder() -> (String, Arc<Mutex<Vec<serde_json::Value>>>) {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let app = axum::Router::new()
+            .route(
+                "/hook",
+                axum::routing::post(
+                    |axum::extract::State(received): axum::extract::State<
+                        Arc<Mutex<Vec<serde_json::Value>>>,
+                    >,
+                     axum::Json(body): axum::Json<serde_json::Value>| async move {
+                        received.lock().unwrap().push(body);
+                        "ok"
+                    },
+                ),
+            )
+            .with_state(Arc::clone(&received));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (url, received)
+    }
+
+    #[tokio::test]
+    async fn test_queue_on_open_circuit() {
+        let db = Db::new(":memory:").await.unwrap();
+        let breakers = Arc::new(CircuitBreakers::default());
+        let url = "http://127.0.0.1:9/hook";
+        for _ in 0..5 {
+            breakers.record_failure(url);
+        }
+        let (healthy, hits) = target(StatusCode::OK).await;
+        let handler = handler(&db, breakers);
+        let webhook = WebhookConfig {
+            source: "test".to_string(),
+            urls: vec![url.to_string(), healthy.clone()],
+            ..Default::default()
+        };
+        let payload = Payload::Json(serde_json::json!({ "id": "test/1" }));
+
+        handler.deliver(&webhook, vec![payload.clone()]).await;
+        assert_eq!(hits.load(Ordering::Relaxed), 1);
+        // Kept while the circuit is still open, without sending it to
+        // the other url again
+        handler.handle_flush_queued(&webhook).await.unwrap();
+        assert_eq!(hits.load(Ordering::Relaxed), 1);
+        assert!(!db.has_queued_payloads("test", &healthy).await.unwrap());
+        assert_eq!(
+            db.take_queued_payloads("test", Some(url)).await.unwrap(),
+            [payload]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_queued_keep_order() {
+        let db = Db::new(":memory:").await.unwrap();
+        let breakers = Arc::new(CircuitBreakers::default());
+        let (url, received) = recorder().await;
+        for _ in 0..5 {
+            breakers.record_failure(&url);
+        }
+        let handler = handler(&db, Arc::clone(&breakers));
+        let webhook = WebhookConfig {
+            source: "test".to_string(),
+            urls: vec![url.clone()],
+            rate_limit: Some(Arc::new(RateLimiter::new(6000))),
+            ..Default::default()
+        };
+        let payload = |id: &str| Payload::Json(serde_json::json!({ "id": id }));
+        let idle = async || {
+            handler.limited.close();
+            handler.limited.wait().await;
+            handler.limited.reopen();
+        };
+
+        handler
+            .deliver(&webhook, vec![payload("test/1"), payload("test/2")])
+            .await;
+        idle().await;
+        // Later payloads wait behind the queued ones once the circuit closes
+        breakers.record_success(&url);
+        handler.deliver(&webhook, vec![payload("test/3")]).await;
+        idle().await;
+        assert!(received.lock().unwrap().is_empty());
+
+        handler.handle_flush_queued(&webhook).await.unwrap();
+        idle().await;
+        let ids: Vec<_> = received
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|p| p["id"].to_string())
+            .collect();
+        assert_eq!(ids, [r#""test/1""#, r#""test/2""#, r#""test/3""#]);
+        assert!(!db.has_queued_payloads("test", &url).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_failing_target() {
+        let db = Db::new(":memory:").await.unwrap();
+        let (failing, failed) = target(StatusCode::INTERNAL_SERVER_ERROR).await;
+        let (healthy, hits) = target(StatusCode::OK).await;
+        let handler = handler(&db, Arc::default());
+        let webhook = WebhookConfig {
+            source: "test".to_string(),
+            urls: vec![failing, healthy],
+            retry_delay: Some(Duration::from_millis(10)),
+            ..Default::default()
+        };
+        let payload = |id: &str| Payload::Json(serde_json::json!({ "id": id }));
+
+        handler.deliver(&webhook, vec![payload("test/1")]).await;
+        assert_eq!(failed.load(Ordering::Relaxed), 5);
+        assert_eq!(hits.load(Ordering::Relaxed), 1);
+
+        // The circuit of the failing url is open now
+        handler.deliver(&webhook, vec![payload("test/2")]).await;
+        handler.handle_flush_queued(&webhook).await.unwrap();
+        assert_eq!(failed.load(Ordering::Relaxed), 5);
+        assert_eq!(hits.load(Ordering::Relaxed), 2);
+        assert_eq!(handler.sender.delivered.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn test_queue_over_budget() {
+        let app = axum::Router::new().route(
+            "/hook",
+            axum::routing::post(|| async {
+                tokio::time::sleep(Duration::from_millis(1100)).await;
+                "ok"
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let db = Db::new(":memory:").await.unwrap();
+        let handler = handler(&db, Arc::default());
+        let env = handler.sender.env.get();
+        handler.sender.env.set(EnvConfig {
+            webhook_budget_secs: 1,
+            ..(*env).clone()
+        });
+        let webhook = WebhookConfig {
+            source: "test".to_string(),
+            urls: vec![url],
+            ..Default::default()
+        };
+        let payloads: Vec<_> = (1..=3)
+            .map(|i| Payload::Json(serde_json::json!({ "id": format!("test/{i}") })))
+            .collect();
+
+        handler.deliver(&webhook, payloads.clone()).await;
+        assert_eq!(handler.sender.delivered.load(Ordering::Relaxed), 1);
+        assert_eq!(
+            db.take_queued_payloads("test", Some(&webhook.urls[0]))
+                .await
+                .unwrap(),
+            payloads[1..]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_catchup_limit() {
+        let (url, received) = recorder().await;
+        let db = Db::new(":memory:").await.unwrap();
+        let handler = handler(&db, Arc::default());
+        let webhook = WebhookConfig {
+            source: "test".to_string(),
+            urls: vec![url],
+            catchup_limit: Some(2),
+            ..Default::default()
+        };
+        let page = Page {
+            channel: Channel {
+                id: "test".to_string(),
+                name: None,
+                image: None,
+                counters: Default::default(),
+                description: None,
+            },
+            posts: (1..=4)
+                .map(|i| Post {
+                    id: format!("test/{i}"),
+                    date: Some(format!("2026-03-04T1{i}:00:00+00:00")),
+                    ..Default::default()
+                })
+                .collect(),
+        };
+
+        handler.handle_new_posts(&page, &webhook).await.unwrap();
+        // The skipped posts are still stored, so they aren't sent later
+        assert!(db.get_posts("test/1").await.unwrap().is_some());
+        let received = received.lock().unwrap();
+        let ids: Vec<_> = received[0]["new_posts"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|p| p["id"].as_str().unwrap())
+            .collect();
+        assert_eq!(received.len(), 1);
+        assert_eq!(ids, ["test/3", "test/4"]);
+    }
+
+    #[tokio::test]
+    async fn test_album_across_pages() {
+        let db = Db::new(":memory:").await.unwrap();
+        let handler = handler(&db, Arc::default());
+        let webhook = WebhookConfig {
+            dry_run: true,
+            ..Default::default()
+        };
+        let album = |id: u32, size: u32| Post {
+            id: format!("test/{id}"),
+            album_size: Some(size),
+            ..Default::default()
+        };
+        let page = |posts: Vec<Post>| Page {
+            channel: Channel {
+                id: "test".to_string(),
+                name: None,
+                image: None,
+                counters: Default::default(),
+                description: None,
+            },
+            posts,
+        };
+        let mut rx = handler.post_tx.subscribe();
+
+        handler
+            .handle_new_posts(&page(vec![album(10, 4)]), &webhook)
+            .await
+            .unwrap();
+        assert_eq!(rx.try_recv().unwrap().post.id, "test/10");
+        // The next page starts inside the album
+        handler
+            .handle_new_posts(&page(vec![album(12, 2), album(14, 2)]), &webhook)
+            .await
+            .unwrap();
+        // An older page ends inside it
+        assert_eq!(
+            handler
+                .handle_backfill(&page(vec![album(8, 3)]))
+                .await
+                .unwrap(),
+            0
+        );
+
+        assert_eq!(rx.try_recv().unwrap().post.id, "test/14");
+        assert!(rx.try_recv().is_err());
+        assert!(db.get_posts("test/12").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_channel_info_per_source() {
+        let db = Db::new(":memory:").await.unwrap();
+        let handler = handler(&db, Arc::default());
+        let webhook = |source: &str| WebhookConfig {
+            source: source.to_string(),
+            dry_run: true,
+            ..Default::default()
+        };
+        let mut channel = Channel {
+            id: "test".to_string(),
+            name: Some("Test".to_string()),
+            image: None,
+            counters: Default::default(),
+            description: None,
+        };
+        for source in ["a", "b"] {
+            handler
+                .handle_channel_info(&channel, &webhook(source))
+                .await
+                .unwrap();
+        }
+
+        // Both sources see the rename, not only the first one to poll
+        channel.name = Some("Renamed".to_string());
+        handler
+            .handle_channel_info(&channel, &webhook("a"))
+            .await
+            .unwrap();
+        let info = db.get_channel_info("b").await.unwrap().unwrap();
+        assert_eq!(info.name.as_deref(), Some("Test"));
+    }
+
+    #[test]
+    fn test_body_compressed_once() {
+        let payload = Payload::Raw {
+            body: "a".repeat(webhook::COMPRESS_MIN_LEN),
+            content_type: "text/plain".to_string(),
+        };
+        let Body::Encoded { bytes, gzip, .. } = Body::new(&payload, true).unwrap() else {
+            panic!("raw payload is not encoded");
+        };
+        assert!(gzip);
+        assert!(bytes.len() < webhook::COMPRESS_MIN_LEN);
+
+        let payload = Payload::Json(serde_json::json!({ "id": "test/1" }));
+        let Body::Encoded { gzip, .. } = Body::new(&payload, true).unwrap() else {
+            panic!("json payload is not encoded");
+        };
+        assert!(!gzip);
+    }
+
+    #[test]
+    fn test_sort_by_date() {
+        let mut posts: Vec<_> = [
+            ("test/2", "2026-03-04T12:00:00+00:00"),
+            ("test/3", "2026-03-04T15:30:00+03:00"),
+            ("test/1", "2026-03-04T11:00:00+00:00"),
+        ]
+        .into_iter()
+        .map(|(id, date)| Post {
+            id: id.to_string(),
+            date: Some(date.to_string()),
+            ..Default::default()
+        })
+        .collect();
+
+        sort_by_date(&mut posts);
+        let ids: Vec<_> = posts.iter().map(|p| p.id.as_str()).collect();
+        assert_eq!(ids, ["test/1", "test/2", "test/3"]);
     }
 }
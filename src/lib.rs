@@ -1,25 +1,43 @@
-use std::{collections::HashMap, sync::Arc};
-use tokio::sync::{Mutex, mpsc};
+use futures_util::{StreamExt, stream};
+use std::{collections::HashMap, fmt, sync::Arc};
+use tokio::sync::{Mutex, broadcast, mpsc, oneshot};
+use tokio::time::{Duration, Instant};
 use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
 
-use config::EnvConfig;
+use config::{EnvConfig, EnvHandle};
 use events::{Event, EventHandler};
 
+use crate::model::WebhookTest;
+use crate::sources::client_pool::ClientPool;
 use crate::sources::registry;
-use crate::sources::{Source, SourceConfig, SourceInfo};
+use crate::sources::{BulkResult, Source, SourceConfig, SourceDebug, SourceInfo, SourceStats};
+use crate::webhook::breaker::{CircuitBreakers, TargetStatus};
 
 pub mod api;
 pub mod config;
 pub mod db;
 pub mod events;
+pub mod logging;
 pub mod model;
+pub mod openapi;
 pub mod sources;
+pub mod tls;
+pub mod webhook;
+pub mod websocket;
+
+type SourceMap = Arc<Mutex<HashMap<String, Arc<Box<dyn Source + Send>>>>>;
+
+/// Sources of [Server::add_sources] that are probed at the same time
+const BULK_PROBES: usize = 8;
 
 /// Core server state for the server.
 pub struct Server {
     pub shutdown: CancellationToken,
+    /// Child of `shutdown` stopping the sources while the API keeps serving
+    stopping: CancellationToken,
 
-    sources: Mutex<HashMap<String, Arc<Box<dyn Source + Send>>>>,
+    sources: SourceMap,
     ntf: model::NtfMap,
     db: db::Db,
 
@@ -27,36 +45,183 @@ pub struct Server {
     cmd_rx: Mutex<Option<mpsc::Receiver<SourceCmd>>>,
     event_tx: mpsc::Sender<Event>,
     event_rx: Mutex<Option<mpsc::Receiver<Event>>>,
+    post_tx: broadcast::Sender<model::PostEvent>,
+    breakers: Arc<CircuitBreakers>,
+    /// HTTP clients shared by sources when `share_clients` is enabled
+    clients: Arc<ClientPool>,
+    /// Config of this server, replaced by [Server::update_config]
+    env: EnvHandle,
+    started: Instant,
+}
+
+/// Builder for a [Server] configured from code, see [Server::builder]
+#[derive(Default)]
+pub struct ServerBuilder {
+    env: EnvConfig,
+    sources: Vec<SourceConfig>,
+}
+
+impl ServerBuilder {
+    /// Replace the whole [EnvConfig], defaults to [EnvConfig::default].
+    pub fn env(mut self, env: EnvConfig) -> Self {
+        self.env = env;
+        self
+    }
+
+    /// Path to the SQLite database, `:memory:` keeps it in memory.
+    ///
+    /// Overrides `data_dir` and `db_filename` of the config.
+    pub fn db_path(mut self, path: impl Into<String>) -> Self {
+        self.env.db_path = Some(path.into());
+        self
+    }
+
+    /// Store the source on build, like an entry of `channels_json`.
+    pub fn source(mut self, cfg: SourceConfig) -> Self {
+        self.sources.push(cfg);
+        self
+    }
+
+    /// Validate the config, open the database and store the sources.
+    pub async fn build(self) -> anyhow::Result<Server> {
+        Server::build(self.env, self.sources).await
+    }
 }
 
 /// Commands for the [Server] to manage sources
 pub enum SourceCmd {
-    Add(SourceConfig),
+    /// Start a built source and report the result back
+    Add(
+        SourceConfig,
+        Box<dyn Source + Send>,
+        oneshot::Sender<anyhow::Result<()>>,
+    ),
     Remove(String),
+    PollNow(String),
+    /// Stop and start a running source again, keeping its stored state
+    Restart(String, oneshot::Sender<anyhow::Result<()>>),
 }
 
+/// Errors returned by [Server::add_source] and [Server::update_source]
+#[derive(Debug)]
+pub enum SourceError {
+    /// Source with the same id already exists
+    Exists(String),
+    /// Id is already used by a source of another channel
+    Collision { id: String, existing: String },
+    /// Config failed validation
+    Invalid(anyhow::Error),
+    /// Source could not be created, e.g. the proxy list is unreachable
+    Spawn(anyhow::Error),
+    /// No source is stored with the id
+    NotFound(String),
+    /// Source is stored, but stopped
+    NotRunning(String),
+    /// Source is already running
+    Running(String),
+    /// Server is stopping its sources
+    ShuttingDown,
+}
+
+impl fmt::Display for SourceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Exists(id) => write!(f, "source {id} already exists"),
+            Self::Collision { id, existing } => {
+                write!(f, "source id {id} is already used by {existing}")
+            }
+            Self::Invalid(e) => write!(f, "invalid source config: {e}"),
+            Self::Spawn(e) => write!(f, "failed to start source: {e}"),
+            Self::NotFound(id) => write!(f, "source {id} not found"),
+            Self::NotRunning(id) => write!(f, "source {id} is not running"),
+            Self::Running(id) => write!(f, "source {id} is already running"),
+            Self::ShuttingDown => write!(f, "server is shutting down"),
+        }
+    }
+}
+
+impl std::error::Error for SourceError {}
+
 impl Server {
-    /// Create a new instance of [Server].
+    /// Create a new instance of [Server] with config from environment.
     pub async fn new() -> anyhow::Result<Self> {
+        Self::with_config(EnvConfig::from_dotenv()?).await
+    }
+
+    /// Create a new instance of [Server] with the given [EnvConfig].
+    ///
+    /// Sources from `channels_json` are stored, and started by [Server::run].
+    pub async fn with_config(env: EnvConfig) -> anyhow::Result<Self> {
+        Self::builder().env(env).build().await
+    }
+
+    /// Configure a [Server] from code, without reading the environment.
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder::default()
+    }
+
+    async fn build(env: EnvConfig, sources: Vec<SourceConfig>) -> anyhow::Result<Self> {
         tracing::info!("initializing");
-        let env = EnvConfig::from_dotenv()?;
         let (cmd_tx, cmd_rx) = mpsc::channel(100);
         env.validate()?;
         let (event_tx, event_rx) = mpsc::channel(100);
+        let (post_tx, _) = broadcast::channel(100);
 
-        let db = db::Db::new(&env.db_path).await?;
-        config::init_env(env);
+        let db = db::Db::with_options(&env.database_path(), &env.pool_options()).await?;
+        let mut seeds = env.seed_sources()?;
+        seeds.extend(sources);
+        let env = EnvHandle::new(env);
 
-        Ok(Self {
-            shutdown: CancellationToken::new(),
-            sources: Mutex::new(HashMap::new()),
+        let shutdown = CancellationToken::new();
+        let server = Self {
+            stopping: shutdown.child_token(),
+            shutdown,
+            sources: Arc::new(Mutex::new(HashMap::new())),
             ntf: Arc::new(Mutex::new(HashMap::new())),
             db,
             cmd_tx,
             cmd_rx: Mutex::new(Some(cmd_rx)),
             event_tx,
             event_rx: Mutex::new(Some(event_rx)),
-        })
+            post_tx,
+            breakers: Arc::new(CircuitBreakers::default()),
+            clients: Arc::new(ClientPool::new(env.clone())),
+            env,
+            started: Instant::now(),
+        };
+        server.seed_sources(seeds).await?;
+
+        Ok(server)
+    }
+
+    /// Store sources defined in `channels_json` or [ServerBuilder::source].
+    ///
+    /// A stored source with the same id is replaced if its config differs,
+    /// so the environment stays the source of truth across restarts.
+    /// Nothing is stored if any of the sources is invalid.
+    async fn seed_sources(&self, seeds: Vec<SourceConfig>) -> anyhow::Result<()> {
+        let mut sources = Vec::new();
+        let mut problems = Vec::new();
+        for (i, cfg) in seeds.into_iter().enumerate() {
+            match registry::normalize(cfg, &self.env.get()) {
+                Ok(cfg) if cfg.id.is_empty() => problems.push(format!("source {i} has no id")),
+                Ok(cfg) => sources.push(cfg),
+                Err(e) => problems.push(format!("source {i}: {e}")),
+            }
+        }
+        if !problems.is_empty() {
+            anyhow::bail!("invalid seeded sources:\n  - {}", problems.join("\n  - "));
+        }
+
+        for cfg in sources {
+            match self.db.get_source(&cfg.id).await? {
+                Some(existing) if existing.kind == cfg.kind && existing.raw == cfg.raw => continue,
+                Some(_) => tracing::info!("updating seeded source {}", cfg.id),
+                None => tracing::info!("adding seeded source {}", cfg.id),
+            }
+            self.db.insert_source(&cfg).await?;
+        }
+        Ok(())
     }
 
     /// Run [Server]
@@ -68,12 +233,37 @@ impl Server {
             .await
             .take()
             .expect("event receiver already taken");
-        let event_handler = EventHandler::new(event_rx, self.db.clone(), self.ntf.clone());
-        tokio::spawn(async move { event_handler.run().await });
+        let event_handler = EventHandler::new(
+            event_rx,
+            self.db.clone(),
+            self.ntf.clone(),
+            self.post_tx.clone(),
+            self.breakers.clone(),
+            self.env.clone(),
+        );
+        let drain = event_handler.shutdown_token();
+        let mut event_handle = tokio::spawn(async move { event_handler.run().await });
 
-        // Load sources from db
+        // Load sources from db, spreading their first polls
+        let jitter = self.env.get().poll_jitter;
         for cfg in self.db.get_all_sources().await? {
-            self.spawn_source(&cfg).await;
+            if !cfg.active {
+                tracing::info!("source {} is inactive: {:?}", cfg.id, cfg.error);
+                continue;
+            }
+            let res = async {
+                let source = self.build_source(&cfg).await?;
+                if jitter {
+                    source.jitter_first_poll();
+                }
+                self.start_source(&cfg, source).await
+            };
+            if let Err(e) = res.await {
+                tracing::error!("failed to start source {}: {e}", cfg.id);
+            }
+        }
+        if self.env.get().verify_webhooks_on_start {
+            tokio::spawn(Arc::clone(&self).verify_webhooks());
         }
 
         // Command loop
@@ -86,34 +276,278 @@ impl Server {
 
         loop {
             tokio::select! {
-                _ = self.shutdown.cancelled() => {
+                _ = self.stopping.cancelled() => {
+                    // New commands fail right away, queued ones are answered
+                    cmd_rx.close();
+                    while let Ok(cmd) = cmd_rx.try_recv() {
+                        match cmd {
+                            SourceCmd::Add(_, _, ack) | SourceCmd::Restart(_, ack) => {
+                                ack.send(Err(SourceError::ShuttingDown.into())).ok();
+                            }
+                            SourceCmd::Remove(_) | SourceCmd::PollNow(_) => {}
+                        }
+                    }
                     self.stop_all().await;
                     break;
                 }
                 cmd = cmd_rx.recv() => {
                     match cmd {
-                        Some(SourceCmd::Add(cfg)) => self.spawn_source(&cfg).await,
+                        Some(SourceCmd::Add(cfg, source, ack)) => {
+                            ack.send(self.start_source(&cfg, source).await).ok();
+                        }
                         Some(SourceCmd::Remove(id)) => self.shutdown_source(&id).await,
+                        Some(SourceCmd::Restart(id, ack)) => {
+                            ack.send(self.restart(&id).await).ok();
+                        }
+                        Some(SourceCmd::PollNow(id)) => {
+                            let source = self.sources.lock().await.get(&id).cloned();
+                            match source.map(|s| s.poll_now()) {
+                                Some(Err(e)) => tracing::warn!("failed to poll source {id}: {e}"),
+                                None => tracing::warn!("source {id} is not running"),
+                                Some(Ok(_)) => {}
+                            }
+                        }
                         None => self.shutdown.cancel(),
                     }
                 }
             }
         }
 
+        // Sources are stopped, let the event handler finish in-flight webhooks
+        let env = self.env.get();
+        let mut timeout = Duration::from_secs(env.drain_timeout);
+        if env.shutdown_grace_secs > 0 {
+            timeout = timeout.min(Duration::from_secs(env.shutdown_grace_secs));
+        }
+        tracing::info!("draining events for up to {}s", timeout.as_secs());
+        drain.cancel();
+        if tokio::time::timeout(timeout, &mut event_handle)
+            .await
+            .is_err()
+        {
+            tracing::warn!("drain timed out, dropping remaining webhooks");
+            event_handle.abort();
+        }
+
+        // Stop the API as well when shutting down gracefully
+        self.shutdown.cancel();
         Ok(())
     }
 
-    /// Send a command to create a [Source].
-    pub async fn add_source(&self, cfg: &SourceConfig) -> anyhow::Result<()> {
-        self.db.insert_source(cfg).await?;
-        self.cmd_tx.send(SourceCmd::Add(cfg.clone())).await?;
+    /// Stop the sources and drain events, then cancel [Server::shutdown].
+    ///
+    /// The API keeps serving for up to `shutdown_grace_secs`, after which
+    /// the shutdown is forced. Without a grace period, [Server::shutdown]
+    /// is cancelled right away.
+    pub async fn graceful_shutdown(&self) {
+        let grace = Duration::from_secs(self.env.get().shutdown_grace_secs);
+        if grace.is_zero() {
+            self.shutdown.cancel();
+            return;
+        }
+
+        self.stopping.cancel();
+        let deadline = Instant::now() + grace;
+        let mut countdown = tokio::time::interval(Duration::from_secs(5));
+        loop {
+            tokio::select! {
+                _ = self.shutdown.cancelled() => return,
+                _ = tokio::time::sleep_until(deadline) => {
+                    tracing::warn!("grace period elapsed, forcing shutdown");
+                    self.shutdown.cancel();
+                    return;
+                }
+                _ = countdown.tick() => {
+                    let left = deadline.saturating_duration_since(Instant::now());
+                    tracing::info!("shutting down in {}s", left.as_secs());
+                }
+            }
+        }
+    }
+
+    /// Check that the webhook urls of the running sources respond.
+    ///
+    /// Any response counts as reachable, unreachable urls are only
+    /// logged with the host, as the url may hold a token.
+    async fn verify_webhooks(self: Arc<Self>) {
+        let client = reqwest::Client::new();
+        let sources: Vec<_> = self.sources.lock().await.values().cloned().collect();
+        let mut checks = Vec::new();
+        for source in &sources {
+            for url in source.webhook_urls().await {
+                let client = &client;
+                checks.push(async move {
+                    let host = url::Url::parse(&url)
+                        .ok()
+                        .and_then(|u| u.host_str().map(str::to_string))
+                        .unwrap_or_else(|| "invalid url".to_string());
+                    let res = client
+                        .head(&url)
+                        .timeout(Duration::from_secs(10))
+                        .send()
+                        .await;
+                    match res {
+                        Ok(res) => tracing::info!(
+                            "webhook target {host} of source {} is reachable ({})",
+                            source.id(),
+                            res.status()
+                        ),
+                        Err(e) => tracing::warn!(
+                            "webhook target {host} of source {} is unreachable: {}",
+                            source.id(),
+                            e.without_url()
+                        ),
+                    }
+                });
+            }
+        }
+        futures_util::future::join_all(checks).await;
+    }
+
+    /// Send a command to create a [Source] and wait until it's started.
+    ///
+    /// Fails with [SourceError::Exists] if the id is taken, use
+    /// [Server::update_source] to modify existing sources, or with
+    /// [SourceError::Collision] if the id belongs to another channel,
+    /// or with [SourceError::Invalid] if its target is not valid, like a
+    /// t.me page that is not a channel. The target is checked before the
+    /// source is stored, the row is removed again if it can't be started.
+    ///
+    /// Returns the stored source with the defaults of its kind applied.
+    pub async fn add_source(&self, cfg: &SourceConfig) -> anyhow::Result<SourceInfo> {
+        let cfg = self.check_new_source(cfg).await?;
+        let source = self.probe_source(&cfg).await?;
+        self.db.insert_source(&cfg).await?;
+        self.start_stored_source(&cfg, source).await?;
+
+        self.stored_source(&cfg.id).await
+    }
+
+    /// Create many sources at once, like when importing a channel list.
+    ///
+    /// Every config is checked and probed like in [Server::add_source],
+    /// up to [BULK_PROBES] at a time. The valid ones are then stored in a
+    /// single transaction and started. Returns a result for each config
+    /// in the same order, an error is only returned if the transaction
+    /// fails and nothing was stored.
+    pub async fn add_sources(&self, cfgs: Vec<SourceConfig>) -> anyhow::Result<Vec<BulkResult>> {
+        let probed: Vec<_> = stream::iter(cfgs)
+            .map(|cfg| async move {
+                let res = async {
+                    let checked = self.check_new_source(&cfg).await?;
+                    let source = self.probe_source(&checked).await?;
+                    anyhow::Ok((checked, source))
+                }
+                .await;
+                (cfg.id, res)
+            })
+            .buffered(BULK_PROBES)
+            .collect()
+            .await;
+
+        let mut results = Vec::with_capacity(probed.len());
+        let mut valid: Vec<(SourceConfig, _)> = Vec::new();
+        for (id, res) in probed {
+            match res {
+                Ok((cfg, _)) if valid.iter().any(|(v, _)| v.id == cfg.id) => {
+                    let e = anyhow::anyhow!("source {} appears more than once", cfg.id);
+                    results.push(BulkResult::error(&cfg.id, e));
+                }
+                Ok((cfg, source)) => {
+                    results.push(BulkResult::ok(&cfg.id));
+                    valid.push((cfg, source));
+                }
+                Err(e) => results.push(BulkResult::error(&id, e)),
+            }
+        }
+        let cfgs: Vec<_> = valid.iter().map(|(cfg, _)| cfg.clone()).collect();
+        self.db.insert_sources(&cfgs).await?;
+
+        // Stored sources are kept, one that fails to start is stopped
+        // until it's resumed, like after an update
+        for (cfg, source) in valid {
+            if let Err(e) = self.start_cmd(&cfg, source).await {
+                tracing::warn!("failed to start imported source {}: {e}", cfg.id);
+                self.db.set_source_inactive(&cfg.id, &e.to_string()).await?;
+                if let Some(res) = results.iter_mut().find(|r| r.ok && r.id == cfg.id) {
+                    let e = anyhow::anyhow!("stored, but failed to start: {e}");
+                    *res = BulkResult::error(&cfg.id, e);
+                }
+            }
+        }
+
+        Ok(results)
+    }
+
+    /// Normalize a new source config and check that its id is free.
+    async fn check_new_source(&self, cfg: &SourceConfig) -> anyhow::Result<SourceConfig> {
+        let cfg =
+            registry::normalize(cfg.clone(), &self.env.get()).map_err(SourceError::Invalid)?;
+        if let Some(existing) = self.db.get_source(&cfg.id).await? {
+            let (old, new) = (existing.target(), cfg.target());
+            if existing.kind != cfg.kind || old != new {
+                return Err(SourceError::Collision {
+                    id: cfg.id,
+                    existing: old.unwrap_or(&existing.kind).to_string(),
+                }
+                .into());
+            }
+            return Err(SourceError::Exists(cfg.id).into());
+        }
+        Ok(cfg)
+    }
+
+    /// Build a new source and check its target with [Source::probe].
+    ///
+    /// Runs outside of the command loop, so other commands don't wait
+    /// for the probe request.
+    async fn probe_source(&self, cfg: &SourceConfig) -> anyhow::Result<Box<dyn Source + Send>> {
+        let source = self.build_source(cfg).await.map_err(SourceError::Spawn)?;
+        source.probe().await.map_err(SourceError::Invalid)?;
+        Ok(source)
+    }
 
+    /// Start a source that was just stored, removing its row if it fails.
+    async fn start_stored_source(
+        &self,
+        cfg: &SourceConfig,
+        source: Box<dyn Source + Send>,
+    ) -> anyhow::Result<()> {
+        if let Err(e) = self.start_cmd(cfg, source).await {
+            self.db.delete_source(&cfg.id).await?;
+            let e = e
+                .downcast::<SourceError>()
+                .unwrap_or_else(SourceError::Spawn);
+            return Err(e.into());
+        }
         Ok(())
     }
 
+    /// Send a command to start a built source and wait for the result.
+    async fn start_cmd(
+        &self,
+        cfg: &SourceConfig,
+        source: Box<dyn Source + Send>,
+    ) -> anyhow::Result<()> {
+        let (ack_tx, ack_rx) = oneshot::channel();
+        self.send_cmd(SourceCmd::Add(cfg.clone(), source, ack_tx))
+            .await?;
+        ack_rx.await.map_err(|_| SourceError::ShuttingDown)?
+    }
+
+    /// Send a command to the loop of [Server::run].
+    ///
+    /// Fails with [SourceError::ShuttingDown] once the sources are stopping.
+    async fn send_cmd(&self, cmd: SourceCmd) -> anyhow::Result<()> {
+        self.cmd_tx
+            .send(cmd)
+            .await
+            .map_err(|_| SourceError::ShuttingDown.into())
+    }
+
     /// Send a command to remove a [Source].
     pub async fn remove_source(&self, id: &str) -> anyhow::Result<()> {
-        self.cmd_tx.send(SourceCmd::Remove(id.to_string())).await?;
+        self.send_cmd(SourceCmd::Remove(id.to_string())).await?;
 
         // Remove from db
         if let Err(e) = self.db.delete_source(id).await {
@@ -123,20 +557,143 @@ impl Server {
         Ok(())
     }
 
+    /// Send a command to poll a running [Source] without waiting for its interval.
+    pub async fn poll_source(&self, id: &str) -> anyhow::Result<()> {
+        self.send_cmd(SourceCmd::PollNow(id.to_string())).await?;
+        Ok(())
+    }
+
+    /// Send a command to restart a running [Source] and wait until it's started.
+    ///
+    /// The source gets a new HTTP client and proxy, its database row
+    /// and stored posts are kept.
+    pub async fn restart_source(&self, id: &str) -> anyhow::Result<()> {
+        let (ack_tx, ack_rx) = oneshot::channel();
+        self.send_cmd(SourceCmd::Restart(id.to_string(), ack_tx))
+            .await?;
+        ack_rx.await.map_err(|_| SourceError::ShuttingDown)?
+    }
+
     /// Update [Source] with a new [SourceConfig] and [EnvConfig].
-    pub async fn update_source(&self, cfg: &SourceConfig) -> anyhow::Result<()> {
+    ///
+    /// Also resets the inactive state of a stopped [Source], and returns
+    /// the stored source like [Server::add_source].
+    pub async fn update_source(&self, cfg: &SourceConfig) -> anyhow::Result<SourceInfo> {
+        if self.db.get_source(&cfg.id).await?.is_none() {
+            return Err(SourceError::NotFound(cfg.id.clone()).into());
+        }
+
+        let cfg =
+            registry::normalize(cfg.clone(), &self.env.get()).map_err(SourceError::Invalid)?;
+        if self.check_source_running(&cfg.id).await {
+            self.shutdown_source(&cfg.id).await;
+        }
+        self.db.insert_source(&cfg).await?;
+
+        // Keep the new config, but mark it stopped with the reason
+        if let Err(e) = self.spawn_source(&cfg).await {
+            self.db.set_source_inactive(&cfg.id, &e.to_string()).await?;
+            return Err(SourceError::Spawn(e).into());
+        }
+
+        self.stored_source(&cfg.id).await
+    }
+
+    /// Resume a [Source] that was stopped because of an error.
+    pub async fn resume_source(&self, id: &str) -> anyhow::Result<()> {
+        let cfg = self
+            .db
+            .get_source(id)
+            .await?
+            .ok_or_else(|| SourceError::NotFound(id.to_string()))?;
+        if self.sources.lock().await.contains_key(id) {
+            return Err(SourceError::Running(id.to_string()).into());
+        }
+
+        self.update_source(&cfg).await?;
+        Ok(())
+    }
+
+    /// Import older posts of a running [Source] without sending webhooks.
+    ///
+    /// Returns the number of imported posts.
+    pub async fn backfill_source(&self, id: &str, pages: u32) -> anyhow::Result<usize> {
         let source = self
             .sources
             .lock()
             .await
-            .get(&cfg.id)
-            .ok_or(anyhow::anyhow!("source not found"))?
-            .clone();
+            .get(id)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("source is not running"))?;
+
+        source
+            .backfill(pages)
+            .instrument(tracing::info_span!("source", id = %id))
+            .await
+    }
+
+    /// Last page fetched by a running [Source] with `debug_capture`.
+    ///
+    /// Returns `None` if the source is not running.
+    pub async fn debug_source(&self, id: &str, html: bool) -> anyhow::Result<Option<SourceDebug>> {
+        let Some(source) = self.sources.lock().await.get(id).cloned() else {
+            return Ok(None);
+        };
+
+        source.debug(html).await.map(Some)
+    }
+
+    /// Send a sample post to the webhook urls of a running [Source].
+    ///
+    /// Returns `None` if the source is not running.
+    pub async fn test_webhook(&self, id: &str) -> anyhow::Result<Option<Vec<WebhookTest>>> {
+        let Some(source) = self.sources.lock().await.get(id).cloned() else {
+            return Ok(None);
+        };
+
+        source
+            .test_webhook()
+            .instrument(tracing::info_span!("source", id = %id))
+            .await
+            .map(Some)
+    }
+
+    /// Get the current [EnvConfig].
+    pub async fn get_config(&self) -> Arc<EnvConfig> {
+        self.env.get()
+    }
 
-        self.shutdown_source(source.id()).await;
-        self.spawn_source(cfg).await;
+    /// Validate and replace the current [EnvConfig].
+    ///
+    /// Sources read the config on every use, so the change takes effect
+    /// on their next poll. `bind_address`, `port`, the database path, the
+    /// dashboard settings, `max_concurrent_webhooks` and the tls files
+    /// require a restart.
+    pub async fn update_config(&self, env: EnvConfig) -> anyhow::Result<()> {
+        env.validate()?;
+        let old = self.env.set(env);
+        let new = self.env.get();
 
-        self.db.insert_source(cfg).await?;
+        if old.bind_address != new.bind_address
+            || old.port != new.port
+            || old.database_path() != new.database_path()
+            || old.serve_dashboard != new.serve_dashboard
+            || old.static_dir != new.static_dir
+            || old.max_concurrent_webhooks != new.max_concurrent_webhooks
+            || old.tls_cert_path != new.tls_cert_path
+            || old.tls_key_path != new.tls_key_path
+        {
+            tracing::warn!(
+                "bind_address, port, database path, dashboard, max_concurrent_webhooks and tls changes require a restart"
+            );
+        }
+        tracing::info!(
+            "config updated, min_poll_interval: {}s -> {}s, proxy_list_url: {:?} -> {:?}",
+            old.min_poll_interval,
+            new.min_poll_interval,
+            old.proxy_list_url,
+            new.proxy_list_url
+        );
 
         Ok(())
     }
@@ -167,10 +724,47 @@ impl Server {
             None => return Ok(None),
         };
 
-        res.active = self.check_source_running(id).await;
+        if let Some(source) = self.sources.lock().await.get(id) {
+            res.with_runtime(source.as_ref().as_ref());
+        }
         Ok(Some(res))
     }
 
+    /// Get a [Source] that was just stored, failing if it's gone again.
+    async fn stored_source(&self, id: &str) -> anyhow::Result<SourceInfo> {
+        self.get_source(id)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("source {id} was removed while starting"))
+    }
+
+    /// Compact the database, see [db::Db::vacuum].
+    pub async fn vacuum_db(&self) -> anyhow::Result<model::VacuumResult> {
+        self.db.vacuum().await
+    }
+
+    /// Search stored posts by text, see [db::Db::search_posts].
+    pub async fn search_posts(&self, query: &str, limit: u32) -> anyhow::Result<Vec<model::Post>> {
+        self.db.search_posts(query, limit).await
+    }
+
+    /// Get [SourceStats] of a source, `None` if it doesn't exist.
+    pub async fn source_stats(&self, id: &str) -> anyhow::Result<Option<SourceStats>> {
+        let Some(cfg) = self.db.get_source(id).await? else {
+            return Ok(None);
+        };
+        let Some(channel) = cfg.channel() else {
+            return Ok(Some(SourceStats {
+                posts: 0,
+                latest_post_date: None,
+            }));
+        };
+
+        Ok(Some(SourceStats {
+            posts: self.db.count_posts_by_channel(channel).await?,
+            latest_post_date: self.db.latest_post_date(channel).await?,
+        }))
+    }
+
     /// Get all [Source]s from the database.
     pub async fn get_all_sources(&self) -> anyhow::Result<Vec<SourceInfo>> {
         let running = self.sources.lock().await;
@@ -181,9 +775,11 @@ impl Server {
             .await?
             .into_iter()
             .map(|cfg| {
-                let active = running.contains_key(&cfg.id);
+                let source = running.get(&cfg.id);
                 let mut info = SourceInfo::from(cfg);
-                info.active = active;
+                if let Some(source) = source {
+                    info.with_runtime(source.as_ref().as_ref());
+                }
                 info
             })
             .collect();
@@ -218,15 +814,40 @@ impl Server {
         Ok(())
     }
 
+    /// Subscribe to new posts from all [Source]s.
+    ///
+    /// Slow receivers lag behind and skip posts instead of blocking sources.
+    pub fn subscribe_posts(&self) -> broadcast::Receiver<model::PostEvent> {
+        self.post_tx.subscribe()
+    }
+
     /// Get the health of the [Server].
     pub async fn health(&self) -> anyhow::Result<model::Health> {
         let sources = self.sources.lock().await;
         Ok(model::Health {
             ok: true,
             sources: sources.len(),
+            open_webhooks: self.breakers.open_count(),
         })
     }
 
+    /// Name and version of the build with the uptime of the server.
+    pub fn version(&self) -> model::Version {
+        model::Version {
+            name: env!("CARGO_PKG_NAME").to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            git_sha: option_env!("GIT_SHA")
+                .filter(|sha| !sha.is_empty())
+                .map(String::from),
+            uptime_secs: self.started.elapsed().as_secs(),
+        }
+    }
+
+    /// Circuit breaker status of webhook targets with recent failures.
+    pub fn get_webhook_status(&self) -> Vec<TargetStatus> {
+        self.breakers.status()
+    }
+
     /// Shutdowns all [Source]s.
     async fn stop_all(&self) {
         tracing::info!("stopping all sources");
@@ -241,34 +862,86 @@ impl Server {
         }
     }
 
-    async fn spawn_source(&self, cfg: &SourceConfig) {
+    /// Build and start a [Source].
+    async fn spawn_source(&self, cfg: &SourceConfig) -> anyhow::Result<()> {
         // Check if source already exists
         if self.sources.lock().await.contains_key(&cfg.id) {
-            tracing::warn!("source with id '{}' already exists", cfg.id);
-            return;
+            anyhow::bail!("source with id '{}' is already running", cfg.id);
         }
 
-        // Build source
-        let id = cfg.id.clone();
-        let source = match registry::build(cfg.clone(), self.event_tx.clone()).await {
-            Ok(s) => Arc::new(s),
-            Err(e) => {
-                tracing::error!("failed to build source: {e}");
-                return;
-            }
-        };
+        let source = self.build_source(cfg).await?;
+        self.start_source(cfg, source).await
+    }
 
+    async fn build_source(&self, cfg: &SourceConfig) -> anyhow::Result<Box<dyn Source + Send>> {
+        registry::build(
+            cfg.clone(),
+            self.event_tx.clone(),
+            Arc::clone(&self.clients),
+            self.env.clone(),
+        )
+        .await
+    }
+
+    /// Start a built [Source] in the background.
+    async fn start_source(
+        &self,
+        cfg: &SourceConfig,
+        source: Box<dyn Source + Send>,
+    ) -> anyhow::Result<()> {
+        if self.sources.lock().await.contains_key(&cfg.id) {
+            anyhow::bail!("source with id '{}' is already running", cfg.id);
+        }
+
+        let id = cfg.id.clone();
+        let source = Arc::new(source);
         self.sources
             .lock()
             .await
             .insert(id.clone(), Arc::clone(&source));
 
-        // Spawn source
-        tokio::task::spawn(async move {
+        // Spawn source, its logs carry the source id
+        let sources = Arc::clone(&self.sources);
+        let db = self.db.clone();
+        let span = tracing::info_span!("source", id = %id, log_level = cfg.log_level());
+        let task = async move {
             if let Err(e) = source.run().await {
                 tracing::error!("source {id} error: {e}");
+
+                // Mark as inactive so it's not restarted until resumed
+                if let Err(e) = db.set_source_inactive(&id, &e.to_string()).await {
+                    tracing::error!("failed to mark source {id} inactive: {e}");
+                }
+
+                // Remove from sources map, unless already replaced
+                let mut sources = sources.lock().await;
+                if sources.get(&id).is_some_and(|s| Arc::ptr_eq(s, &source)) {
+                    sources.remove(&id);
+                }
             }
-        });
+        };
+        tokio::task::spawn(task.instrument(span));
+
+        Ok(())
+    }
+
+    async fn restart(&self, id: &str) -> anyhow::Result<()> {
+        let cfg = self
+            .db
+            .get_source(id)
+            .await?
+            .ok_or_else(|| SourceError::NotFound(id.to_string()))?;
+        if !self.check_source_running(id).await {
+            return Err(SourceError::NotRunning(id.to_string()).into());
+        }
+
+        tracing::info!("restarting source {id}");
+        self.shutdown_source(id).await;
+        if let Err(e) = self.spawn_source(&cfg).await {
+            self.db.set_source_inactive(id, &e.to_string()).await?;
+            return Err(e);
+        }
+        Ok(())
     }
 
     async fn shutdown_source(&self, id: &str) {
@@ -288,3 +961,140 @@ impl Server {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn scraper_config(id: &str, channel: &str) -> SourceConfig {
+        serde_json::from_value(json!({
+            "id": id,
+            "kind": "telegram_scraper",
+            "raw": {
+                "id": id,
+                "channel_url": channel,
+                "poll_interval": 60,
+                "webhook_url": "https://example.com/hook",
+            },
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_id_collision() {
+        let env = envy::from_iter([("DB_PATH".to_string(), ":memory:".to_string())]).unwrap();
+        let server = Server::with_config(env).await.unwrap();
+        let existing =
+            registry::normalize(scraper_config("news", "durov"), &EnvConfig::default()).unwrap();
+        server.db.insert_source(&existing).await.unwrap();
+
+        let err = server
+            .add_source(&scraper_config("news", "telegram"))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(SourceError::Collision { existing, .. }) if existing == "https://t.me/s/durov"
+        ));
+
+        let err = server
+            .add_source(&scraper_config("news", "@durov"))
+            .await
+            .unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(SourceError::Exists(_))));
+    }
+
+    #[tokio::test]
+    async fn test_restart_stopped() {
+        let env = envy::from_iter([("DB_PATH".to_string(), ":memory:".to_string())]).unwrap();
+        let server = Server::with_config(env).await.unwrap();
+        let err = server.restart("news").await.unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(SourceError::NotFound(_))));
+
+        let cfg =
+            registry::normalize(scraper_config("news", "durov"), &EnvConfig::default()).unwrap();
+        server.db.insert_source(&cfg).await.unwrap();
+        let err = server.restart("news").await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(SourceError::NotRunning(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_resume_running() {
+        let env = envy::from_iter([("DB_PATH".to_string(), ":memory:".to_string())]).unwrap();
+        let server = Server::with_config(env).await.unwrap();
+        let err = server.resume_source("news").await.unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(SourceError::NotFound(_))));
+
+        let cfg =
+            registry::normalize(scraper_config("news", "durov"), &EnvConfig::default()).unwrap();
+        server.db.insert_source(&cfg).await.unwrap();
+        let source = server.build_source(&cfg).await.unwrap();
+        server
+            .sources
+            .lock()
+            .await
+            .insert(cfg.id.clone(), Arc::new(source));
+        let err = server.resume_source("news").await.unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(SourceError::Running(_))));
+    }
+
+    #[tokio::test]
+    async fn test_commands_during_shutdown() {
+        let env = envy::from_iter([
+            ("DB_PATH".to_string(), ":memory:".to_string()),
+            ("SHUTDOWN_GRACE_SECS".to_string(), "30".to_string()),
+        ])
+        .unwrap();
+        let server = Arc::new(Server::with_config(env).await.unwrap());
+        tokio::spawn(Arc::clone(&server).run());
+
+        server.stopping.cancel();
+        server.cmd_tx.closed().await;
+        let err = tokio::time::timeout(Duration::from_secs(1), server.restart_source("news"))
+            .await
+            .expect("command should not hang")
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(SourceError::ShuttingDown)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_builder() {
+        let server = Server::builder()
+            .db_path(":memory:")
+            .source(scraper_config("", "@durov"))
+            .build()
+            .await
+            .unwrap();
+
+        let sources = server.get_all_sources().await.unwrap();
+        assert_eq!(sources.len(), 1);
+        assert_eq!(sources[0].id, "durov");
+    }
+
+    #[tokio::test]
+    async fn test_seed_sources() {
+        let channels = json!([
+            { "channel_url": "@durov", "poll_interval": 60, "webhook_url": "https://example.com/a" },
+            { "id": "news", "channel_url": "telegram", "poll_interval": 120, "webhook_url": "https://example.com/b" },
+        ]);
+        let env = envy::from_iter([
+            ("DB_PATH".to_string(), ":memory:".to_string()),
+            ("CHANNELS_JSON".to_string(), channels.to_string()),
+        ])
+        .unwrap();
+        let server = Server::with_config(env).await.unwrap();
+
+        let durov = server.db.get_source("durov").await.unwrap().unwrap();
+        assert_eq!(durov.kind, "telegram_scraper");
+        assert_eq!(durov.target(), Some("https://t.me/s/durov"));
+        let news = server.db.get_source("news").await.unwrap().unwrap();
+        assert_eq!(news.raw["poll_interval"], 120);
+    }
+}
@@ -0,0 +1,100 @@
+//! Tracing filter with per-source log levels
+//!
+//! Sources run inside a `source` span. When the span has a `log_level`
+//! field, events inside it are logged up to that level, even if the
+//! global level is lower.
+
+use std::fmt;
+
+use tracing::field::{Field, Visit};
+use tracing::{Level, Subscriber, span};
+use tracing_subscriber::filter::dynamic_filter_fn;
+use tracing_subscriber::layer::{Context, Filter, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+/// Level from the `log_level` field of a span
+struct SpanLevel(Level);
+
+/// [Layer] remembering the `log_level` field of new spans, see [filter]
+pub struct SpanLevels;
+
+impl<S> Layer<S> for SpanLevels
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        let mut visitor = LevelVisitor(None);
+        attrs.record(&mut visitor);
+        if let (Some(level), Some(span)) = (visitor.0, ctx.span(id)) {
+            span.extensions_mut().insert(SpanLevel(level));
+        }
+    }
+}
+
+struct LevelVisitor(Option<Level>);
+
+impl Visit for LevelVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "log_level" {
+            self.0 = value.parse().ok();
+        }
+    }
+
+    fn record_debug(&mut self, _: &Field, _: &dyn fmt::Debug) {}
+}
+
+/// Filter passing events up to `level`, or up to the `log_level` of
+/// the innermost span that has one. Requires the [SpanLevels] layer.
+pub fn filter<S>(level: Level) -> impl Filter<S>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    dynamic_filter_fn(move |meta, cx| {
+        // Spans are always kept, so their level is known to their events
+        if meta.is_span() || *meta.level() <= level {
+            return true;
+        }
+        cx.lookup_current()
+            .and_then(|span| {
+                span.scope()
+                    .find_map(|s| s.extensions().get::<SpanLevel>().map(|l| l.0))
+            })
+            .is_some_and(|span_level| *meta.level() <= span_level)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tracing_subscriber::layer::SubscriberExt;
+
+    struct Count(Arc<AtomicUsize>);
+
+    impl<S: Subscriber> Layer<S> for Count {
+        fn on_event(&self, _: &tracing::Event<'_>, _: Context<'_, S>) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn test_span_level() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let subscriber = tracing_subscriber::registry()
+            .with(SpanLevels)
+            .with(Count(count.clone()).with_filter(filter(Level::INFO)));
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::debug!("dropped");
+            tracing::info_span!("source", id = "quiet").in_scope(|| tracing::debug!("dropped"));
+            tracing::info_span!("source", id = "noisy", log_level = "debug").in_scope(|| {
+                tracing::debug!("logged");
+                tracing::trace!("dropped");
+            });
+            tracing::info!("logged");
+        });
+
+        assert_eq!(count.load(Ordering::Relaxed), 2);
+    }
+}
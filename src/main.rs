@@ -1,23 +1,34 @@
-use litehook::{Server, api::Api};
+use litehook::{Server, api::Api, config::EnvConfig, logging};
+use tracing_subscriber::{Layer, layer::SubscriberExt, util::SubscriberInitExt};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    tracing_subscriber::fmt()
+    dotenvy::dotenv().ok();
+    let level = std::env::var("LOG_LEVEL")
+        .ok()
+        .and_then(|l| l.parse().ok())
+        .unwrap_or(tracing::Level::INFO);
+
+    // Sources can log more than the global level, see litehook::logging
+    let fmt = tracing_subscriber::fmt::layer()
         .with_timer(tracing_subscriber::fmt::time::ChronoLocal::new(
             "%Y-%m-%d %H:%M:%S".to_string(),
         ))
-        .with_max_level(tracing::Level::INFO)
         .with_level(true)
         .with_target(false)
+        .with_filter(logging::filter(level));
+    tracing_subscriber::registry()
+        .with(logging::SpanLevels)
+        .with(fmt)
         .init();
 
     let server = std::sync::Arc::new(Server::new().await?);
 
     let shutdown_handle = tokio::spawn({
-        let shutdown_token = server.shutdown.clone();
+        let server = std::sync::Arc::clone(&server);
         async move {
-            handle_signal().await;
-            shutdown_token.cancel();
+            handle_signal(&server).await;
+            server.graceful_shutdown().await;
         }
     });
 
@@ -37,7 +48,7 @@ async fn main() -> anyhow::Result<()> {
     Ok(())
 }
 
-pub async fn handle_signal() {
+pub async fn handle_signal(server: &Server) {
     #[cfg(unix)]
     {
         use tokio::signal::unix::{SignalKind, signal};
@@ -45,10 +56,14 @@ pub async fn handle_signal() {
         let mut sigterm =
             signal(SignalKind::terminate()).expect("Failed to install SIGTERM handler");
         let mut sigint = signal(SignalKind::interrupt()).expect("Failed to install SIGINT handler");
+        let mut sighup = signal(SignalKind::hangup()).expect("Failed to install SIGHUP handler");
 
-        tokio::select! {
-            _ = sigterm.recv() => {},
-            _ = sigint.recv() => {},
+        loop {
+            tokio::select! {
+                _ = sigterm.recv() => break,
+                _ = sigint.recv() => break,
+                _ = sighup.recv() => reload_config(server).await,
+            }
         }
     }
 
@@ -61,3 +76,17 @@ pub async fn handle_signal() {
 
     tracing::info!("received shutdown signal");
 }
+
+/// Reload [EnvConfig] from the environment and `.env` file.
+pub async fn reload_config(server: &Server) {
+    tracing::info!("received SIGHUP, reloading config");
+
+    let res = match EnvConfig::reload_dotenv() {
+        Ok(env) => server.update_config(env).await,
+        Err(e) => Err(e),
+    };
+
+    if let Err(e) = res {
+        tracing::error!("failed to reload config: {e}");
+    }
+}
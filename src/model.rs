@@ -1,7 +1,10 @@
 use std::collections::HashMap;
 use std::sync::Arc;
 
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
 use sqlx::FromRow;
 use sqlx::types::Json;
 use tokio::sync::{Mutex, oneshot};
@@ -9,45 +12,229 @@ use tokio::sync::{Mutex, oneshot};
 pub type NtfMap = Arc<Mutex<HashMap<String, (Notification, Option<oneshot::Sender<String>>)>>>;
 
 /// Post reactions
+///
+/// Custom emoji reactions have no unicode `emoji`, only an `image` url.
 #[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
 pub struct PostReaction {
     pub emoji: Option<String>,
     pub count: Option<String>,
+    #[serde(default)]
+    pub image: Option<String>,
+}
+
+/// Kind of a media attachment
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MediaKind {
+    Photo,
+    /// Preview image of a video
+    VideoThumb,
+    Gif,
+    /// Voice message, the url is the audio file
+    Voice,
+    /// Round video message, the url is the video file
+    RoundVideo,
+    /// Audio file, the url links to the post
+    Audio,
+    /// Any other file, the url links to the post
+    Document,
+}
+
+impl MediaKind {
+    /// Check if the url of the media is an image.
+    pub fn is_image(self) -> bool {
+        matches!(self, Self::Photo | Self::VideoThumb | Self::Gif)
+    }
+}
+
+/// Media attachment of a post
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct Media {
+    pub url: String,
+    pub kind: MediaKind,
+    /// Duration as shown on the page, like `0:42`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration: Option<String>,
+    /// File name of documents, title of audio files
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filename: Option<String>,
+}
+
+/// Post this post replies to
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct Reply {
+    /// Id of the referenced post, like `channel/123`
+    pub id: String,
+    /// Beginning of the quoted text
+    pub text: Option<String>,
 }
 
 /// DB row for Post
 #[derive(FromRow)]
 pub struct PostRow {
     pub id: String,
+    pub url: Option<String>,
     pub author: String,
     pub text: String,
     pub media: Json<Option<Vec<String>>>,
+    pub media_items: Option<Json<Vec<Media>>>,
     pub reactions: Json<Option<Vec<PostReaction>>>,
     pub views: String,
     pub date: String,
+    pub service: Option<String>,
+    pub edited: bool,
+    pub sponsored: bool,
+    pub reply_to: Option<Json<Reply>>,
+    pub album_size: Option<u32>,
 }
 
 /// Post
 #[derive(Serialize, Clone, PartialEq, Debug, Default)]
 pub struct Post {
     pub id: String,
+    /// Permalink like `https://t.me/<channel>/<id>`
+    pub url: String,
+    /// Admin signature of signed posts, the channel name otherwise
     pub author: Option<String>,
     pub text: Option<String>,
+    /// Media urls, same order as `media_items`
     pub media: Option<Vec<String>>,
+    /// Media with their kind, only for scraped posts
+    pub media_items: Option<Vec<Media>>,
     pub reactions: Option<Vec<PostReaction>>,
     pub views: Option<String>,
+    pub views_count: Option<u64>,
     pub date: Option<String>,
+    /// Service message text, like "pinned a message"
+    pub service: Option<String>,
+    /// Post was edited after publishing
+    pub edited: bool,
+    /// Sponsored message (ad) inserted by Telegram
+    pub sponsored: bool,
+    /// Post this post replies to
+    pub reply_to: Option<Reply>,
+    /// Number of media in the album the post was merged from, the
+    /// position of a media in the album is its index in `media_items`
+    pub album_size: Option<u32>,
+}
+
+impl Post {
+    /// Relative view growth that counts as a stats change
+    const VIEWS_CHANGE_RATIO: f64 = 0.1;
+
+    /// Count of the most popular reaction.
+    pub fn top_reaction(&self) -> Option<u64> {
+        self.reactions
+            .as_ref()?
+            .iter()
+            .filter_map(|r| r.count.as_deref().and_then(parse_count))
+            .max()
+    }
+
+    /// Check if views grew by at least 10% or the top reaction count
+    /// changed since the `old` version of the post.
+    pub fn stats_changed(&self, old: &Post) -> bool {
+        let views_changed = match (old.views_count, self.views_count) {
+            (Some(old), Some(new)) => {
+                new.abs_diff(old) as f64 >= old.max(1) as f64 * Self::VIEWS_CHANGE_RATIO
+            }
+            (None, Some(_)) => true,
+            _ => false,
+        };
+
+        views_changed || self.top_reaction() != old.top_reaction()
+    }
+
+    /// Urls of the images in `media`, all of them for posts without
+    /// `media_items`.
+    pub fn image_urls(&self) -> Vec<&str> {
+        match &self.media_items {
+            Some(items) => items
+                .iter()
+                .filter(|m| m.kind.is_image())
+                .map(|m| m.url.as_str())
+                .collect(),
+            None => self.media.iter().flatten().map(String::as_str).collect(),
+        }
+    }
+
+    /// SHA-1 of the text and media, `None` for posts without both.
+    ///
+    /// Scraped media are hashed by kind, duration and file name, as t.me
+    /// serves the same file under a different url every time. Media of
+    /// the Telegram client are file ids and hashed as they are.
+    pub fn content_hash(&self) -> Option<String> {
+        let text = self.text.as_deref().unwrap_or_default();
+        let media = self.media.as_deref().unwrap_or_default();
+        let items = self.media_items.as_deref().unwrap_or_default();
+        if text.is_empty() && media.is_empty() && items.is_empty() {
+            return None;
+        }
+
+        let mut hasher = Sha1::new();
+        hasher.update(text);
+        if items.is_empty() {
+            for id in media {
+                hasher.update("\n");
+                hasher.update(id);
+            }
+        }
+        for item in items {
+            hasher.update(format!(
+                "\n{:?} {} {}",
+                item.kind,
+                item.duration.as_deref().unwrap_or_default(),
+                item.filename.as_deref().unwrap_or_default()
+            ));
+        }
+        Some(format!("{:x}", hasher.finalize()))
+    }
+
+    /// Channel of the post, the part of the id before the message number.
+    ///
+    /// Messages of the Telegram client are identified by their chat id.
+    pub fn channel(&self) -> &str {
+        self.id
+            .rsplit_once('/')
+            .map_or(self.id.as_str(), |(channel, _)| channel)
+    }
+
+    /// Message numbers of an album post, one per media starting at its id.
+    ///
+    /// The first number is the grouped id of the album, `None` for
+    /// posts that aren't albums.
+    pub fn album_ids(&self) -> Option<std::ops::Range<u64>> {
+        let size = self.album_size?;
+        let (_, num) = self.id.rsplit_once('/')?;
+        let start: u64 = num.parse().ok()?;
+        Some(start..start + u64::from(size.max(1)))
+    }
+
+    /// Publish date in UTC, `None` if it's missing or not RFC 3339.
+    ///
+    /// `date` is stored as scraped, so it's checked here before any
+    /// date based logic relies on it.
+    pub fn parsed_date(&self) -> Option<DateTime<Utc>> {
+        DateTime::parse_from_rfc3339(self.date.as_deref()?.trim())
+            .ok()
+            .map(|date| date.with_timezone(&Utc))
+    }
 }
 
 /// Channel counters for post
 ///
-/// Values are strings from channel's page counters (e.g. "1.8M", "1.2k")
-#[derive(Serialize, Debug)]
+/// Values are strings from channel's page counters (e.g. "1.8M", "1.2k"),
+/// `*_count` fields hold the same values parsed with [parse_count].
+#[derive(Serialize, Debug, Default)]
 pub struct ChannelCounters {
     pub subscribers: Option<String>,
+    pub subscribers_count: Option<u64>,
     pub photos: Option<String>,
+    pub photos_count: Option<u64>,
     pub videos: Option<String>,
+    pub videos_count: Option<u64>,
     pub links: Option<String>,
+    pub links_count: Option<u64>,
 }
 
 /// Channel
@@ -60,13 +247,89 @@ pub struct Channel {
     pub description: Option<String>,
 }
 
+/// Channel fields watched for changes
+///
+/// Counters are left out, they change on almost every poll.
+#[derive(Serialize, Debug, Clone, PartialEq, FromRow)]
+pub struct ChannelInfo {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub image: Option<String>,
+}
+
+impl From<&Channel> for ChannelInfo {
+    fn from(channel: &Channel) -> Self {
+        Self {
+            name: channel.name.clone(),
+            description: channel.description.clone(),
+            image: channel.image.clone(),
+        }
+    }
+}
+
+/// Reason a webhook was sent, lets receivers route payloads by type
+#[derive(Serialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEvent {
+    #[default]
+    NewPosts,
+    EditedPost,
+    StatsUpdate,
+    ChannelInfoChanged,
+}
+
 /// Webhook payload with channel and new posts
 #[derive(Serialize, Debug)]
 pub struct WebhookPayload<'a> {
+    pub event: WebhookEvent,
     pub channel: &'a Channel,
     pub new_posts: &'a [Post],
 }
 
+/// Engagement stats of a known post
+#[derive(Serialize, Debug)]
+pub struct PostStats<'a> {
+    pub id: &'a str,
+    pub views: Option<&'a str>,
+    pub views_count: Option<u64>,
+    pub reactions: Option<&'a [PostReaction]>,
+}
+
+impl<'a> From<&'a Post> for PostStats<'a> {
+    fn from(post: &'a Post) -> Self {
+        Self {
+            id: &post.id,
+            views: post.views.as_deref(),
+            views_count: post.views_count,
+            reactions: post.reactions.as_deref(),
+        }
+    }
+}
+
+/// Webhook payload with stats updates of known posts
+#[derive(Serialize, Debug)]
+pub struct StatsPayload<'a> {
+    pub event: WebhookEvent,
+    pub channel: &'a str,
+    pub updated_posts: Vec<PostStats<'a>>,
+}
+
+/// Webhook payload with the previous and current channel info
+#[derive(Serialize, Debug)]
+pub struct ChannelChangedPayload<'a> {
+    pub event: WebhookEvent,
+    pub channel: &'a str,
+    pub old: &'a ChannelInfo,
+    pub new: &'a ChannelInfo,
+}
+
+/// New post event for live subscribers
+#[derive(Serialize, Clone, Debug)]
+pub struct PostEvent {
+    pub channel: String,
+    pub post: Post,
+}
+
 /// Parsed page with channel and posts
 #[derive(Serialize, Debug)]
 pub struct Page {
@@ -83,23 +346,224 @@ pub struct Notification {
 }
 
 /// Health check result
-#[derive(Serialize)]
+#[derive(Serialize, JsonSchema)]
 pub struct Health {
     pub ok: bool,
     pub sources: usize,
+    /// Webhook targets paused by the circuit breaker
+    pub open_webhooks: usize,
+}
+
+/// Build of the running server
+#[derive(Serialize, Debug, JsonSchema)]
+pub struct Version {
+    pub name: String,
+    pub version: String,
+    /// Commit the binary was built from, set with `GIT_SHA` at build time
+    pub git_sha: Option<String>,
+    /// Seconds since the server was created
+    pub uptime_secs: u64,
+}
+
+/// Database size before and after a `VACUUM`
+#[derive(Serialize, Debug, JsonSchema)]
+pub struct VacuumResult {
+    pub size_before: i64,
+    pub size_after: i64,
+    pub freed: i64,
+}
+
+/// Result of a test webhook sent to one url
+#[derive(Serialize, Debug, JsonSchema)]
+pub struct WebhookTest {
+    pub url: String,
+    /// HTTP status of the response, `None` if the request failed
+    pub status: Option<u16>,
+    /// Beginning of the response body
+    pub body: Option<String>,
+    /// Why the request failed, like a timeout or a refused connection
+    pub error: Option<String>,
 }
 
 /// Convert PostRow to Post
 impl From<PostRow> for Post {
     fn from(row: PostRow) -> Self {
         Self {
+            // Rows stored by older versions have no url
+            url: row.url.unwrap_or_else(|| post_url(&row.id)),
             id: row.id,
             author: Some(row.author),
             text: Some(row.text),
             media: row.media.0,
+            media_items: row.media_items.map(|m| m.0),
             reactions: row.reactions.0,
+            views_count: parse_count(&row.views),
             views: Some(row.views),
             date: Some(row.date),
+            service: row.service,
+            edited: row.edited,
+            sponsored: row.sponsored,
+            reply_to: row.reply_to.map(|r| r.0),
+            album_size: row.album_size,
         }
     }
 }
+
+/// Build permalink of a post from its `channel/123` id.
+pub fn post_url(id: &str) -> String {
+    format!("https://t.me/{id}")
+}
+
+/// Parse a counter string like "1.8M", "1.2k" or "1 234" into a number.
+///
+/// Supports K/M/B suffixes, decimals and thousands separators.
+/// A comma is treated as a decimal separator only when followed by a suffix,
+/// e.g. "1,5K" is 1500 while "1,234" is 1234.
+pub fn parse_count(s: &str) -> Option<u64> {
+    let s: String = s.chars().filter(|c| !c.is_whitespace()).collect();
+
+    let (num, mult) = match s.chars().last()?.to_ascii_uppercase() {
+        'K' => (&s[..s.len() - 1], 1e3),
+        'M' => (&s[..s.len() - 1], 1e6),
+        'B' => (&s[..s.len() - 1], 1e9),
+        _ => (s.as_str(), 1.0),
+    };
+
+    let num = if mult > 1.0 && !num.contains('.') {
+        num.replace(',', ".")
+    } else {
+        num.replace(',', "")
+    };
+
+    let value: f64 = num.parse().ok()?;
+    if !value.is_finite() || value < 0.0 {
+        return None;
+    }
+
+    Some((value * mult).round() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_count() {
+        assert_eq!(parse_count("1 234"), Some(1234));
+        assert_eq!(parse_count("1.2k"), Some(1200));
+        assert_eq!(parse_count("999"), Some(999));
+        assert_eq!(parse_count("1.8M"), Some(1_800_000));
+        assert_eq!(parse_count("1,234"), Some(1234));
+        assert_eq!(parse_count("1,5K"), Some(1500));
+    }
+
+    #[test]
+    fn test_parse_count_invalid() {
+        assert_eq!(parse_count(""), None);
+        assert_eq!(parse_count("K"), None);
+        assert_eq!(parse_count("abc"), None);
+        assert_eq!(parse_count("-5"), None);
+    }
+
+    #[test]
+    fn test_parsed_date() {
+        let post = |date: Option<&str>| Post {
+            date: date.map(str::to_string),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            post(Some("2026-03-04T15:00:00+03:00")).parsed_date(),
+            Some("2026-03-04T12:00:00Z".parse().unwrap())
+        );
+        assert_eq!(post(Some("")).parsed_date(), None);
+        assert_eq!(post(Some("yesterday")).parsed_date(), None);
+        assert_eq!(post(None).parsed_date(), None);
+    }
+
+    #[test]
+    fn test_content_hash() {
+        let post = |url: &str, kind: MediaKind| Post {
+            text: Some("Hello".to_string()),
+            media: Some(vec![url.to_string()]),
+            media_items: Some(vec![Media {
+                url: url.to_string(),
+                kind,
+                duration: None,
+                filename: None,
+            }]),
+            ..Default::default()
+        };
+
+        let hash = post("https://cdn4.telesco.pe/file/a.jpg", MediaKind::Photo).content_hash();
+        assert!(hash.is_some());
+        assert_eq!(
+            post("https://cdn5.telesco.pe/file/b.jpg", MediaKind::Photo).content_hash(),
+            hash
+        );
+        assert_ne!(
+            post("https://cdn4.telesco.pe/file/a.jpg", MediaKind::Gif).content_hash(),
+            hash
+        );
+        assert_eq!(Post::default().content_hash(), None);
+    }
+
+    #[test]
+    fn test_post_channel() {
+        let post = |id: &str| Post {
+            id: id.to_string(),
+            ..Default::default()
+        };
+
+        assert_eq!(post("durov/42").channel(), "durov");
+        assert_eq!(post("-100123").channel(), "-100123");
+    }
+
+    #[test]
+    fn test_album_ids() {
+        let post = Post {
+            id: "durov/42".to_string(),
+            album_size: Some(3),
+            ..Default::default()
+        };
+
+        assert_eq!(post.album_ids(), Some(42..45));
+        assert_eq!(
+            Post {
+                album_size: None,
+                ..post
+            }
+            .album_ids(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_stats_changed() {
+        let old = Post {
+            views_count: Some(1000),
+            ..Default::default()
+        };
+        let small = Post {
+            views_count: Some(1050),
+            ..Default::default()
+        };
+        let large = Post {
+            views_count: Some(1100),
+            ..Default::default()
+        };
+        let reacted = Post {
+            views_count: Some(1000),
+            reactions: Some(vec![PostReaction {
+                emoji: Some("👍".to_string()),
+                count: Some("3".to_string()),
+                image: None,
+            }]),
+            ..Default::default()
+        };
+
+        assert!(!small.stats_changed(&old));
+        assert!(large.stats_changed(&old));
+        assert!(reacted.stats_changed(&old));
+    }
+}
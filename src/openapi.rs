@@ -0,0 +1,312 @@
+//! OpenAPI description of the [crate::api] endpoints
+//!
+//! Paths are written by hand, schemas are generated from the serde
+//! types with [schemars], so they follow the actual request and
+//! response shapes.
+
+use schemars::r#gen::SchemaSettings;
+use serde_json::{Map, Value, json};
+
+use crate::config::EnvConfig;
+use crate::model::{Health, VacuumResult, Version, WebhookTest};
+use crate::sources::registry::SourceRegistration;
+use crate::sources::{BulkResult, SourceConfig, SourceDebug, SourceInfo, SourceStats};
+use crate::webhook::breaker::TargetStatus;
+
+/// Build OpenAPI 3.0 document.
+pub fn spec() -> Value {
+    let mut generator = SchemaSettings::openapi3().into_generator();
+    generator.subschema_for::<SourceConfig>();
+    generator.subschema_for::<SourceInfo>();
+    generator.subschema_for::<SourceStats>();
+    generator.subschema_for::<BulkResult>();
+    generator.subschema_for::<SourceDebug>();
+    generator.subschema_for::<EnvConfig>();
+    generator.subschema_for::<Health>();
+    generator.subschema_for::<TargetStatus>();
+    generator.subschema_for::<WebhookTest>();
+    generator.subschema_for::<VacuumResult>();
+    generator.subschema_for::<Version>();
+
+    let mut schemas = Map::new();
+    for (name, schema) in generator.take_definitions() {
+        schemas.insert(name, json!(schema));
+    }
+
+    // Source kinds use draft-07 refs, move them under components
+    let mut kinds = Vec::new();
+    for reg in inventory::iter::<SourceRegistration>() {
+        let root = serde_json::to_string(&(reg.fields)())
+            .unwrap_or_default()
+            .replace("#/definitions/", "#/components/schemas/");
+        let mut root: Value = serde_json::from_str(&root).unwrap_or_default();
+
+        if let Some(Value::Object(defs)) =
+            root.as_object_mut().and_then(|r| r.remove("definitions"))
+        {
+            schemas.extend(defs);
+        }
+        if let Some(r) = root.as_object_mut() {
+            r.remove("$schema");
+        }
+        schemas.insert(reg.kind.to_string(), root);
+        kinds.push(json!({ "$ref": format!("#/components/schemas/{}", reg.kind) }));
+    }
+
+    // `raw` is one of the registered source configs
+    for name in ["SourceConfig", "SourceInfo"] {
+        schemas[name]["properties"]["raw"] = json!({ "oneOf": kinds });
+    }
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": env!("CARGO_PKG_NAME"),
+            "description": env!("CARGO_PKG_DESCRIPTION"),
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "security": [{ "bearer": [] }],
+        "paths": paths(),
+        "components": {
+            "schemas": schemas,
+            "securitySchemes": {
+                "bearer": { "type": "http", "scheme": "bearer" },
+            },
+        },
+    })
+}
+
+fn schema_ref(name: &str) -> Value {
+    json!({ "$ref": format!("#/components/schemas/{name}") })
+}
+
+fn json_body(schema: Value) -> Value {
+    json!({ "content": { "application/json": { "schema": schema } } })
+}
+
+fn response(description: &str, schema: Option<Value>) -> Value {
+    let mut res = json!({ "description": description });
+    if let Some(schema) = schema {
+        res["content"] = json_body(schema)["content"].clone();
+    }
+    res
+}
+
+fn paths() -> Value {
+    let id_param = json!({
+        "name": "id",
+        "in": "path",
+        "required": true,
+        "schema": { "type": "string" },
+    });
+    let ok = response("Success", None);
+
+    json!({
+        "/sources": {
+            "get": {
+                "summary": "List all sources with their state and runtime status",
+                "responses": {
+                    "200": response("Sources", Some(json!({ "type": "array", "items": schema_ref("SourceInfo") }))),
+                },
+            },
+            "post": {
+                "summary": "Create a source",
+                "requestBody": json_body(schema_ref("SourceConfig")),
+                "responses": {
+                    "201": response("Stored source with defaults applied, its url is in the Location header", Some(schema_ref("SourceInfo"))),
+                    "400": response("Invalid config", None),
+                    "409": response("Source already exists or the id is used by another channel", None),
+                    "502": response("Source could not be started", None),
+                },
+            },
+        },
+        "/sources/bulk": {
+            "post": {
+                "summary": "Create many sources, the valid ones are probed and then stored in one transaction",
+                "requestBody": json_body(json!({ "type": "array", "items": schema_ref("SourceConfig") })),
+                "responses": {
+                    "200": response("Result of each source in request order", Some(json!({ "type": "array", "items": schema_ref("BulkResult") }))),
+                    "500": response("Sources could not be stored, none were added", None),
+                },
+            },
+        },
+        "/sources/types": {
+            "get": {
+                "summary": "List registered source kinds with their config schema",
+                "responses": { "200": response("Source kinds", Some(json!({ "type": "array", "items": { "type": "object" } }))) },
+            },
+        },
+        "/sources/{id}": {
+            "parameters": [id_param],
+            "get": {
+                "summary": "Get a source",
+                "responses": {
+                    "200": response("Source", Some(schema_ref("SourceInfo"))),
+                    "404": response("Source not found", None),
+                },
+            },
+            "put": {
+                "summary": "Update and restart a source",
+                "requestBody": json_body(schema_ref("SourceConfig")),
+                "responses": {
+                    "200": response("Stored source with defaults applied", Some(schema_ref("SourceInfo"))),
+                    "400": response("Invalid config", None),
+                    "502": response("Source could not be started", None),
+                },
+            },
+            "delete": {
+                "summary": "Stop and delete a source",
+                "responses": { "200": ok },
+            },
+        },
+        "/sources/{id}/resume": {
+            "parameters": [id_param],
+            "post": {
+                "summary": "Restart a source that was stopped because of an error",
+                "responses": { "200": ok },
+            },
+        },
+        "/sources/{id}/restart": {
+            "parameters": [id_param],
+            "post": {
+                "summary": "Restart a running source with a new HTTP client and proxy, keeping its stored posts",
+                "responses": {
+                    "200": ok,
+                    "404": response("Source not found", None),
+                    "409": response("Source is not running", None),
+                    "500": response("Source could not be started", None),
+                },
+            },
+        },
+        "/sources/{id}/backfill": {
+            "parameters": [id_param, {
+                "name": "pages",
+                "in": "query",
+                "schema": { "type": "integer", "minimum": 0, "default": 5 },
+            }],
+            "post": {
+                "summary": "Import older posts without sending webhooks",
+                "responses": { "200": response("Number of imported posts", Some(json!({ "type": "integer" }))) },
+            },
+        },
+        "/sources/{id}/stats": {
+            "parameters": [id_param],
+            "get": {
+                "summary": "Number of stored posts and the newest post date",
+                "responses": {
+                    "200": response("Stats", Some(schema_ref("SourceStats"))),
+                    "404": response("Source not found", None),
+                },
+            },
+        },
+        "/sources/{id}/test-webhook": {
+            "parameters": [id_param],
+            "post": {
+                "summary": "Send a sample post to the webhook urls of a running source",
+                "responses": {
+                    "200": response("Response of each webhook url", Some(json!({ "type": "array", "items": schema_ref("WebhookTest") }))),
+                    "404": response("Source is not running", None),
+                },
+            },
+        },
+        "/sources/{id}/debug": {
+            "parameters": [id_param, {
+                "name": "html",
+                "in": "query",
+                "schema": { "type": "boolean", "default": false },
+            }],
+            "get": {
+                "summary": "Last page fetched by a running source with debug_capture, optionally with the HTML of its first post",
+                "responses": {
+                    "200": response("Captured page", Some(schema_ref("SourceDebug"))),
+                    "400": response("debug_capture is disabled or no page was fetched yet", None),
+                    "404": response("Source is not running", None),
+                },
+            },
+        },
+        "/config": {
+            "get": {
+                "summary": "Get the global config, secrets are omitted",
+                "responses": { "200": response("Config", Some(schema_ref("EnvConfig"))) },
+            },
+            "put": {
+                "summary": "Replace the global config",
+                "requestBody": json_body(schema_ref("EnvConfig")),
+                "responses": { "200": ok, "400": response("Invalid config", None) },
+            },
+        },
+        "/webhooks": {
+            "get": {
+                "summary": "Circuit breaker status of failing webhook targets",
+                "responses": { "200": response("Targets", Some(json!({ "type": "array", "items": schema_ref("TargetStatus") }))) },
+            },
+        },
+        "/search": {
+            "parameters": [
+                { "name": "q", "in": "query", "required": true, "schema": { "type": "string" } },
+                { "name": "limit", "in": "query", "schema": { "type": "integer", "minimum": 0, "maximum": 100, "default": 20 } },
+            ],
+            "get": {
+                "summary": "Search stored posts by text, best matches first",
+                "responses": { "200": response("Matching posts", Some(json!({ "type": "array", "items": { "type": "object" } }))) },
+            },
+        },
+        "/admin/vacuum": {
+            "post": {
+                "summary": "Compact the database file, writes pause until it's done",
+                "responses": {
+                    "200": response("Database size in bytes before and after", Some(schema_ref("VacuumResult"))),
+                    "500": response("Vacuum failed", None),
+                },
+            },
+        },
+        "/health": {
+            "get": {
+                "summary": "Health check",
+                "security": [],
+                "responses": { "200": response("Health", Some(schema_ref("Health"))) },
+            },
+        },
+        "/version": {
+            "get": {
+                "summary": "Version and git commit of the build with the uptime",
+                "security": [],
+                "responses": { "200": response("Version", Some(schema_ref("Version"))) },
+            },
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn collect_refs<'a>(value: &'a Value, refs: &mut Vec<&'a str>) {
+        match value {
+            Value::Object(map) => {
+                if let Some(Value::String(r)) = map.get("$ref") {
+                    refs.push(r);
+                }
+                map.values().for_each(|v| collect_refs(v, refs));
+            }
+            Value::Array(items) => items.iter().for_each(|v| collect_refs(v, refs)),
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn test_refs_resolve() {
+        let spec = spec();
+        let mut refs = Vec::new();
+        collect_refs(&spec, &mut refs);
+
+        assert!(!refs.is_empty());
+        for r in refs {
+            let name = r.trim_start_matches("#/components/schemas/");
+            assert!(
+                spec["components"]["schemas"].get(name).is_some(),
+                "unresolved {r}"
+            );
+        }
+    }
+}
@@ -0,0 +1,149 @@
+//! HTTP clients shared by sources
+//!
+//! With `share_clients` enabled, sources with the same [ClientOptions]
+//! use one client, so they share a connection pool and proxy instead
+//! of each picking their own. Requests through a shared client can be
+//! limited with `fetch_rate_limit`. Proxies are picked by the
+//! [ProxySelector] of the pool, shared or not.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+use super::proxy::ProxySelector;
+use super::{ClientOptions, create_client, get_proxy_list};
+use crate::config::EnvHandle;
+use crate::webhook::rate_limit::RateLimiter;
+
+/// [ClientOptions] that produce the same client
+type ClientKey = (Option<String>, Vec<(String, String)>, u64);
+
+/// Client of a source with the rate limit of its pool, if shared
+#[derive(Debug, Clone)]
+pub struct PooledClient {
+    pub client: reqwest::Client,
+    limiter: Option<Arc<RateLimiter>>,
+    /// Proxy list entry the client uses
+    proxy: Option<String>,
+}
+
+impl PooledClient {
+    /// Wait until the shared rate limit allows another request.
+    pub async fn acquire(&self) {
+        if let Some(limiter) = &self.limiter {
+            limiter.acquire().await;
+        }
+    }
+}
+
+/// Shared clients managed by the [crate::Server]
+#[derive(Debug, Default)]
+pub struct ClientPool {
+    clients: Mutex<HashMap<ClientKey, PooledClient>>,
+    proxies: ProxySelector,
+    env: EnvHandle,
+}
+
+impl ClientPool {
+    pub fn new(env: EnvHandle) -> Self {
+        Self {
+            clients: Mutex::default(),
+            proxies: ProxySelector::default(),
+            env,
+        }
+    }
+
+    /// Check if a source with `opts` uses a shared client.
+    pub fn is_shared(&self, opts: &ClientOptions) -> bool {
+        self.env.get().share_clients && !opts.dedicated
+    }
+
+    /// Client for `opts`, the shared one if sharing is enabled.
+    pub async fn get(&self, opts: &ClientOptions) -> anyhow::Result<PooledClient> {
+        if !self.is_shared(opts) {
+            return self.create(opts, false).await;
+        }
+
+        let mut clients = self.clients.lock().await;
+        if let Some(client) = clients.get(&self.key(opts)) {
+            return Ok(client.clone());
+        }
+        let client = self.create(opts, true).await?;
+        clients.insert(self.key(opts), client.clone());
+        Ok(client)
+    }
+
+    /// New client for `opts`, replacing the shared one, like after
+    /// network errors to pick another proxy. The rate limit is kept.
+    pub async fn renew(&self, opts: &ClientOptions) -> anyhow::Result<PooledClient> {
+        if !self.is_shared(opts) {
+            return self.create(opts, false).await;
+        }
+
+        let mut clients = self.clients.lock().await;
+        let mut client = self.create(opts, true).await?;
+        if let Some(old) = clients.get(&self.key(opts)) {
+            client.limiter = old.limiter.clone();
+        }
+        clients.insert(self.key(opts), client.clone());
+        Ok(client)
+    }
+
+    /// Skip the proxy of a client after its requests failed, so the
+    /// next [ClientPool::renew] picks another one.
+    pub fn proxy_failed(&self, client: &PooledClient) {
+        if let Some(proxy) = &client.proxy {
+            self.proxies.mark_failed(proxy);
+        }
+    }
+
+    async fn create(&self, opts: &ClientOptions, shared: bool) -> anyhow::Result<PooledClient> {
+        let env = self.env.get();
+        let limiter = env
+            .fetch_rate_limit
+            .filter(|_| shared)
+            .map(|limit| Arc::new(RateLimiter::new(limit)));
+        let proxy = match &env.proxy_list_url {
+            Some(url) => Some(
+                self.proxies
+                    .pick(env.proxy_strategy, &get_proxy_list(url).await?)
+                    .ok_or_else(|| anyhow::anyhow!("failed to fetch proxy"))?,
+            ),
+            None => None,
+        };
+        Ok(PooledClient {
+            client: create_client(opts, opts.timeout(&env), proxy.as_deref())?,
+            limiter,
+            proxy,
+        })
+    }
+
+    fn key(&self, opts: &ClientOptions) -> ClientKey {
+        let mut headers: Vec<_> = opts
+            .headers
+            .iter()
+            .map(|(name, value)| (name.to_lowercase(), value.clone()))
+            .collect();
+        headers.sort();
+        let timeout = opts.timeout(&self.env.get()).as_secs();
+        (opts.user_agent.clone(), headers, timeout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key() {
+        let opts = |name: &str| ClientOptions {
+            headers: HashMap::from([(name.to_string(), "1".to_string())]),
+            timeout: Some(10),
+            ..Default::default()
+        };
+
+        let pool = ClientPool::default();
+        assert_eq!(pool.key(&opts("X-A")), pool.key(&opts("x-a")));
+        assert_ne!(pool.key(&opts("x-a")), pool.key(&opts("x-b")));
+    }
+}
@@ -1,34 +1,191 @@
-use rand::prelude::IndexedRandom;
-use serde::{Deserialize, Deserializer, Serialize};
+use chrono::{DateTime, Utc};
+use reqwest::header::{
+    ACCEPT_ENCODING, CONTENT_ENCODING, HeaderMap, HeaderName, HeaderValue, REFERER,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use sqlx::FromRow;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Read;
+use tokio::time::Duration;
 
-use crate::config;
+use crate::config::EnvConfig;
+use crate::model::WebhookTest;
+use crate::webhook::latency::LatencySummary;
 
+pub mod client_pool;
+pub mod proxy;
 pub mod registry;
 pub mod telegram;
 
-#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, JsonSchema)]
 pub struct SourceConfig {
     pub id: String,
     pub kind: String,
     pub raw: serde_json::Value,
+
+    /// ISO8601 timestamps managed by the database
+    #[serde(skip_deserializing)]
+    pub created_at: Option<String>,
+    #[serde(skip_deserializing)]
+    pub updated_at: Option<String>,
+
+    /// Inactive sources are not started until resumed
+    #[serde(skip_deserializing, default = "default_active")]
+    pub active: bool,
+    /// Reason why the source was stopped
+    #[serde(skip_deserializing)]
+    pub error: Option<String>,
+}
+
+impl SourceConfig {
+    /// Channel the source reads from, if its kind has one
+    pub fn target(&self) -> Option<&str> {
+        self.raw.get("channel_url").and_then(|v| v.as_str())
+    }
+
+    /// Log level of the source, if its kind has one, see [crate::logging]
+    pub fn log_level(&self) -> Option<&str> {
+        self.raw.get("log_level").and_then(|v| v.as_str())
+    }
+
+    /// Channel name used in post ids, the last segment of [SourceConfig::target]
+    pub fn channel(&self) -> Option<&str> {
+        self.target()?.trim_end_matches('/').rsplit('/').next()
+    }
+}
+
+fn default_active() -> bool {
+    true
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct SourceInfo {
     pub id: String,
     pub kind: String,
     pub raw: serde_json::Value,
     pub active: bool,
+    pub created_at: Option<String>,
+    pub updated_at: Option<String>,
+    pub error: Option<String>,
+    /// Webhooks are logged instead of sent
+    pub dry_run: bool,
+    pub state: SourceState,
+
+    /// Runtime status, only present for running sources that track it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<SourceStatus>,
+}
+
+/// Whether a source is running and its polls succeed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SourceState {
+    Running,
+    /// Running, but its last polls failed and are retried
+    Failing,
+    /// Not running, like after an error until it's resumed
+    #[default]
+    Stopped,
+}
+
+impl SourceState {
+    /// State of a running source from its [SourceStatus].
+    pub fn of_running(status: Option<&SourceStatus>) -> Self {
+        match status {
+            Some(status) if status.consecutive_failures > 0 => Self::Failing,
+            _ => Self::Running,
+        }
+    }
+}
+
+/// Outcome of one config passed to [crate::Server::add_sources]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct BulkResult {
+    pub id: String,
+    /// Source was stored and started
+    pub ok: bool,
+    /// Reason the source was skipped
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl BulkResult {
+    pub fn ok(id: &str) -> Self {
+        Self {
+            id: id.to_string(),
+            ok: true,
+            error: None,
+        }
+    }
+
+    pub fn error(id: &str, e: anyhow::Error) -> Self {
+        Self {
+            id: id.to_string(),
+            ok: false,
+            error: Some(e.to_string()),
+        }
+    }
+}
+
+/// Runtime status of a running [Source]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct SourceStatus {
+    /// Last time the source attempted to poll
+    pub last_polled_at: Option<DateTime<Utc>>,
+    /// Last time a poll completed successfully
+    pub last_success_at: Option<DateTime<Utc>>,
+    /// Polls that failed or found no channel since the last successful one
+    #[serde(default)]
+    pub consecutive_failures: u32,
+    /// Latency of recent webhook deliveries
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub webhook_latency: Option<LatencySummary>,
+}
+
+/// Stored posts of the channel a source reads from
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SourceStats {
+    pub posts: i64,
+    /// Date of the newest stored post
+    pub latest_post_date: Option<String>,
+}
+
+/// Last page fetched by a source with `debug_capture` enabled
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SourceDebug {
+    pub url: String,
+    pub fetched_at: DateTime<Utc>,
+    /// Posts parsed from the page, before any filters
+    pub post_count: usize,
+    /// Raw HTML of the first post on the page, only when requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first_post_html: Option<String>,
+}
+
+impl SourceInfo {
+    /// Add the state and status of the running `source`.
+    pub fn with_runtime(&mut self, source: &(dyn Source + Send)) {
+        self.active = true;
+        self.status = source.status();
+        self.state = SourceState::of_running(self.status.as_ref());
+    }
 }
 
 impl From<SourceConfig> for SourceInfo {
     fn from(cfg: SourceConfig) -> Self {
         Self {
+            dry_run: cfg.raw["dry_run"].as_bool().unwrap_or(false),
             id: cfg.id,
             kind: cfg.kind,
             raw: cfg.raw,
             active: false,
+            created_at: cfg.created_at,
+            updated_at: cfg.updated_at,
+            error: cfg.error,
+            state: SourceState::Stopped,
+            status: None,
         }
     }
 }
@@ -49,51 +206,264 @@ pub trait Source: Send + Sync {
     async fn stop(&self) -> anyhow::Result<()> {
         Ok(())
     }
+
+    /// Import up to `pages` pages of older posts without sending webhooks,
+    /// returns the number of imported posts
+    async fn backfill(&self, _pages: u32) -> anyhow::Result<usize> {
+        anyhow::bail!("{} source doesn't support backfill", self.name())
+    }
+
+    /// Check that the target of a new source exists before it's stored,
+    /// temporary failures should not fail the check
+    async fn probe(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Last fetched page, with the HTML of its first post if `html` is set
+    async fn debug(&self, _html: bool) -> anyhow::Result<SourceDebug> {
+        anyhow::bail!("{} source doesn't support debug capture", self.name())
+    }
+
+    /// Webhook urls the source delivers to
+    async fn webhook_urls(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Send a sample post to the webhook urls of the source
+    async fn test_webhook(&self) -> anyhow::Result<Vec<WebhookTest>> {
+        anyhow::bail!("{} source doesn't support webhook tests", self.name())
+    }
+
+    /// Delay the first poll by a random amount, called for the sources
+    /// started with the server before they run
+    fn jitter_first_poll(&self) {}
+
+    /// Poll right away instead of waiting for the next interval
+    fn poll_now(&self) -> anyhow::Result<()> {
+        anyhow::bail!("{} source doesn't support polling", self.name())
+    }
+
+    /// Runtime status, `None` if the source doesn't track it
+    fn status(&self) -> Option<SourceStatus> {
+        None
+    }
 }
 
-/// Fetch SOCKS5 proxy list, and create proxy config
-async fn get_proxy(proxy_list_url: &str) -> anyhow::Result<String> {
+/// Fetch proxy list, one entry per line
+async fn get_proxy_list(proxy_list_url: &str) -> anyhow::Result<Vec<String>> {
     let res = reqwest::Client::new()
         .get(proxy_list_url)
         .send()
         .await?
         .text()
         .await?;
-    let mut rng = rand::rng();
-    let proxy_addr: Vec<&str> = res
+    Ok(res
         .lines()
         .map(|s| s.trim())
         .filter(|s| !s.is_empty())
-        .collect();
-    let proxy_addr = proxy_addr
-        .choose(&mut rng)
-        .ok_or_else(|| anyhow::anyhow!("failed to fetch proxy"))?;
-    Ok(proxy_addr.to_string())
+        .map(String::from)
+        .collect())
+}
+
+/// Parse proxy list entry like `[scheme://][user:pass@]host:port`.
+///
+/// Entries without a scheme are SOCKS5 proxies, credentials
+/// in the url are used to authenticate with the proxy.
+fn proxy_url(entry: &str) -> anyhow::Result<url::Url> {
+    let url = if entry.contains("://") {
+        url::Url::parse(entry)?
+    } else {
+        url::Url::parse(&format!("socks5h://{entry}"))?
+    };
+
+    match url.scheme() {
+        "http" | "https" | "socks5" | "socks5h" => Ok(url),
+        scheme => anyhow::bail!("unsupported proxy scheme: {scheme}"),
+    }
 }
 
-/// Create web client
-async fn create_client() -> anyhow::Result<reqwest::Client> {
+/// Options for [create_client]
+#[derive(Debug, Clone, Default)]
+pub struct ClientOptions {
+    /// Overrides the default `litehook/<version>` user agent
+    pub user_agent: Option<String>,
+    /// Additional headers sent with every request
+    pub headers: HashMap<String, String>,
+    /// Overrides the global `request_timeout` in seconds
+    pub timeout: Option<u64>,
+    /// Never share the client with other sources, see [client_pool]
+    pub dedicated: bool,
+}
+
+impl ClientOptions {
+    /// Build [HeaderMap] from headers, fails on malformed names or values.
+    pub fn header_map(&self) -> anyhow::Result<HeaderMap> {
+        let mut map = HeaderMap::new();
+        for (name, value) in &self.headers {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| anyhow::anyhow!("invalid header name: {name}"))?;
+            let value = HeaderValue::from_str(value)
+                .map_err(|_| anyhow::anyhow!("invalid value for header {name}"))?;
+            map.insert(name, value);
+        }
+        Ok(map)
+    }
+
+    /// Request timeout, falls back to the global `request_timeout`.
+    pub fn timeout(&self, env: &EnvConfig) -> Duration {
+        Duration::from_secs(self.timeout.unwrap_or(env.request_timeout))
+    }
+
+    /// Check that user agent, headers and timeout are valid.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.timeout == Some(0) {
+            anyhow::bail!("request timeout must be greater than 0");
+        }
+        if let Some(ua) = &self.user_agent {
+            HeaderValue::from_str(ua).map_err(|_| anyhow::anyhow!("invalid user agent: {ua}"))?;
+        }
+        self.header_map()?;
+        Ok(())
+    }
+}
+
+/// Create web client with `timeout`, using `proxy` if set
+fn create_client(
+    opts: &ClientOptions,
+    timeout: Duration,
+    proxy: Option<&str>,
+) -> anyhow::Result<reqwest::Client> {
+    let user_agent = opts.user_agent.clone().unwrap_or(format!(
+        "{}/{}",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION")
+    ));
     let mut builder = reqwest::Client::builder()
-        .timeout(tokio::time::Duration::from_secs(30))
-        .user_agent(format!(
-            "{}/{}",
-            env!("CARGO_PKG_NAME"),
-            env!("CARGO_PKG_VERSION")
-        ));
+        .timeout(timeout)
+        .user_agent(user_agent)
+        .default_headers(opts.header_map()?);
 
     // Configure proxy
-    if let Some(url) = &config::get_env().proxy_list_url {
-        let addr = get_proxy(url).await?;
-        tracing::info!("using proxy address {}", addr);
-        builder = builder.proxy(reqwest::Proxy::all(format!("socks5h://{}", addr))?);
+    if let Some(entry) = proxy {
+        let proxy = proxy_url(entry)?;
+        tracing::info!(
+            "using proxy address {}://{}:{}",
+            proxy.scheme(),
+            proxy.host_str().unwrap_or_default(),
+            proxy.port_or_known_default().unwrap_or_default()
+        );
+        builder = builder.proxy(reqwest::Proxy::all(proxy)?);
     };
 
     Ok(builder.build()?)
 }
 
+/// Errors returned by [fetch_url]
+#[derive(Debug)]
+pub enum FetchError {
+    /// HTTP 429, with the delay from `Retry-After` header if present
+    RateLimited(Option<Duration>),
+    /// Any other unsuccessful HTTP status
+    Status(reqwest::StatusCode),
+    /// Network or body decoding error
+    Request(reqwest::Error),
+    /// Compressed body could not be decompressed
+    Decode(std::io::Error),
+}
+
+impl fmt::Display for FetchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::RateLimited(Some(d)) => write!(f, "rate limited, retry after {}s", d.as_secs()),
+            Self::RateLimited(None) => write!(f, "rate limited"),
+            Self::Status(status) => write!(f, "unexpected status {status}"),
+            Self::Request(e) => write!(f, "request failed: {e}"),
+            Self::Decode(e) => write!(f, "failed to decompress body: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for FetchError {}
+
+impl From<reqwest::Error> for FetchError {
+    fn from(e: reqwest::Error) -> Self {
+        Self::Request(e)
+    }
+}
+
 /// Helper for fetching URL
-pub async fn fetch_url(client: &reqwest::Client, url: &str) -> anyhow::Result<String> {
-    Ok(client.get(url).send().await?.text().await?)
+///
+/// Requests a gzip or deflate compressed body and decompresses it.
+pub async fn fetch_url(client: &reqwest::Client, url: &str) -> Result<String, FetchError> {
+    fetch_page(client, url).await.map(|(_, body)| body)
+}
+
+/// Same as [fetch_url], but also returns the final url after redirects.
+pub async fn fetch_page(
+    client: &reqwest::Client,
+    url: &str,
+) -> Result<(url::Url, String), FetchError> {
+    send_page(client.get(url)).await
+}
+
+/// Same as [fetch_page], with a `Referer` like a browser following a link.
+pub async fn fetch_page_from(
+    client: &reqwest::Client,
+    url: &str,
+    referer: &str,
+) -> Result<(url::Url, String), FetchError> {
+    send_page(client.get(url).header(REFERER, referer)).await
+}
+
+async fn send_page(req: reqwest::RequestBuilder) -> Result<(url::Url, String), FetchError> {
+    let res = req.header(ACCEPT_ENCODING, "gzip, deflate").send().await?;
+
+    if res.status() == reqwest::StatusCode::TOO_MANY_REQUESTS {
+        let retry_after = res
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.trim().parse().ok())
+            .map(Duration::from_secs);
+        return Err(FetchError::RateLimited(retry_after));
+    }
+    if !res.status().is_success() {
+        return Err(FetchError::Status(res.status()));
+    }
+
+    let final_url = res.url().clone();
+    let encoding = res
+        .headers()
+        .get(CONTENT_ENCODING)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_ascii_lowercase);
+    let body = res.bytes().await?;
+
+    let body = decode_body(encoding.as_deref(), &body).map_err(FetchError::Decode)?;
+    Ok((final_url, body))
+}
+
+/// Decompress body by its `Content-Encoding` into a string.
+fn decode_body(encoding: Option<&str>, body: &[u8]) -> std::io::Result<String> {
+    let mut decoded = Vec::new();
+    match encoding.map(str::trim) {
+        Some("gzip" | "x-gzip") => {
+            flate2::read::GzDecoder::new(body).read_to_end(&mut decoded)?;
+        }
+        // Deflate is usually zlib wrapped, but some servers send raw deflate
+        Some("deflate") => {
+            if flate2::read::ZlibDecoder::new(body)
+                .read_to_end(&mut decoded)
+                .is_err()
+            {
+                decoded.clear();
+                flate2::read::DeflateDecoder::new(body).read_to_end(&mut decoded)?;
+            }
+        }
+        _ => decoded.extend_from_slice(body),
+    }
+
+    Ok(String::from_utf8_lossy(&decoded).into_owned())
 }
 
 /// Helper for deserializing channels separated by commas.
@@ -130,3 +500,73 @@ where
         .filter(|s| !s.is_empty())
         .collect())
 }
+
+/// Helper for serializing items back into a comma separated string.
+pub fn serialize_items<S>(items: &[String], serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.serialize_str(&items.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_proxy_url() {
+        let url = proxy_url("user:p%40ss@127.0.0.1:1080").unwrap();
+        assert_eq!(url.scheme(), "socks5h");
+        assert_eq!(url.username(), "user");
+        assert_eq!(url.password(), Some("p%40ss"));
+
+        let url = proxy_url("http://10.0.0.1:8080").unwrap();
+        assert_eq!(url.scheme(), "http");
+
+        assert!(proxy_url("ftp://10.0.0.1:21").is_err());
+    }
+
+    #[test]
+    fn test_decode_gzip_body() {
+        use flate2::{Compression, write::GzEncoder};
+        use std::io::Write;
+
+        let html = "<div class=\"tgme_channel_info\">привет</div>";
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(html.as_bytes()).unwrap();
+        let body = encoder.finish().unwrap();
+
+        assert_eq!(decode_body(Some("gzip"), &body).unwrap(), html);
+        assert_eq!(decode_body(None, html.as_bytes()).unwrap(), html);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limited() {
+        use axum::http::{StatusCode, header};
+
+        let app = axum::Router::new()
+            .route(
+                "/retry-after",
+                axum::routing::get(|| async {
+                    (StatusCode::TOO_MANY_REQUESTS, [(header::RETRY_AFTER, "30")])
+                }),
+            )
+            .route(
+                "/no-header",
+                axum::routing::get(|| async { StatusCode::TOO_MANY_REQUESTS }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        let client = reqwest::Client::new();
+
+        assert!(matches!(
+            fetch_url(&client, &format!("http://{addr}/retry-after")).await,
+            Err(FetchError::RateLimited(Some(d))) if d == Duration::from_secs(30)
+        ));
+        assert!(matches!(
+            fetch_url(&client, &format!("http://{addr}/no-header")).await,
+            Err(FetchError::RateLimited(None))
+        ));
+    }
+}
@@ -0,0 +1,106 @@
+//! Proxy picked from the proxy list for a new client
+//!
+//! `proxy_strategy` decides which entry a client gets. Proxies of clients
+//! that failed are skipped by every strategy, once all entries failed
+//! the whole list is tried again.
+
+use rand::prelude::IndexedRandom;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ProxyStrategy {
+    /// Random entry for every client
+    #[default]
+    Random,
+    /// Entries in list order, spreads clients evenly over the list
+    RoundRobin,
+    /// Same entry for every client until it fails
+    Sticky,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// Position of the next round robin entry
+    next: usize,
+    /// Last picked entry
+    current: Option<String>,
+    failed: HashSet<String>,
+}
+
+/// Proxy state shared by the clients of a [super::client_pool::ClientPool]
+#[derive(Debug, Default)]
+pub struct ProxySelector {
+    state: Mutex<State>,
+}
+
+impl ProxySelector {
+    /// Pick an entry of `list` that didn't fail, `None` if the list is empty.
+    pub fn pick(&self, strategy: ProxyStrategy, list: &[String]) -> Option<String> {
+        let mut state = self.state.lock().expect("proxy lock poisoned");
+        let mut alive: Vec<&String> = list.iter().filter(|p| !state.failed.contains(*p)).collect();
+        if alive.is_empty() {
+            if !state.failed.is_empty() {
+                tracing::warn!("all proxies failed, trying them again");
+                state.failed.clear();
+            }
+            alive = list.iter().collect();
+        }
+
+        let proxy = match strategy {
+            ProxyStrategy::Random => *alive.choose(&mut rand::rng())?,
+            ProxyStrategy::RoundRobin => {
+                let proxy = alive.get(state.next % alive.len().max(1))?;
+                state.next = state.next.wrapping_add(1);
+                *proxy
+            }
+            ProxyStrategy::Sticky => match &state.current {
+                Some(current) if alive.contains(&current) => current,
+                _ => *alive.first()?,
+            },
+        }
+        .clone();
+        state.current = Some(proxy.clone());
+        Some(proxy)
+    }
+
+    /// Skip `proxy` for new clients.
+    pub fn mark_failed(&self, proxy: &str) {
+        tracing::info!("proxy failed, skipping it for new clients");
+        let mut state = self.state.lock().expect("proxy lock poisoned");
+        state.failed.insert(proxy.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pick() {
+        let list: Vec<String> = ["a", "b", "c"].map(String::from).to_vec();
+        let pick = |selector: &ProxySelector, strategy| selector.pick(strategy, &list).unwrap();
+
+        let selector = ProxySelector::default();
+        let picked: Vec<_> = (0..4)
+            .map(|_| pick(&selector, ProxyStrategy::RoundRobin))
+            .collect();
+        assert_eq!(picked, ["a", "b", "c", "a"]);
+
+        let selector = ProxySelector::default();
+        assert_eq!(pick(&selector, ProxyStrategy::Sticky), "a");
+        assert_eq!(pick(&selector, ProxyStrategy::Sticky), "a");
+        selector.mark_failed("a");
+        assert_eq!(pick(&selector, ProxyStrategy::Sticky), "b");
+        selector.mark_failed("b");
+        assert_eq!(pick(&selector, ProxyStrategy::Random), "c");
+
+        // Every entry failed, the list starts over
+        selector.mark_failed("c");
+        assert!(list.contains(&pick(&selector, ProxyStrategy::Random)));
+        assert!(selector.pick(ProxyStrategy::Random, &[]).is_none());
+    }
+}
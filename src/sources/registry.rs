@@ -1,8 +1,11 @@
 use std::future::Future;
 use std::pin::Pin;
+use std::sync::Arc;
 use tokio::sync::mpsc;
 
+use crate::config::{EnvConfig, EnvHandle};
 use crate::events::Event;
+use crate::sources::client_pool::ClientPool;
 use crate::sources::{Source, SourceConfig};
 
 pub type SourceFactory =
@@ -12,19 +15,30 @@ pub struct SourceRegistration {
     pub kind: &'static str,
     pub name: &'static str,
     pub fields: fn() -> schemars::schema::RootSchema,
-    pub factory: fn(SourceConfig, mpsc::Sender<Event>) -> SourceFactory,
+    /// Validate and normalize config before it's stored
+    pub normalize: fn(SourceConfig, &EnvConfig) -> anyhow::Result<SourceConfig>,
+    pub factory: fn(SourceConfig, mpsc::Sender<Event>, Arc<ClientPool>, EnvHandle) -> SourceFactory,
 }
 
 inventory::collect!(SourceRegistration);
 
+fn find(kind: &str) -> anyhow::Result<&'static SourceRegistration> {
+    inventory::iter::<SourceRegistration>()
+        .find(|r| r.kind == kind)
+        .ok_or_else(|| anyhow::anyhow!("no source registered for kind '{}'", kind))
+}
+
 /// Build a source from config
 pub async fn build(
     cfg: SourceConfig,
     tx: mpsc::Sender<Event>,
+    clients: Arc<ClientPool>,
+    env: EnvHandle,
 ) -> anyhow::Result<Box<dyn Source + Send>> {
-    let registration = inventory::iter::<SourceRegistration>()
-        .find(|r| r.kind == cfg.kind)
-        .ok_or_else(|| anyhow::anyhow!("no source registered for kind '{}'", cfg.kind))?;
+    (find(&cfg.kind)?.factory)(cfg, tx, clients, env).await
+}
 
-    (registration.factory)(cfg, tx).await
+/// Validate and normalize source config
+pub fn normalize(cfg: SourceConfig, env: &EnvConfig) -> anyhow::Result<SourceConfig> {
+    (find(&cfg.kind)?.normalize)(cfg, env)
 }
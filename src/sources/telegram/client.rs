@@ -1,6 +1,7 @@
 //! This code is based on this example from tgt:
 //! https://github.com/FedericoBruzzone/tgt/blob/main/examples/telegram.rs
 
+use std::time::Duration;
 use tdlib_rs::{
     enums::{AuthorizationState, MessageContent, Update},
     functions,
@@ -10,7 +11,7 @@ use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 use tokio_util::sync::CancellationToken;
 
 use super::TelegramClientConfig;
-use crate::{events::Event, model::Post};
+use crate::{events::Event, model::Post, webhook::WebhookConfig};
 
 pub struct TelegramClient {
     pub cfg: TelegramClientConfig,
@@ -44,7 +45,11 @@ impl TelegramClient {
         let shutdown = self.shutdown.clone();
         let client_id = self.client_id;
         let tx = self.tx.clone();
-        let webhook_url = self.cfg.webhook_url.clone();
+        let webhook = WebhookConfig {
+            urls: self.cfg.webhook_url.clone(),
+            retry_delay: Some(Duration::from_secs(5)),
+            ..Default::default()
+        };
         let channels = self.cfg.channel_ids.clone();
 
         // Spawn blocking because TDLib's tdlib_rs::receive() is a blocking function.
@@ -84,20 +89,20 @@ impl TelegramClient {
                             match &msg.content {
                                 MessageContent::MessageText(m) => {
                                     let _ = tx.blocking_send(Event::NewMessage(
-                                        webhook_url.clone(),
-                                        Post {
+                                        webhook.clone(),
+                                        Box::new(Post {
                                             id: msg.chat_id.to_string(),
                                             author: author_id,
                                             text: Some(m.text.text.clone()),
                                             ..Default::default()
-                                        },
+                                        }),
                                     ));
                                 }
 
                                 MessageContent::MessagePhoto(m) => {
                                     let _ = tx.blocking_send(Event::NewMessage(
-                                        webhook_url.clone(),
-                                        Post {
+                                        webhook.clone(),
+                                        Box::new(Post {
                                             id: msg.chat_id.to_string(),
                                             author: author_id,
                                             text: Some(m.caption.text.clone()),
@@ -109,20 +114,20 @@ impl TelegramClient {
                                                     .collect(),
                                             ),
                                             ..Default::default()
-                                        },
+                                        }),
                                     ));
                                 }
 
                                 MessageContent::MessageVideo(m) => {
                                     let _ = tx.blocking_send(Event::NewMessage(
-                                        webhook_url.clone(),
-                                        Post {
+                                        webhook.clone(),
+                                        Box::new(Post {
                                             id: msg.chat_id.to_string(),
                                             author: author_id,
                                             text: Some(m.caption.text.clone()),
                                             media: Some(vec![m.video.video.id.to_string()]),
                                             ..Default::default()
-                                        },
+                                        }),
                                     ));
                                 }
 
@@ -1,11 +1,24 @@
+use regex::Regex;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::LazyLock as Lazy;
 use tokio::sync::Mutex;
 use tokio::sync::mpsc;
 
+use crate::config::{EnvConfig, EnvHandle};
 use crate::events::Event;
+use crate::model::WebhookTest;
+use crate::sources::client_pool::ClientPool;
 use crate::sources::registry::SourceRegistration;
-use crate::sources::{Source, SourceConfig, deserialize_items};
+use crate::sources::{
+    ClientOptions, Source, SourceConfig, SourceDebug, SourceStatus, deserialize_items,
+    serialize_items,
+};
+use crate::webhook::quiet_hours::QuietHours;
+use crate::webhook::template::Template;
+use crate::webhook::{self, WebhookAuth, WebhookConfig, WebhookFormat, telegram_bot};
 
 use self::client::TelegramClient;
 use self::scraper::TelegramScraper;
@@ -17,18 +30,286 @@ pub mod scraper;
 pub const KIND_SCRAPER: &str = "telegram_scraper";
 pub const KIND_CLIENT: &str = "telegram_client";
 
+/// Canonical prefix of scraped channel urls
+pub(crate) const CHANNEL_URL_PREFIX: &str = "https://t.me/s/";
+
+static CHANNEL_NAME_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"^[A-Za-z0-9_]{3,32}$").unwrap());
+
+/// Rewrite common channel forms like `@channel`, `t.me/channel` or
+/// `https://t.me/channel` into `https://t.me/s/<channel>`.
+pub fn normalize_channel_url(input: &str) -> anyhow::Result<String> {
+    let name = input.trim();
+    let name = name
+        .strip_prefix("https://")
+        .or_else(|| name.strip_prefix("http://"))
+        .unwrap_or(name);
+    let name = name.strip_prefix("www.").unwrap_or(name);
+    let name = name
+        .strip_prefix("t.me/")
+        .or_else(|| name.strip_prefix("telegram.me/"))
+        .unwrap_or(name);
+    let name = name.strip_prefix("s/").unwrap_or(name);
+    let name = name.strip_prefix('@').unwrap_or(name);
+    let name = name.split(['?', '#']).next().unwrap_or_default();
+    let name = name.trim_end_matches('/');
+
+    let url = format!("{CHANNEL_URL_PREFIX}{name}");
+    if !CHANNEL_NAME_RE.is_match(name) {
+        anyhow::bail!("invalid channel_url {input:?}, normalized to {url:?}");
+    }
+    Ok(url)
+}
+
+fn default_seed_silently() -> bool {
+    true
+}
+
 pub enum TelegramSourceKind {
-    Scraper(TelegramScraper),
+    Scraper(Box<TelegramScraper>),
     Client(Mutex<TelegramClient>),
 }
 
 /// Config for Telegram scraper
 #[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
 pub struct TelegramScraperConfig {
+    /// Derived from the channel name when empty
+    #[serde(default)]
     pub id: String,
     pub channel_url: String,
+    /// Only fetch posts matching this search term, uses the `?q=` search of t.me
+    pub channel_query: Option<String>,
     pub poll_interval: i64,
-    pub webhook_url: String,
+
+    #[serde(
+        deserialize_with = "deserialize_items",
+        serialize_with = "serialize_items"
+    )]
+    #[schemars(with = "String")]
+    pub webhook_url: Vec<String>,
+
+    /// Webhook payload format
+    #[serde(default)]
+    pub webhook_format: WebhookFormat,
+    /// Only send these post fields, like `["id", "text", "url"]`, in JSON payloads
+    pub webhook_fields: Option<Vec<String>>,
+    /// Authentication of webhook requests, replaces the global `x-secret` header
+    #[serde(default)]
+    pub webhook_auth: WebhookAuth,
+    /// Custom JSON payload with `{{post.text}}` like placeholders, sent per post
+    pub webhook_template: Option<String>,
+    /// Send `webhook_template` as is with this content type instead of JSON
+    pub webhook_content_type: Option<String>,
+    /// Gzip webhook bodies larger than 8 KiB, the receiver must accept `Content-Encoding: gzip`
+    #[serde(default)]
+    pub webhook_compress: bool,
+    /// Store sponsored posts without sending them
+    #[serde(default)]
+    pub skip_sponsored: bool,
+    /// Send stats updates when views or reactions of known posts change
+    #[serde(default)]
+    pub track_stats: bool,
+    /// Store posts of the first poll without sending them, so only newer posts are sent
+    #[serde(default = "default_seed_silently")]
+    pub seed_silently: bool,
+    /// Store posts with the same text and media as a post seen within
+    /// the last day under another id without sending them
+    #[serde(default)]
+    pub dedup_by_content: bool,
+    /// Only send the newest posts when a poll finds more new posts than
+    /// this, like after downtime, the older ones are stored without webhooks
+    pub catchup_limit: Option<usize>,
+    /// Store new posts and log webhooks without sending them
+    #[serde(default)]
+    pub dry_run: bool,
+    /// Maximum webhook deliveries per minute, extra posts are queued
+    pub webhook_rate_limit: Option<u32>,
+    /// Daily window in which webhooks are queued and sent once it ends
+    pub quiet_hours: Option<QuietHours>,
+
+    /// Custom User-Agent header for fetching the channel
+    pub user_agent: Option<String>,
+    /// Additional headers for fetching the channel
+    pub extra_headers: Option<HashMap<String, String>>,
+    /// Timeout for fetching the channel in seconds, overrides the global one
+    pub request_timeout: Option<u64>,
+    /// Use an own HTTP client and proxy even when `SHARE_CLIENTS` is enabled
+    #[serde(default)]
+    pub dedicated_client: bool,
+    /// Switch to the new channel name when t.me redirects to it
+    #[serde(default)]
+    pub follow_renames: bool,
+
+    /// Only send posts with text matching this regex
+    pub text_regex: Option<String>,
+
+    /// Log level of this source, like `debug`, overrides the global `LOG_LEVEL`
+    pub log_level: Option<String>,
+    /// Keep the last fetched page for `GET /sources/{id}/debug`
+    #[serde(default)]
+    pub debug_capture: bool,
+}
+
+impl TelegramScraperConfig {
+    /// Rewrite `channel_url` into `https://t.me/s/<channel>` form, derive
+    /// empty `id` from the channel name and clamp poll interval to the
+    /// global `min_poll_interval`.
+    ///
+    /// A `?q=` search in `channel_url` is moved to `channel_query`.
+    pub fn normalize(&mut self, env: &EnvConfig) -> anyhow::Result<()> {
+        if self.channel_query.is_none()
+            && let Some((_, query)) = self.channel_url.split_once('?')
+        {
+            self.channel_query = url::form_urlencoded::parse(query.as_bytes())
+                .find(|(key, _)| key == "q")
+                .map(|(_, value)| value.into_owned());
+        }
+        self.channel_url = normalize_channel_url(&self.channel_url)?;
+        if self.id.is_empty() {
+            self.id = self
+                .channel_url
+                .trim_start_matches(CHANNEL_URL_PREFIX)
+                .to_string();
+        }
+
+        let min = env.min_poll_interval;
+        if self.poll_interval < min as i64 {
+            tracing::warn!(
+                "poll interval {}s of {} is below the minimum, using {}s",
+                self.poll_interval,
+                self.id,
+                min
+            );
+            self.poll_interval = min as i64;
+        }
+        Ok(())
+    }
+
+    /// Check that the config is valid.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.poll_interval <= 0 {
+            anyhow::bail!("poll_interval must be positive");
+        }
+        if self.webhook_rate_limit == Some(0) {
+            anyhow::bail!("webhook_rate_limit must be positive");
+        }
+        if self.catchup_limit == Some(0) {
+            anyhow::bail!("catchup_limit must be positive, use seed_silently to skip old posts");
+        }
+        if let Some(level) = &self.log_level
+            && level.parse::<tracing::Level>().is_err()
+        {
+            anyhow::bail!(
+                "invalid log_level {level:?}, expected trace, debug, info, warn or error"
+            );
+        }
+        self.client_options().validate()?;
+        self.text_regex()?;
+        self.webhook()?;
+        if self.webhook_template.is_none() {
+            // Stats updates are only sent as litehook JSON
+            if self.track_stats && self.webhook_format != WebhookFormat::Json {
+                anyhow::bail!(
+                    "track_stats is not supported with the slack and telegram_bot formats"
+                );
+            }
+            if self.webhook_format == WebhookFormat::TelegramBot {
+                for url in &self.webhook_url {
+                    telegram_bot::method_url(url, "sendMessage")?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Url of the channel page with the search query and the
+    /// `before` pagination cursor, if set.
+    pub fn page_url(&self, before: Option<u64>) -> anyhow::Result<url::Url> {
+        let mut url = url::Url::parse(&self.channel_url)?;
+        if let Some(query) = self
+            .channel_query
+            .as_deref()
+            .map(str::trim)
+            .filter(|q| !q.is_empty())
+        {
+            url.query_pairs_mut().append_pair("q", query);
+        }
+        if let Some(id) = before {
+            url.query_pairs_mut().append_pair("before", &id.to_string());
+        }
+        Ok(url)
+    }
+
+    /// Compile `text_regex` if set.
+    pub fn text_regex(&self) -> anyhow::Result<Option<Regex>> {
+        self.text_regex
+            .as_deref()
+            .map(|r| Regex::new(r).map_err(|e| anyhow::anyhow!("invalid text_regex: {e}")))
+            .transpose()
+    }
+
+    /// Webhook delivery settings, fails if `webhook_template` is invalid.
+    ///
+    /// The rate limiter is owned by the running scraper and set there.
+    pub fn webhook(&self) -> anyhow::Result<WebhookConfig> {
+        let template = match (&self.webhook_template, self.raw_content_type()?) {
+            (Some(template), Some(content_type)) => {
+                Some(Template::parse_raw(template, content_type)?)
+            }
+            (Some(template), None) => Some(Template::parse(template)?),
+            (None, Some(_)) => anyhow::bail!("webhook_content_type requires webhook_template"),
+            (None, None) => None,
+        };
+        if let Some(fields) = &self.webhook_fields {
+            webhook::validate_fields(fields)?;
+        }
+        self.webhook_auth.validate()?;
+        if let Some(quiet_hours) = &self.quiet_hours {
+            quiet_hours.validate()?;
+        }
+
+        Ok(WebhookConfig {
+            source: self.id.clone(),
+            urls: self.webhook_url.clone(),
+            format: self.webhook_format,
+            fields: self.webhook_fields.clone(),
+            auth: self.webhook_auth.clone(),
+            compress: self.webhook_compress,
+            template,
+            skip_sponsored: self.skip_sponsored,
+            track_stats: self.track_stats,
+            seed_silently: self.seed_silently,
+            dedup_by_content: self.dedup_by_content,
+            catchup_limit: self.catchup_limit,
+            dry_run: self.dry_run,
+            quiet_hours: self.quiet_hours.clone(),
+            rate_limit: None,
+            latency: None,
+            retry_delay: None,
+        })
+    }
+
+    /// Content type of raw templates, `None` when payloads are JSON.
+    fn raw_content_type(&self) -> anyhow::Result<Option<&str>> {
+        let Some(content_type) = self.webhook_content_type.as_deref() else {
+            return Ok(None);
+        };
+        let mime = content_type.split(';').next().unwrap_or_default().trim();
+        if mime.is_empty() || reqwest::header::HeaderValue::from_str(content_type).is_err() {
+            anyhow::bail!("invalid webhook_content_type {content_type:?}");
+        }
+
+        Ok((!mime.eq_ignore_ascii_case("application/json")).then_some(content_type))
+    }
+
+    /// Options for the HTTP client used for fetching the channel.
+    pub fn client_options(&self) -> ClientOptions {
+        ClientOptions {
+            user_agent: self.user_agent.clone(),
+            headers: self.extra_headers.clone().unwrap_or_default(),
+            timeout: self.request_timeout,
+            dedicated: self.dedicated_client,
+        }
+    }
 }
 
 /// Config for Telegram client
@@ -38,9 +319,13 @@ pub struct TelegramClientConfig {
     pub api_id: i32,
     pub api_hash: String,
     pub phone_number: String,
-    pub webhook_url: String,
 
     #[serde(deserialize_with = "deserialize_items")]
+    #[schemars(with = "String")]
+    pub webhook_url: Vec<String>,
+
+    #[serde(deserialize_with = "deserialize_items")]
+    #[schemars(with = "String")]
     pub channel_ids: Vec<String>,
 }
 
@@ -61,11 +346,19 @@ pub struct TelegramSource {
 }
 
 impl TelegramSource {
-    pub async fn new(cfg: SourceConfig, tx: mpsc::Sender<Event>) -> anyhow::Result<Self> {
+    pub async fn new(
+        cfg: SourceConfig,
+        tx: mpsc::Sender<Event>,
+        clients: Arc<ClientPool>,
+        env: EnvHandle,
+    ) -> anyhow::Result<Self> {
         let kind = match cfg.kind.as_str() {
             KIND_SCRAPER => {
                 let scraper_cfg: TelegramScraperConfig = serde_json::from_value(cfg.raw.clone())?;
-                TelegramSourceKind::Scraper(TelegramScraper::new(scraper_cfg, tx).await?)
+                scraper_cfg.validate()?;
+                TelegramSourceKind::Scraper(Box::new(
+                    TelegramScraper::new(scraper_cfg, tx, clients, env).await?,
+                ))
             }
             KIND_CLIENT => {
                 let client_cfg: TelegramClientConfig = serde_json::from_value(cfg.raw.clone())?;
@@ -104,6 +397,88 @@ impl Source for TelegramSource {
             TelegramSourceKind::Client(client) => client.lock().await.stop().await,
         }
     }
+
+    async fn backfill(&self, pages: u32) -> anyhow::Result<usize> {
+        match &self.kind {
+            TelegramSourceKind::Scraper(scraper) => scraper.backfill(pages).await,
+            TelegramSourceKind::Client(_) => {
+                anyhow::bail!("telegram client doesn't support backfill")
+            }
+        }
+    }
+
+    async fn probe(&self) -> anyhow::Result<()> {
+        match &self.kind {
+            TelegramSourceKind::Scraper(scraper) => Ok(scraper.probe().await?),
+            TelegramSourceKind::Client(_) => Ok(()),
+        }
+    }
+
+    async fn debug(&self, html: bool) -> anyhow::Result<SourceDebug> {
+        match &self.kind {
+            TelegramSourceKind::Scraper(scraper) => scraper.debug(html).await,
+            TelegramSourceKind::Client(_) => {
+                anyhow::bail!("telegram client doesn't support debug capture")
+            }
+        }
+    }
+
+    async fn webhook_urls(&self) -> Vec<String> {
+        match &self.kind {
+            TelegramSourceKind::Scraper(scraper) => scraper.cfg.read().await.webhook_url.clone(),
+            // The client is locked while it runs
+            TelegramSourceKind::Client(_) => Vec::new(),
+        }
+    }
+
+    async fn test_webhook(&self) -> anyhow::Result<Vec<WebhookTest>> {
+        match &self.kind {
+            TelegramSourceKind::Scraper(scraper) => scraper.test_webhook().await,
+            TelegramSourceKind::Client(_) => {
+                anyhow::bail!("telegram client doesn't support webhook tests")
+            }
+        }
+    }
+
+    fn jitter_first_poll(&self) {
+        if let TelegramSourceKind::Scraper(scraper) = &self.kind {
+            scraper.jitter_first_poll();
+        }
+    }
+
+    fn poll_now(&self) -> anyhow::Result<()> {
+        match &self.kind {
+            TelegramSourceKind::Scraper(scraper) => {
+                scraper.poll_now();
+                Ok(())
+            }
+            TelegramSourceKind::Client(_) => anyhow::bail!("telegram client doesn't poll"),
+        }
+    }
+
+    fn status(&self) -> Option<SourceStatus> {
+        match &self.kind {
+            TelegramSourceKind::Scraper(scraper) => Some(scraper.status()),
+            TelegramSourceKind::Client(_) => None,
+        }
+    }
+}
+
+fn normalize_scraper(mut cfg: SourceConfig, env: &EnvConfig) -> anyhow::Result<SourceConfig> {
+    let mut scraper: TelegramScraperConfig = serde_json::from_value(cfg.raw)?;
+    if scraper.id.is_empty() {
+        scraper.id = cfg.id.clone();
+    }
+    scraper.normalize(env)?;
+    scraper.validate()?;
+    cfg.id = scraper.id.clone();
+    cfg.raw = serde_json::to_value(&scraper)?;
+    Ok(cfg)
+}
+
+fn normalize_client(cfg: SourceConfig, _env: &EnvConfig) -> anyhow::Result<SourceConfig> {
+    serde_json::from_value::<TelegramClientConfig>(cfg.raw.clone())?;
+    Ok(cfg)
 }
 
 // Register sources
@@ -111,8 +486,9 @@ inventory::submit!(SourceRegistration {
     kind: KIND_SCRAPER,
     name: "Telegram scraper",
     fields: || schemars::schema_for!(TelegramScraperConfig),
-    factory: |cfg, tx| Box::pin(async move {
-        Ok(Box::new(TelegramSource::new(cfg, tx).await?) as Box<dyn Source + Send>)
+    normalize: normalize_scraper,
+    factory: |cfg, tx, clients, env| Box::pin(async move {
+        Ok(Box::new(TelegramSource::new(cfg, tx, clients, env).await?) as Box<dyn Source + Send>)
     }),
 });
 
@@ -120,7 +496,96 @@ inventory::submit!(SourceRegistration {
     kind: KIND_CLIENT,
     name: "Telegram client",
     fields: || schemars::schema_for!(TelegramClientConfig),
-    factory: |cfg, tx| Box::pin(async move {
-        Ok(Box::new(TelegramSource::new(cfg, tx).await?) as Box<dyn Source + Send>)
+    normalize: normalize_client,
+    factory: |cfg, tx, clients, env| Box::pin(async move {
+        Ok(Box::new(TelegramSource::new(cfg, tx, clients, env).await?) as Box<dyn Source + Send>)
     }),
 });
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_channel_url() {
+        for input in [
+            "@durov",
+            "durov",
+            "t.me/durov",
+            "t.me/s/durov",
+            "https://t.me/durov/",
+            "https://t.me/s/durov?before=10",
+        ] {
+            assert_eq!(
+                normalize_channel_url(input).unwrap(),
+                "https://t.me/s/durov"
+            );
+        }
+
+        assert!(normalize_channel_url("https://example.com/durov").is_err());
+    }
+
+    #[test]
+    fn test_page_url_query() {
+        let cfg: TelegramScraperConfig = serde_json::from_value(serde_json::json!({
+            "channel_url": "https://t.me/s/durov",
+            "channel_query": "ton & tg",
+            "poll_interval": 60,
+            "webhook_url": "https://example.com/hook",
+        }))
+        .unwrap();
+
+        assert_eq!(
+            cfg.page_url(Some(10)).unwrap().as_str(),
+            "https://t.me/s/durov?q=ton+%26+tg&before=10"
+        );
+    }
+
+    #[test]
+    fn test_comma_separated_schema() {
+        // The dashboard edits these as plain text, not JSON
+        let schema = serde_json::to_value(schemars::schema_for!(TelegramScraperConfig)).unwrap();
+        assert_eq!(schema["properties"]["webhook_url"]["type"], "string");
+        let schema = serde_json::to_value(schemars::schema_for!(TelegramClientConfig)).unwrap();
+        assert_eq!(schema["properties"]["channel_ids"]["type"], "string");
+    }
+
+    #[test]
+    fn test_track_stats_format() {
+        let cfg = |format: &str| -> TelegramScraperConfig {
+            serde_json::from_value(serde_json::json!({
+                "channel_url": "https://t.me/s/durov",
+                "poll_interval": 60,
+                "webhook_url": "https://example.com/hook",
+                "webhook_format": format,
+                "track_stats": true,
+            }))
+            .unwrap()
+        };
+
+        assert!(cfg("json").validate().is_ok());
+        assert!(cfg("slack").validate().is_err());
+
+        let mut slack = cfg("slack");
+        slack.webhook_template = Some(r#"{"text": "{{post.text}}"}"#.to_string());
+        assert!(slack.validate().is_ok());
+    }
+
+    #[test]
+    fn test_derive_blank_id() {
+        let cfg: SourceConfig = serde_json::from_value(serde_json::json!({
+            "id": "",
+            "kind": KIND_SCRAPER,
+            "raw": {
+                "channel_url": "https://t.me/durov",
+                "poll_interval": 60,
+                "webhook_url": "https://example.com/hook",
+            },
+        }))
+        .unwrap();
+
+        let cfg = normalize_scraper(cfg, &EnvConfig::default()).unwrap();
+        assert_eq!(cfg.id, "durov");
+        assert_eq!(cfg.raw["id"], "durov");
+    }
+}
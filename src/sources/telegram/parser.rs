@@ -1,8 +1,30 @@
 use html_to_markdown_rs::convert;
-use scraper::{ElementRef, Html, Selector};
+use scraper::{CaseSensitivity, ElementRef, Html, Selector};
 use std::sync::LazyLock as Lazy;
 
-use crate::model::{Channel, ChannelCounters, Page, Post, PostReaction};
+use crate::model::{
+    Channel, ChannelCounters, Media, MediaKind, Page, Post, PostReaction, Reply, parse_count,
+    post_url,
+};
+
+/// Max number of characters kept from the quoted text of a reply
+const REPLY_SNIPPET_LEN: usize = 200;
+
+/// Word stems of counter types, t.me translates them to the browser language
+const SUBSCRIBER_STEMS: &[&str] = &[
+    "subscriber",
+    "подписчик",
+    "підписник",
+    "suscriptor",
+    "abonnent",
+    "abonné",
+    "iscritt",
+    "inscrit",
+    "abone",
+];
+const PHOTO_STEMS: &[&str] = &["photo", "foto", "фото"];
+const VIDEO_STEMS: &[&str] = &["video", "vídeo", "vidéo", "видео", "відео"];
+const LINK_STEMS: &[&str] = &["link", "ссылк", "посилан", "enlace", "lien", "collegament"];
 
 static ID_SEL: Lazy<Selector> =
     Lazy::new(|| Selector::parse("div.tgme_channel_info_header_username a").unwrap());
@@ -16,19 +38,56 @@ static DESC_SEL: Lazy<Selector> =
     Lazy::new(|| Selector::parse("div.tgme_channel_info_description").unwrap());
 
 static MSG_SEL: Lazy<Selector> = Lazy::new(|| Selector::parse("div.tgme_widget_message").unwrap());
+/// Name above the post, the author of a reply is inside the reply link
 static AUTHOR_SEL: Lazy<Selector> = Lazy::new(|| {
-    Selector::parse("div.tgme_widget_message_author a.tgme_widget_message_owner_name span").unwrap()
+    Selector::parse(
+        "div.tgme_widget_message_bubble > div.tgme_widget_message_author .tgme_widget_message_owner_name, \
+        div.tgme_widget_message_bubble > div.tgme_widget_message_author .tgme_widget_message_author_name",
+    )
+    .unwrap()
 });
-static TEXT_SEL: Lazy<Selector> =
-    Lazy::new(|| Selector::parse("div.tgme_widget_message_text").unwrap());
-static MEDIA_SEL: Lazy<Selector> =
-    Lazy::new(|| Selector::parse("a.tgme_widget_message_photo_wrap").unwrap());
+/// Admin signature in the footer of channels with signatures enabled
+static SIGNATURE_SEL: Lazy<Selector> =
+    Lazy::new(|| Selector::parse(".tgme_widget_message_from_author").unwrap());
+static TEXT_SEL: Lazy<Selector> = Lazy::new(|| {
+    Selector::parse("div.tgme_widget_message_text:not(.js-message_reply_text)").unwrap()
+});
+static MEDIA_SEL: Lazy<Selector> = Lazy::new(|| {
+    Selector::parse(
+        "a.tgme_widget_message_photo_wrap, a.tgme_widget_message_video_player, \
+        .tgme_widget_message_voice_player, .tgme_widget_message_roundvideo_player, \
+        .tgme_widget_message_document_wrap",
+    )
+    .unwrap()
+});
+static VIDEO_THUMB_SEL: Lazy<Selector> = Lazy::new(|| {
+    Selector::parse("i.tgme_widget_message_video_thumb, i.tgme_widget_message_roundvideo_thumb")
+        .unwrap()
+});
+static VIDEO_SEL: Lazy<Selector> = Lazy::new(|| Selector::parse("video").unwrap());
+static AUDIO_SEL: Lazy<Selector> = Lazy::new(|| Selector::parse("audio").unwrap());
+static DURATION_SEL: Lazy<Selector> = Lazy::new(|| {
+    Selector::parse(
+        "time.message_video_duration, time.tgme_widget_message_voice_duration, \
+        time.tgme_widget_message_roundvideo_duration",
+    )
+    .unwrap()
+});
+static DOCUMENT_LINK_SEL: Lazy<Selector> = Lazy::new(|| Selector::parse("a[href]").unwrap());
+static DOCUMENT_ICON_SEL: Lazy<Selector> =
+    Lazy::new(|| Selector::parse(".tgme_widget_message_document_icon").unwrap());
+static DOCUMENT_TITLE_SEL: Lazy<Selector> =
+    Lazy::new(|| Selector::parse(".tgme_widget_message_document_title").unwrap());
 static REACTIONS_SEL: Lazy<Selector> =
     Lazy::new(|| Selector::parse("div.tgme_widget_message_reactions").unwrap());
 static VIEWS_SEL: Lazy<Selector> =
     Lazy::new(|| Selector::parse("span.tgme_widget_message_views").unwrap());
+static META_SEL: Lazy<Selector> =
+    Lazy::new(|| Selector::parse("span.tgme_widget_message_meta").unwrap());
 static DATE_SEL: Lazy<Selector> =
     Lazy::new(|| Selector::parse("a.tgme_widget_message_date time").unwrap());
+static DATE_LINK_SEL: Lazy<Selector> =
+    Lazy::new(|| Selector::parse("a.tgme_widget_message_date").unwrap());
 
 static COUNTER_BLOCK_SEL: Lazy<Selector> =
     Lazy::new(|| Selector::parse("div.tgme_channel_info_counter").unwrap());
@@ -37,11 +96,30 @@ static TYPE_SEL: Lazy<Selector> = Lazy::new(|| Selector::parse("span.counter_typ
 
 static REACTION_SEL: Lazy<Selector> = Lazy::new(|| Selector::parse("span.tgme_reaction").unwrap());
 static EMOJI_SEL: Lazy<Selector> = Lazy::new(|| Selector::parse("i.emoji b").unwrap());
+static EMOJI_ICON_SEL: Lazy<Selector> = Lazy::new(|| Selector::parse("i.emoji").unwrap());
+
+static REPLY_SEL: Lazy<Selector> =
+    Lazy::new(|| Selector::parse("a.tgme_widget_message_reply").unwrap());
+static REPLY_TEXT_SEL: Lazy<Selector> =
+    Lazy::new(|| Selector::parse(".js-message_reply_text").unwrap());
+
+static SERVICE_SEL: Lazy<Selector> =
+    Lazy::new(|| Selector::parse("div.tgme_widget_message_service").unwrap());
+static SPONSORED_SEL: Lazy<Selector> =
+    Lazy::new(|| Selector::parse("div.tgme_widget_message_sponsored").unwrap());
+static GROUPED_SEL: Lazy<Selector> =
+    Lazy::new(|| Selector::parse("div.tgme_widget_message_grouped_wrap").unwrap());
 
 static CNL_SEL: Lazy<Selector> = Lazy::new(|| Selector::parse("div.tgme_channel_info").unwrap());
 static POST_SEL: Lazy<Selector> =
     Lazy::new(|| Selector::parse("div.tgme_widget_message_wrap").unwrap());
 
+/// Parts of the "View in Telegram" page shown instead of the message list
+static PAGE_ACTION_SEL: Lazy<Selector> =
+    Lazy::new(|| Selector::parse("div.tgme_page div.tgme_page_action").unwrap());
+static PAGE_EXTRA_SEL: Lazy<Selector> =
+    Lazy::new(|| Selector::parse("div.tgme_page div.tgme_page_extra").unwrap());
+
 trait ElementRefExt {
     fn whole_text(&self) -> String;
     fn select_first(&self, selector: &Selector) -> Option<ElementRef<'_>>;
@@ -58,12 +136,7 @@ impl ElementRefExt for ElementRef<'_> {
 }
 
 fn parse_counters(container: ElementRef<'_>) -> anyhow::Result<ChannelCounters> {
-    let mut data = ChannelCounters {
-        subscribers: None,
-        photos: None,
-        videos: None,
-        links: None,
-    };
+    let mut data = ChannelCounters::default();
 
     for block in container.select(&COUNTER_BLOCK_SEL) {
         let value = block
@@ -76,22 +149,41 @@ fn parse_counters(container: ElementRef<'_>) -> anyhow::Result<ChannelCounters>
             .map(|v| v.whole_text())
             .unwrap_or_default();
 
-        match kind.as_str() {
-            "subscriber" => data.subscribers = Some(value),
-            "subscribers" => data.subscribers = Some(value),
-            "photo" => data.photos = Some(value),
-            "photos" => data.photos = Some(value),
-            "video" => data.videos = Some(value),
-            "videos" => data.videos = Some(value),
-            "link" => data.links = Some(value),
-            "links" => data.links = Some(value),
-            _ => {}
+        match counter_field(&mut data, &kind) {
+            Some(field) => *field = Some(value),
+            None => tracing::debug!("unknown counter type {kind:?}"),
         }
     }
 
+    data.subscribers_count = data.subscribers.as_deref().and_then(parse_count);
+    data.photos_count = data.photos.as_deref().and_then(parse_count);
+    data.videos_count = data.videos.as_deref().and_then(parse_count);
+    data.links_count = data.links.as_deref().and_then(parse_count);
+
     Ok(data)
 }
 
+/// Counter of [ChannelCounters] a type label like "subscribers" refers to.
+///
+/// Labels are matched by word stem, so plural forms and some
+/// other languages than English are recognized.
+fn counter_field<'a>(data: &'a mut ChannelCounters, kind: &str) -> Option<&'a mut Option<String>> {
+    let kind = kind.trim().to_lowercase();
+    let is = |stems: &[&str]| stems.iter().any(|s| kind.starts_with(s));
+
+    if is(SUBSCRIBER_STEMS) {
+        Some(&mut data.subscribers)
+    } else if is(PHOTO_STEMS) {
+        Some(&mut data.photos)
+    } else if is(VIDEO_STEMS) {
+        Some(&mut data.videos)
+    } else if is(LINK_STEMS) {
+        Some(&mut data.links)
+    } else {
+        None
+    }
+}
+
 fn parse_reactions(container: ElementRef<'_>) -> anyhow::Result<Vec<PostReaction>> {
     let mut data: Vec<PostReaction> = Vec::new();
 
@@ -99,54 +191,151 @@ fn parse_reactions(container: ElementRef<'_>) -> anyhow::Result<Vec<PostReaction
         let emoji = reaction
             .select_first(&EMOJI_SEL)
             .map(|v| v.whole_text())
-            .unwrap_or("unknown".to_string());
-
-        let count = reaction
-            .whole_text()
-            .replace(emoji.as_str(), "")
-            .trim()
-            .to_string();
+            .filter(|v| !v.trim().is_empty());
+
+        // Custom emoji are rendered as a background image without text
+        let image = match emoji {
+            Some(_) => None,
+            None => reaction
+                .select_first(&EMOJI_ICON_SEL)
+                .and_then(|v| v.value().attr("style"))
+                .and_then(parse_style_url),
+        };
+
+        let emoji = emoji.or_else(|| image.is_none().then(|| "unknown".to_string()));
+
+        let mut count = reaction.whole_text();
+        if let Some(e) = &emoji {
+            count = count.replace(e.as_str(), "");
+        }
 
         data.push(PostReaction {
-            emoji: Some(emoji),
-            count: Some(count),
+            emoji,
+            count: Some(count.trim().to_string()),
+            image,
         });
     }
 
     Ok(data)
 }
 
-fn parse_media(container: ElementRef<'_>) -> anyhow::Result<Option<String>> {
-    if let Some(style) = container.value().attr("style")
-        && let Some(start) = style.find("url('")
-    {
-        let start = start + 5;
-        let end = style[start..].find("')").unwrap();
-        let url = style[start..start + end].to_string();
-        return Ok(Some(url));
+/// Extract the url from `background-image:url('...')` inline style
+fn parse_style_url(style: &str) -> Option<String> {
+    let start = style.find("url('")? + 5;
+    let end = style[start..].find("')")?;
+    Some(style[start..start + end].to_string())
+}
+
+/// Parse photo, video, voice or document element into [Media].
+///
+/// Videos only have a preview image, GIFs are videos that play on their own.
+/// Audio files and other documents can't be downloaded from the page,
+/// their url links to the post.
+fn parse_media(container: ElementRef<'_>) -> anyhow::Result<Option<Media>> {
+    let el = container.value();
+    let has_class = |class| el.has_class(class, CaseSensitivity::CaseSensitive);
+    let duration = container
+        .select_first(&DURATION_SEL)
+        .map(|d| d.whole_text().trim().to_string())
+        .filter(|d| !d.is_empty());
+    let media = |url: &str, kind| Media {
+        url: url.to_string(),
+        kind,
+        duration: duration.clone(),
+        filename: None,
+    };
+
+    if has_class("tgme_widget_message_photo_wrap") {
+        return Ok(el
+            .attr("style")
+            .and_then(parse_style_url)
+            .map(|url| media(&url, MediaKind::Photo)));
+    }
+    if has_class("tgme_widget_message_voice_player") {
+        return Ok(container
+            .select_first(&AUDIO_SEL)
+            .and_then(|audio| audio.value().attr("src"))
+            .map(|url| media(url, MediaKind::Voice)));
+    }
+    if has_class("tgme_widget_message_roundvideo_player") {
+        let url = container
+            .select_first(&VIDEO_SEL)
+            .and_then(|video| video.value().attr("src").map(String::from))
+            .or_else(|| {
+                container
+                    .select_first(&VIDEO_THUMB_SEL)
+                    .and_then(|thumb| thumb.value().attr("style"))
+                    .and_then(parse_style_url)
+            });
+        return Ok(url.map(|url| media(&url, MediaKind::RoundVideo)));
+    }
+    if has_class("tgme_widget_message_document_wrap") {
+        let audio = container
+            .select_first(&DOCUMENT_ICON_SEL)
+            .is_some_and(|icon| {
+                icon.value()
+                    .has_class("audio", CaseSensitivity::CaseSensitive)
+            });
+        let url = el.attr("href").or_else(|| {
+            container
+                .select_first(&DOCUMENT_LINK_SEL)
+                .and_then(|link| link.value().attr("href"))
+        });
+        return Ok(url.map(|url| Media {
+            filename: container
+                .select_first(&DOCUMENT_TITLE_SEL)
+                .map(|t| t.whole_text().trim().to_string())
+                .filter(|t| !t.is_empty()),
+            ..media(
+                url,
+                if audio {
+                    MediaKind::Audio
+                } else {
+                    MediaKind::Document
+                },
+            )
+        }));
     }
 
-    Ok(None)
+    let gif = el.classes().any(|c| c.contains("gif"))
+        || container
+            .select_first(&VIDEO_SEL)
+            .is_some_and(|v| v.value().attr("autoplay").is_some());
+    Ok(container
+        .select_first(&VIDEO_THUMB_SEL)
+        .and_then(|thumb| thumb.value().attr("style"))
+        .and_then(parse_style_url)
+        .map(|url| {
+            media(
+                &url,
+                if gif {
+                    MediaKind::Gif
+                } else {
+                    MediaKind::VideoThumb
+                },
+            )
+        }))
 }
 
 fn parse_channel(channel: ElementRef<'_>) -> anyhow::Result<Channel> {
     let id = channel
         .select_first(&ID_SEL)
         .map(|v| v.whole_text())
-        .expect("channel id not found")
+        .ok_or_else(|| anyhow::anyhow!("channel id not found"))?
         .replace("@", "");
 
     let counters = channel
         .select_first(&COUNTERS_SEL)
         .map(parse_counters)
         .transpose()?
-        .unwrap();
+        .unwrap_or_default();
 
     let name = channel.select_first(&NAME_SEL).map(|v| v.whole_text());
 
     let image = channel
         .select_first(&IMAGE_SEL)
-        .map(|v| v.value().attr("src").unwrap().to_string());
+        .and_then(|v| v.value().attr("src"))
+        .map(str::to_string);
 
     let description = channel
         .select_first(&DESC_SEL)
@@ -164,27 +353,92 @@ fn parse_channel(channel: ElementRef<'_>) -> anyhow::Result<Channel> {
     Ok(data)
 }
 
+/// Parse service message text like "Channel pinned a message"
+fn parse_service(message: ElementRef<'_>) -> Option<String> {
+    let service = message.select_first(&SERVICE_SEL);
+    let is_service = service.is_some() || message.value().classes().any(|c| c == "service_message");
+    if !is_service {
+        return None;
+    }
+
+    let text = service
+        .or_else(|| message.select_first(&TEXT_SEL))
+        .map(|el| el.whole_text().trim().to_string())
+        .filter(|t| !t.is_empty());
+
+    Some(text.unwrap_or("service message".to_string()))
+}
+
+/// Check if message is a sponsored message.
+///
+/// Only the Telegram ad markup is matched, classes merely
+/// containing "sponsored" are ignored.
+fn is_sponsored(message: ElementRef<'_>) -> bool {
+    message
+        .value()
+        .classes()
+        .any(|c| c == "tgme_widget_message_sponsored")
+        || message.select_first(&SPONSORED_SEL).is_some()
+}
+
+/// Parse the quoted post of a reply.
+///
+/// The id is taken from the link like `https://t.me/channel/123`,
+/// the quoted text is cut to [REPLY_SNIPPET_LEN] characters.
+fn parse_reply(reply: ElementRef<'_>) -> Option<Reply> {
+    let href = reply.value().attr("href")?;
+    let path = href.split(['?', '#']).next().unwrap_or_default();
+    let mut parts = path.trim_end_matches('/').rsplit('/');
+    let num = parts.next()?;
+    let channel = parts.next()?;
+    let id = format!("{channel}/{num}");
+    split_post_id(&id)?;
+
+    let text = reply
+        .select_first(&REPLY_TEXT_SEL)
+        .map(|el| el.whole_text().trim().to_string())
+        .filter(|t| !t.is_empty())
+        .map(|t| match t.char_indices().nth(REPLY_SNIPPET_LEN) {
+            Some((end, _)) => format!("{}…", &t[..end]),
+            None => t,
+        });
+
+    Some(Reply { id, text })
+}
+
 fn parse_post(post: ElementRef<'_>) -> anyhow::Result<Post> {
-    let id = post
+    let message = post
         .select_first(&MSG_SEL)
-        .expect("post not found")
+        .ok_or_else(|| anyhow::anyhow!("message not found"))?;
+    let id = message
         .value()
         .attr("data-post")
-        .expect("post id not found")
+        .ok_or_else(|| anyhow::anyhow!("post id not found"))?
         .to_string();
 
-    let author = post.select_first(&AUTHOR_SEL).map(|el| el.whole_text());
+    let service = parse_service(message);
+    let sponsored = is_sponsored(message);
+
+    // Signature of the admin who posted, the channel name otherwise
+    let author = [&*SIGNATURE_SEL, &*AUTHOR_SEL]
+        .into_iter()
+        .filter_map(|sel| post.select_first(sel))
+        .map(|el| el.whole_text().trim().to_string())
+        .find(|name| !name.is_empty());
 
     let text = post
         .select_first(&TEXT_SEL)
         .map(|html| convert(&html.inner_html(), None))
         .transpose()?;
 
-    let media_vec: Vec<String> = post
+    let media_items: Vec<Media> = post
         .select(&MEDIA_SEL)
         .filter_map(|el| parse_media(el).ok().flatten())
         .collect();
-    let media = (!media_vec.is_empty()).then_some(media_vec);
+    let media_items = (!media_items.is_empty()).then_some(media_items);
+    let media = media_items
+        .as_ref()
+        .map(|items| items.iter().map(|m| m.url.clone()).collect());
 
     let reactions = post
         .select_first(&REACTIONS_SEL)
@@ -192,46 +446,414 @@ fn parse_post(post: ElementRef<'_>) -> anyhow::Result<Post> {
         .transpose()?;
 
     let views = post.select_first(&VIEWS_SEL).map(|el| el.whole_text());
+    let views_count = views.as_deref().and_then(parse_count);
+
+    // Date links to the post, the id is a fallback
+    let url = post
+        .select_first(&DATE_LINK_SEL)
+        .and_then(|el| el.value().attr("href"))
+        .filter(|href| href.starts_with("https://"))
+        .map_or_else(|| post_url(&id), |href| href.to_string());
 
     let date = post
         .select_first(&DATE_SEL)
         .and_then(|el| el.value().attr("datetime"))
         .map(|s| s.to_string());
 
+    let edited = post
+        .select_first(&META_SEL)
+        .is_some_and(|el| el.whole_text().contains("edited"));
+
+    let reply_to = post.select_first(&REPLY_SEL).and_then(parse_reply);
+
     Ok(Post {
         id,
+        url,
         author,
         text,
         media,
+        media_items,
         reactions,
         views,
+        views_count,
         date,
+        service,
+        edited,
+        sponsored,
+        reply_to,
+        // Set when merging albums
+        album_size: None,
     })
 }
 
+/// Check if post is a part of album (grouped media)
+fn is_grouped(post: ElementRef<'_>) -> bool {
+    post.value().classes().any(|c| c.contains("grouped"))
+        || post.select_first(&GROUPED_SEL).is_some()
+}
+
+/// Split post id like `channel/123` into channel and message number
+fn split_post_id(id: &str) -> Option<(&str, u64)> {
+    let (channel, num) = id.rsplit_once('/')?;
+    Some((channel, num.parse().ok()?))
+}
+
+/// Merge consecutive grouped posts of the same album into one [Post].
+///
+/// The earliest post id is kept, media is combined and missing
+/// fields are taken from the following posts of the album. Posts of
+/// albums get the number of their media as `album_size`.
+fn merge_albums(posts: Vec<(Post, bool)>) -> Vec<Post> {
+    let mut merged: Vec<(Post, bool)> = Vec::with_capacity(posts.len());
+
+    for (post, grouped) in posts {
+        // Album items have consecutive ids, one per media
+        if let Some((last, last_grouped)) = merged.last_mut()
+            && grouped
+            && *last_grouped
+            && let (Some((a_cnl, a_num)), Some((b_cnl, b_num))) =
+                (split_post_id(&last.id), split_post_id(&post.id))
+            && a_cnl == b_cnl
+            && b_num == a_num + last.media.as_ref().map_or(1, |m| m.len().max(1) as u64)
+        {
+            if let Some(media) = post.media {
+                last.media.get_or_insert_with(Vec::new).extend(media);
+            }
+            if let Some(items) = post.media_items {
+                last.media_items.get_or_insert_with(Vec::new).extend(items);
+            }
+            last.text = last.text.take().or(post.text);
+            last.author = last.author.take().or(post.author);
+            last.reactions = last.reactions.take().or(post.reactions);
+            last.views = last.views.take().or(post.views);
+            last.views_count = last.views_count.or(post.views_count);
+            last.date = last.date.take().or(post.date);
+            continue;
+        }
+
+        merged.push((post, grouped));
+    }
+
+    merged
+        .into_iter()
+        .map(|(mut post, grouped)| {
+            if grouped {
+                post.album_size = Some(post.media.as_ref().map_or(1, |m| m.len().max(1)) as u32);
+            }
+            post
+        })
+        .collect()
+}
+
 /// Parse Telegram channel page
 ///
 /// Parses the channel information, all visible posts on page (no scrolling),
 ///
-/// Returns [TmePage] or None if page is invalid
+/// Returns [Page] or None if page is invalid, like a placeholder page
+/// without the channel username. Posts that fail to parse are skipped,
+/// a channel without posts is a valid page.
 pub fn parse_page(html: &str) -> anyhow::Result<Option<Page>> {
     let document = Html::parse_document(html);
     let mut posts = Vec::new();
 
     // Try to parse channel, return None if invalid
-    let channel = match document
-        .select(&CNL_SEL)
-        .next()
-        .map(parse_channel)
-        .transpose()?
-    {
-        Some(c) => c,
+    let channel = match document.select(&CNL_SEL).next().map(parse_channel) {
+        Some(Ok(c)) => c,
+        Some(Err(e)) => {
+            tracing::debug!("invalid channel info: {e}");
+            return Ok(None);
+        }
         None => return Ok(None),
     };
 
+    // A single malformed post shouldn't fail the whole page
     for post in document.select(&POST_SEL) {
-        posts.push(parse_post(post)?);
+        match parse_post(post) {
+            Ok(parsed) => posts.push((parsed, is_grouped(post))),
+            Err(e) => tracing::warn!("skipping malformed post on {}: {e}", channel.id),
+        }
     }
 
-    Ok(Some(Page { channel, posts }))
+    Ok(Some(Page {
+        channel,
+        posts: merge_albums(posts),
+    }))
+}
+
+/// Check if t.me answered with the page asking to view or join the
+/// channel in the app instead of the message list.
+///
+/// Only channels show a subscriber count there, so user and group pages
+/// are not mistaken for it.
+pub fn is_interstitial(html: &str) -> bool {
+    let document = Html::parse_document(html);
+    document.select(&CNL_SEL).next().is_none()
+        && document.select(&PAGE_ACTION_SEL).next().is_some()
+        && document.select(&PAGE_EXTRA_SEL).any(|extra| {
+            let text = extra.whole_text().to_lowercase();
+            SUBSCRIBER_STEMS.iter().any(|stem| text.contains(stem))
+        })
+}
+
+/// Raw HTML of the first post on a channel page.
+pub fn first_post_html(html: &str) -> Option<String> {
+    Html::parse_document(html)
+        .select(&POST_SEL)
+        .next()
+        .map(|post| post.html())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn photo_post(id: &str, text: Option<&str>) -> Post {
+        Post {
+            id: id.to_string(),
+            text: text.map(str::to_string),
+            media: Some(vec![format!("https://example.com/{id}.png")]),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_merge_albums() {
+        let posts = vec![
+            (photo_post("test/1", None), false),
+            (photo_post("test/2", Some("Album")), true),
+            (photo_post("test/3", None), true),
+            (photo_post("test/4", None), true),
+            (photo_post("test/5", None), false),
+        ];
+
+        let merged = merge_albums(posts);
+
+        assert_eq!(merged.len(), 3);
+        assert_eq!(merged[1].id, "test/2");
+        assert_eq!(merged[1].text.as_deref(), Some("Album"));
+        assert_eq!(merged[1].media.as_ref().map(Vec::len), Some(3));
+        assert_eq!(merged[1].album_size, Some(3));
+        assert_eq!(merged[0].album_size, None);
+    }
+
+    #[test]
+    fn test_merge_albums_non_consecutive() {
+        let posts = vec![
+            (photo_post("test/1", None), true),
+            (photo_post("test/5", None), true),
+        ];
+
+        assert_eq!(merge_albums(posts).len(), 2);
+    }
+
+    #[test]
+    fn test_counters_locale() {
+        let html = Html::parse_fragment(
+            r#"<div class="tgme_channel_info_counters">
+            <div class="tgme_channel_info_counter"><span class="counter_value">1.2M</span> <span class="counter_type">подписчиков</span></div>
+            <div class="tgme_channel_info_counter"><span class="counter_value">12</span> <span class="counter_type">Fotos</span></div>
+            <div class="tgme_channel_info_counter"><span class="counter_value">3</span> <span class="counter_type">files</span></div>
+            </div>"#,
+        );
+        let counters = parse_counters(html.select(&COUNTERS_SEL).next().unwrap()).unwrap();
+
+        assert_eq!(counters.subscribers_count, Some(1_200_000));
+        assert_eq!(counters.photos_count, Some(12));
+        assert_eq!(counters.videos, None);
+    }
+
+    #[test]
+    fn test_custom_emoji_reactions() {
+        let html = Html::parse_fragment(
+            r#"<div class="tgme_widget_message_reactions">
+            <span class="tgme_reaction"><i class="emoji" style="background-image:url('https://telegram.org/img/emoji/40/F09F918D.png')"><b>👍</b></i>5.7K</span>
+            <span class="tgme_reaction"><i class="emoji custom_emoji" style="background-image:url('https://example.com/emoji.webp')"></i>39</span>
+            </div>"#,
+        );
+        let reactions = parse_reactions(html.root_element()).unwrap();
+
+        assert_eq!(
+            reactions,
+            [
+                PostReaction {
+                    emoji: Some("👍".to_string()),
+                    count: Some("5.7K".to_string()),
+                    image: None,
+                },
+                PostReaction {
+                    emoji: None,
+                    count: Some("39".to_string()),
+                    image: Some("https://example.com/emoji.webp".to_string()),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_media_kinds() {
+        let html = Html::parse_fragment(
+            r#"<div class="tgme_widget_message_wrap"><div class="tgme_widget_message" data-post="test/1">
+            <a class="tgme_widget_message_photo_wrap" style="background-image:url('https://example.com/photo.jpg')"></a>
+            <a class="tgme_widget_message_video_player"><i class="tgme_widget_message_video_thumb" style="background-image:url('https://example.com/video.jpg')"></i><video src="v.mp4"></video></a>
+            <a class="tgme_widget_message_video_player"><i class="tgme_widget_message_video_thumb" style="background-image:url('https://example.com/gif.jpg')"></i><video src="g.mp4" autoplay loop muted></video></a>
+            </div></div>"#,
+        );
+        let post = parse_post(html.select(&POST_SEL).next().unwrap()).unwrap();
+
+        let kinds: Vec<_> = post.media_items.iter().flatten().map(|m| m.kind).collect();
+        assert_eq!(
+            kinds,
+            [MediaKind::Photo, MediaKind::VideoThumb, MediaKind::Gif]
+        );
+        assert_eq!(
+            post.media.as_ref().map(|m| m[1].as_str()),
+            Some("https://example.com/video.jpg")
+        );
+    }
+
+    #[test]
+    fn test_voice_and_documents() {
+        let html = Html::parse_fragment(
+            r#"<div class="tgme_widget_message_wrap"><div class="tgme_widget_message" data-post="test/1">
+            <a class="tgme_widget_message_voice_player js-message_voice_player"><audio class="tgme_widget_message_voice" src="https://example.com/voice.ogg"></audio><time class="tgme_widget_message_voice_duration">0:14</time></a>
+            <a class="tgme_widget_message_roundvideo_player"><i class="tgme_widget_message_roundvideo_thumb" style="background-image:url('https://example.com/round.jpg')"></i><video class="tgme_widget_message_roundvideo" src="https://example.com/round.mp4"></video><time class="tgme_widget_message_roundvideo_duration">0:05</time></a>
+            <a class="tgme_widget_message_document_wrap" href="https://t.me/test/1"><i class="tgme_widget_message_document_icon audio"></i><div class="tgme_widget_message_document_title">Song</div></a>
+            <a class="tgme_widget_message_document_wrap" href="https://t.me/test/1"><i class="tgme_widget_message_document_icon"></i><div class="tgme_widget_message_document_title">report.pdf</div></a>
+            </div></div>"#,
+        );
+        let post = parse_post(html.select(&POST_SEL).next().unwrap()).unwrap();
+        let items = post.media_items.clone().unwrap();
+
+        let kinds: Vec<_> = items.iter().map(|m| m.kind).collect();
+        assert_eq!(
+            kinds,
+            [
+                MediaKind::Voice,
+                MediaKind::RoundVideo,
+                MediaKind::Audio,
+                MediaKind::Document
+            ]
+        );
+        assert_eq!(items[0].url, "https://example.com/voice.ogg");
+        assert_eq!(items[0].duration.as_deref(), Some("0:14"));
+        assert_eq!(items[1].url, "https://example.com/round.mp4");
+        assert_eq!(items[3].filename.as_deref(), Some("report.pdf"));
+        assert_eq!(post.media.as_ref().map(Vec::len), Some(4));
+        assert!(post.image_urls().is_empty());
+    }
+
+    #[test]
+    fn test_sponsored() {
+        let html = Html::parse_fragment(
+            r#"<div class="tgme_widget_message tgme_widget_message_sponsored" data-post="ad/1"></div>
+            <div class="tgme_widget_message sponsored_by_channel" data-post="test/1"></div>"#,
+        );
+        let messages: Vec<_> = html.select(&MSG_SEL).collect();
+
+        assert!(is_sponsored(messages[0]));
+        assert!(!is_sponsored(messages[1]));
+    }
+
+    #[test]
+    fn test_reply() {
+        let html = Html::parse_fragment(
+            r#"<div class="tgme_widget_message_wrap"><div class="tgme_widget_message" data-post="test/2">
+            <a class="tgme_widget_message_reply" href="https://t.me/test/1">
+                <div class="tgme_widget_message_author"><span class="tgme_widget_message_author_name">Test</span></div>
+                <div class="tgme_widget_message_metatext js-message_reply_text">Original post</div>
+            </a>
+            <div class="tgme_widget_message_text js-message_text">Reply</div>
+            </div></div>"#,
+        );
+        let post = parse_post(html.select(&POST_SEL).next().unwrap()).unwrap();
+
+        assert_eq!(
+            post.reply_to,
+            Some(Reply {
+                id: "test/1".to_string(),
+                text: Some("Original post".to_string()),
+            })
+        );
+        assert_eq!(post.text.as_deref().map(str::trim), Some("Reply"));
+    }
+
+    #[test]
+    fn test_parse_empty_channel() {
+        let page = parse_page(include_str!("fixtures/empty_channel.html"))
+            .unwrap()
+            .expect("channel not parsed");
+
+        assert_eq!(page.channel.id, "litehook_empty");
+        assert_eq!(page.channel.counters.subscribers_count, None);
+        assert!(page.posts.is_empty());
+
+        // Channel info without a username is a placeholder page
+        let placeholder = r#"<div class="tgme_channel_info"><div class="tgme_channel_info_header_title"><span>Deleted</span></div></div>"#;
+        assert!(parse_page(placeholder).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_interstitial() {
+        let html = include_str!("fixtures/interstitial.html");
+        assert!(parse_page(html).unwrap().is_none());
+        assert!(is_interstitial(html));
+
+        assert!(!is_interstitial(include_str!("fixtures/channel.html")));
+        // A user page has the same layout without subscribers
+        let user = html.replace("1 234 subscribers", "@litehook_test");
+        assert!(!is_interstitial(&user));
+    }
+
+    #[test]
+    fn test_parse_fixture() {
+        let page = parse_page(include_str!("fixtures/channel.html"))
+            .unwrap()
+            .expect("channel not parsed");
+
+        assert_eq!(page.channel.id, "litehook_test");
+        assert_eq!(page.channel.name.as_deref(), Some("Litehook Test"));
+        assert_eq!(page.channel.counters.subscribers_count, Some(1_800_000));
+
+        // The post without id is skipped
+        let ids: Vec<_> = page.posts.iter().map(|p| p.id.as_str()).collect();
+        assert_eq!(
+            ids,
+            ["litehook_test/10", "litehook_test/11", "litehook_test/12"]
+        );
+
+        let first = &page.posts[0];
+        assert_eq!(first.author.as_deref(), Some("Litehook Test"));
+        assert_eq!(first.views_count, Some(13_400));
+        assert_eq!(first.top_reaction(), Some(1200));
+        assert_eq!(first.date.as_deref(), Some("2026-03-04T12:00:00+00:00"));
+        assert_eq!(first.url, "https://t.me/litehook_test/10");
+        assert!(!first.edited);
+
+        let second = &page.posts[1];
+        assert!(second.edited);
+        assert_eq!(
+            second.text.as_deref().map(str::trim),
+            Some("Photo of the day")
+        );
+        assert_eq!(
+            second.media.as_deref(),
+            Some(&["https://cdn4.telesco.pe/file/photo.jpg".to_string()][..])
+        );
+        assert_eq!(
+            second.reply_to.as_ref().map(|r| r.id.as_str()),
+            Some("litehook_test/10")
+        );
+        // Reply author is not the post author
+        assert_eq!(second.author.as_deref(), Some("Litehook Test"));
+
+        // Signed post is attributed to the admin
+        assert_eq!(page.posts[2].author.as_deref(), Some("Jane Admin"));
+    }
+
+    #[test]
+    fn test_first_post_html() {
+        let html = first_post_html(include_str!("fixtures/channel.html")).unwrap();
+        assert!(html.contains(r#"data-post="litehook_test/10""#));
+        assert!(!html.contains("litehook_test/11"));
+    }
 }
@@ -1,38 +1,179 @@
-use anyhow::anyhow;
-use std::sync::Arc;
-use tokio::sync::{RwLock, mpsc};
-use tokio::time::{Duration, sleep};
+use chrono::Utc;
+use rand::RngExt;
+use regex::Regex;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{Notify, RwLock, mpsc, oneshot};
+use tokio::time::{Duration, Instant, sleep, sleep_until};
 use tokio_util::sync::CancellationToken;
 
+use crate::config::EnvHandle;
 use crate::events::Event;
-use crate::sources::{create_client, fetch_url};
+use crate::model::{Channel, ChannelCounters, Page, Post, WebhookTest, post_url};
+use crate::sources::client_pool::{ClientPool, PooledClient};
+use crate::sources::{FetchError, SourceDebug, SourceStatus, fetch_page, fetch_page_from};
+use crate::webhook::WebhookConfig;
+use crate::webhook::latency::DeliveryLatency;
+use crate::webhook::rate_limit::RateLimiter;
 
-use super::TelegramScraperConfig;
 use super::parser;
+use super::{CHANNEL_URL_PREFIX, TelegramScraperConfig};
+
+/// Number of consecutive invalid pages before the scraper gives up
+const MAX_INVALID_POLLS: u32 = 5;
+/// First retry delay after a network error, doubled on every failure
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+/// Most bytes of a page kept with `debug_capture`
+const MAX_CAPTURE_LEN: usize = 512 * 1024;
+/// Longest delay of the first poll, see [TelegramScraper::jitter_first_poll]
+const MAX_POLL_JITTER: Duration = Duration::from_secs(60);
+
+/// Page kept for [TelegramScraper::debug]
+struct Capture {
+    url: String,
+    fetched_at: chrono::DateTime<Utc>,
+    post_count: usize,
+    html: String,
+}
+
+/// Why a poll failed, decides if the scraper retries or stops
+#[derive(Debug)]
+pub enum PollError {
+    /// Network error, timeout or unexpected status, retried with backoff
+    Transient(FetchError),
+    /// HTTP 429, with the delay from `Retry-After` header if present
+    RateLimited(Option<Duration>),
+    /// Channel page was invalid [MAX_INVALID_POLLS] times in a row
+    InvalidChannel(String),
+    /// Page was fetched, but it's not a public channel, see [TelegramScraper::probe]
+    NotAChannel(String),
+    /// t.me showed the "View in Telegram" page instead of the messages,
+    /// retried with backoff without counting as an invalid page
+    Interstitial(String),
+    /// Anything else, stops the scraper
+    Fatal(anyhow::Error),
+}
+
+impl fmt::Display for PollError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Transient(e) => write!(f, "{e}"),
+            Self::RateLimited(_) => write!(f, "rate limited"),
+            Self::InvalidChannel(url) => write!(f, "channel is private or deleted: {url}"),
+            Self::NotAChannel(url) => {
+                write!(f, "not a public channel, like a user or group page: {url}")
+            }
+            Self::Interstitial(url) => write!(f, "t.me showed a join page instead of {url}"),
+            Self::Fatal(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for PollError {}
+
+impl From<FetchError> for PollError {
+    fn from(e: FetchError) -> Self {
+        match e {
+            FetchError::RateLimited(retry_after) => Self::RateLimited(retry_after),
+            e => Self::Transient(e),
+        }
+    }
+}
+
+/// Channel page returned by [TelegramScraper::fetch]
+struct Fetched {
+    /// Url after redirects
+    url: url::Url,
+    html: String,
+    /// `None` if the page has no channel
+    page: Option<Page>,
+    /// Page is the join page, even when fetched with a referer
+    interstitial: bool,
+}
 
 pub struct TelegramScraper {
     pub cfg: Arc<RwLock<TelegramScraperConfig>>,
 
     tx: mpsc::Sender<Event>,
-    client: RwLock<reqwest::Client>,
+    client: RwLock<PooledClient>,
+    clients: Arc<ClientPool>,
+    env: EnvHandle,
+    /// Timeout the current client was built with
+    client_timeout: Mutex<Duration>,
+    text_regex: Option<Regex>,
+    webhook_limiter: Option<Arc<RateLimiter>>,
+    webhook_latency: Arc<DeliveryLatency>,
+    invalid_polls: AtomicU32,
+    /// A redirect to another channel name was already logged
+    rename_warned: AtomicBool,
+    /// Consecutive polls that failed with a [PollError::Transient] or
+    /// [PollError::Interstitial] error
+    failed_polls: AtomicU32,
+    status: Mutex<SourceStatus>,
+    /// Last fetched page, only with `debug_capture`
+    capture: Mutex<Option<Capture>>,
+    poll_now: Notify,
+    /// Delay the first poll, set for sources started with the server
+    first_poll_jitter: AtomicBool,
     shutdown: CancellationToken,
 }
 
 impl TelegramScraper {
-    pub async fn new(cfg: TelegramScraperConfig, tx: mpsc::Sender<Event>) -> anyhow::Result<Self> {
+    pub async fn new(
+        cfg: TelegramScraperConfig,
+        tx: mpsc::Sender<Event>,
+        clients: Arc<ClientPool>,
+        env: EnvHandle,
+    ) -> anyhow::Result<Self> {
         tracing::info!("initializing listener {}", cfg.id);
-        let client = create_client().await?;
+        let opts = cfg.client_options();
+        let client = clients.get(&opts).await?;
+        let text_regex = cfg.text_regex()?;
+        let webhook_limiter = cfg
+            .webhook_rate_limit
+            .map(|limit| Arc::new(RateLimiter::new(limit)));
         Ok(Self {
             cfg: Arc::new(RwLock::new(cfg)),
             tx,
             client: RwLock::new(client),
+            clients,
+            client_timeout: Mutex::new(opts.timeout(&env.get())),
+            env,
+            text_regex,
+            webhook_limiter,
+            webhook_latency: Arc::default(),
+            invalid_polls: AtomicU32::new(0),
+            rename_warned: AtomicBool::new(false),
+            failed_polls: AtomicU32::new(0),
+            status: Mutex::new(SourceStatus::default()),
+            capture: Mutex::new(None),
+            poll_now: Notify::new(),
+            first_poll_jitter: AtomicBool::new(false),
             shutdown: CancellationToken::new(),
         })
     }
 
     pub async fn run(&self) -> anyhow::Result<()> {
+        // Webhooks queued before a restart
+        self.flush_queued().await?;
+
+        if self.first_poll_jitter.load(Ordering::Relaxed) {
+            let interval = u64::try_from(self.cfg.read().await.poll_interval).unwrap_or_default();
+            let jitter = Duration::from_secs(interval)
+                .min(MAX_POLL_JITTER)
+                .mul_f64(rand::rng().random());
+            tracing::debug!("first poll in {}ms", jitter.as_millis());
+
+            tokio::select! {
+                _ = self.shutdown.cancelled() => return Ok(()),
+                _ = sleep(jitter) => {}
+                _ = self.poll_now.notified() => tracing::info!("polling on request"),
+            }
+        }
+
         loop {
-            let channel_url = self.cfg.read().await.channel_url.clone();
+            let channel_url = self.cfg.read().await.page_url(None)?;
 
             tokio::select! {
                 // Shutdown handler
@@ -41,7 +182,7 @@ impl TelegramScraper {
                     return Ok(());
                 }
 
-                res = self.poll_cycle(&channel_url) => { res? }
+                res = self.poll_cycle(channel_url.as_str()) => { res? }
             }
         }
     }
@@ -53,36 +194,491 @@ impl TelegramScraper {
         Ok(())
     }
 
+    /// Delay the first poll by a random part of the poll interval, at
+    /// most [MAX_POLL_JITTER], so sources started together don't poll at
+    /// the same time. Only has an effect before [TelegramScraper::run].
+    pub fn jitter_first_poll(&self) {
+        self.first_poll_jitter.store(true, Ordering::Relaxed);
+    }
+
+    /// Wake up the scraper, skipping the rest of the current delay.
+    pub fn poll_now(&self) {
+        self.poll_now.notify_one();
+    }
+
+    pub fn status(&self) -> SourceStatus {
+        let mut status = self.status.lock().expect("status lock poisoned").clone();
+        status.webhook_latency = self.webhook_latency.summary();
+        status.consecutive_failures =
+            self.failed_polls.load(Ordering::Relaxed) + self.invalid_polls.load(Ordering::Relaxed);
+        status
+    }
+
     /// Poll URL with sleep
+    ///
+    /// When rate limited, sleeps for at least the `Retry-After` delay.
+    /// Network errors are retried with a backoff starting at [RETRY_DELAY]
+    /// up to the poll interval, other errors stop the scraper.
     async fn poll_cycle(&self, url: &str) -> anyhow::Result<()> {
         let interval = self.cfg.read().await.poll_interval;
+        let mut delay = Duration::from_secs(
+            u64::try_from(interval)
+                .unwrap_or(600)
+                .max(self.env.get().min_poll_interval),
+        );
+
+        // Global timeout can change at runtime
+        let opts = self.cfg.read().await.client_options();
+        let timeout = opts.timeout(&self.env.get());
+        if *self.client_timeout.lock().expect("timeout lock poisoned") != timeout {
+            tracing::info!("request timeout changed, recreating client");
+            self.recreate_client().await?;
+        } else if self.clients.is_shared(&opts) {
+            // Shared client may have been renewed by another source
+            *self.client.write().await = self.clients.get(&opts).await?;
+        }
+
         match self.poll(url).await {
-            Ok(_) => {}
-            Err(e) => {
-                tracing::warn!("poll failed, retrying: {e}");
-                *self.client.write().await = create_client().await?;
-                self.poll(url).await?;
+            Ok(()) => self.failed_polls.store(0, Ordering::Relaxed),
+            Err(PollError::RateLimited(retry_after)) => {
+                delay = delay.max(retry_after.unwrap_or_default());
+                tracing::warn!("rate limited, next poll in {}s", delay.as_secs());
+            }
+            Err(e @ (PollError::Transient(_) | PollError::Interstitial(_))) => {
+                let failures = self.failed_polls.fetch_add(1, Ordering::Relaxed) + 1;
+                delay = delay.min(RETRY_DELAY * 2u32.pow(failures.min(10) - 1));
+                tracing::warn!(
+                    "poll failed {failures} times in a row, retrying in {}s: {e}",
+                    delay.as_secs()
+                );
+                // New client picks another proxy, the join page is shown
+                // regardless of the proxy, so it's not counted against it
+                if matches!(e, PollError::Transient(_)) {
+                    self.clients.proxy_failed(&*self.client.read().await);
+                }
+                self.recreate_client().await?;
+            }
+            Err(e) => return Err(e.into()),
+        }
+
+        // Send the queued webhooks right when quiet hours end
+        let wake = Instant::now() + delay;
+        let quiet_end = self
+            .cfg
+            .read()
+            .await
+            .quiet_hours
+            .as_ref()
+            .and_then(|q| q.window_end(Utc::now()))
+            .and_then(|end| (end - Utc::now()).to_std().ok())
+            .map(|left| Instant::now() + left)
+            .filter(|end| *end < wake);
+        if let Some(end) = quiet_end {
+            tokio::select! {
+                _ = sleep_until(end) => self.flush_queued().await?,
+                _ = self.poll_now.notified() => {
+                    tracing::info!("polling on request");
+                    return Ok(());
+                }
             }
         }
-        sleep(Duration::from_secs(interval.try_into().unwrap_or(600))).await;
+
+        tokio::select! {
+            _ = sleep_until(wake) => {}
+            _ = self.poll_now.notified() => tracing::info!("polling on request"),
+        }
+        Ok(())
+    }
+
+    /// Webhook config of the current source config with the shared
+    /// rate limiter and latency stats.
+    async fn webhook(&self) -> anyhow::Result<WebhookConfig> {
+        let mut webhook = self.cfg.read().await.webhook()?;
+        webhook.rate_limit = self.webhook_limiter.clone();
+        webhook.latency = Some(Arc::clone(&self.webhook_latency));
+        Ok(webhook)
+    }
+
+    /// Send the webhooks queued during quiet hours or after the webhook
+    /// budget was spent, unless quiet hours still last.
+    async fn flush_queued(&self) -> anyhow::Result<()> {
+        let webhook = self.webhook().await?;
+        if let Some(end) = webhook
+            .quiet_hours
+            .as_ref()
+            .and_then(|q| q.window_end(Utc::now()))
+        {
+            tracing::debug!("quiet hours until {end}");
+            return Ok(());
+        }
+        self.tx.send(Event::FlushQueued(webhook)).await?;
+        Ok(())
+    }
+
+    /// Fetch and parse a page, waiting for the rate limit of a shared
+    /// client first.
+    ///
+    /// When t.me answers with the join page, it's fetched once more
+    /// coming from the channel's t.me page, which sometimes gets the
+    /// message list. Cookies can be added with the `headers` option.
+    async fn fetch(&self, url: &str) -> Result<Fetched, PollError> {
+        let client = self.client.read().await.clone();
+        client.acquire().await;
+        let (final_url, html) = fetch_page(&client.client, url).await?;
+        let page = parser::parse_page(&html).map_err(PollError::Fatal)?;
+        // Only pages without a channel can be the join page
+        if page.is_some() || !parser::is_interstitial(&html) {
+            return Ok(Fetched {
+                url: final_url,
+                html,
+                page,
+                interstitial: false,
+            });
+        }
+
+        tracing::debug!("got join page for {url}, retrying with referer");
+        let channel_url = self.cfg.read().await.channel_url.clone();
+        let referer = format!(
+            "https://t.me/{}",
+            channel_url.trim_start_matches(CHANNEL_URL_PREFIX)
+        );
+        client.acquire().await;
+        let (final_url, html) = fetch_page_from(&client.client, url, &referer).await?;
+        let page = parser::parse_page(&html).map_err(PollError::Fatal)?;
+        let interstitial = page.is_none() && parser::is_interstitial(&html);
+        Ok(Fetched {
+            url: final_url,
+            html,
+            page,
+            interstitial,
+        })
+    }
+
+    async fn recreate_client(&self) -> anyhow::Result<()> {
+        let opts = self.cfg.read().await.client_options();
+        *self.client.write().await = self.clients.renew(&opts).await?;
+        *self.client_timeout.lock().expect("timeout lock poisoned") = opts.timeout(&self.env.get());
         Ok(())
     }
 
     /// Poll URL, parses the channel info and posts,
     /// stores state in database, and sends webhook notifications.
-    async fn poll(&self, url: &str) -> anyhow::Result<()> {
-        let client = self.client.read().await;
-        let html = fetch_url(&client, url).await?;
-        let page = match parser::parse_page(&html)? {
-            Some(p) => p,
-            None => return Err(anyhow!("invalid channel: {}", url)),
+    ///
+    /// Fails after [MAX_INVALID_POLLS] consecutive pages without a channel,
+    /// which means the channel is private or deleted.
+    async fn poll(&self, url: &str) -> Result<(), PollError> {
+        self.status
+            .lock()
+            .expect("status lock poisoned")
+            .last_polled_at = Some(Utc::now());
+
+        let fetched = self.fetch(url).await?;
+        let html = fetched.html;
+        tracing::trace!("fetched {} bytes from {url}", html.len());
+        self.check_renamed(&fetched.url).await?;
+        let mut page = match fetched.page {
+            Some(p) => {
+                self.invalid_polls.store(0, Ordering::Relaxed);
+                p
+            }
+            None if fetched.interstitial => {
+                return Err(PollError::Interstitial(url.to_string()));
+            }
+            None => {
+                let count = self.invalid_polls.fetch_add(1, Ordering::Relaxed) + 1;
+                if count >= MAX_INVALID_POLLS {
+                    return Err(PollError::InvalidChannel(url.to_string()));
+                }
+                tracing::warn!("invalid channel {url} ({count}/{MAX_INVALID_POLLS})");
+                return Ok(());
+            }
         };
 
-        let webhook_url = self.cfg.read().await.webhook_url.clone();
+        for post in &page.posts {
+            tracing::debug!("parsed post {}", post.id);
+        }
+        if self.cfg.read().await.debug_capture {
+            let mut html = html;
+            if html.len() > MAX_CAPTURE_LEN {
+                let end = html.floor_char_boundary(MAX_CAPTURE_LEN);
+                html.truncate(end);
+            }
+            *self.capture.lock().expect("capture lock poisoned") = Some(Capture {
+                url: url.to_string(),
+                fetched_at: Utc::now(),
+                post_count: page.posts.len(),
+                html,
+            });
+        }
+
+        // Filter posts by text
+        if let Some(re) = &self.text_regex {
+            page.posts
+                .retain(|p| p.text.as_deref().is_some_and(|t| re.is_match(t)));
+        }
+
+        let webhook = self.webhook().await.map_err(PollError::Fatal)?;
+        self.flush_queued().await.map_err(PollError::Fatal)?;
         self.tx
-            .send(Event::NewPosts(Box::new(page), webhook_url))
-            .await?;
+            .send(Event::NewPosts(Box::new(page), webhook))
+            .await
+            .map_err(|e| PollError::Fatal(e.into()))?;
+
+        self.status
+            .lock()
+            .expect("status lock poisoned")
+            .last_success_at = Some(Utc::now());
 
         Ok(())
     }
+
+    /// Handle a redirect of the channel page to another channel name.
+    ///
+    /// With `follow_renames` the scraper switches to the new name and
+    /// the stored config is updated, otherwise a warning is logged once.
+    async fn check_renamed(&self, final_url: &url::Url) -> Result<(), PollError> {
+        let Some(new) = final_url
+            .path_segments()
+            .and_then(|mut s| s.rfind(|s| !s.is_empty()))
+        else {
+            return Ok(());
+        };
+        let cfg = self.cfg.read().await;
+        let old = cfg.channel_url.trim_start_matches(CHANNEL_URL_PREFIX);
+        if final_url.host_str() != Some("t.me") || new.eq_ignore_ascii_case(old) {
+            return Ok(());
+        }
+
+        if !cfg.follow_renames {
+            if !self.rename_warned.swap(true, Ordering::Relaxed) {
+                tracing::warn!(
+                    "channel {old} redirects to {new}, update channel_url or set follow_renames"
+                );
+            }
+            return Ok(());
+        }
+
+        tracing::warn!("channel {old} was renamed to {new}, updating channel_url");
+        let (source, channel_url) = (cfg.id.clone(), format!("{CHANNEL_URL_PREFIX}{new}"));
+        drop(cfg);
+        self.cfg.write().await.channel_url = channel_url.clone();
+        self.tx
+            .send(Event::ChannelRenamed {
+                source,
+                channel_url,
+            })
+            .await
+            .map_err(|e| PollError::Fatal(e.into()))
+    }
+
+    /// Fetch the channel page once, failing with [PollError::NotAChannel]
+    /// when it has no channel.
+    ///
+    /// Fetch errors and the join page are only logged, polling retries
+    /// them later.
+    pub async fn probe(&self) -> Result<(), PollError> {
+        let url = self
+            .cfg
+            .read()
+            .await
+            .page_url(None)
+            .map_err(PollError::Fatal)?;
+        let fetched = match self.fetch(url.as_str()).await {
+            Ok(fetched) => fetched,
+            Err(e @ PollError::Fatal(_)) => return Err(e),
+            Err(e) => {
+                tracing::warn!("failed to check channel {url}: {e}");
+                return Ok(());
+            }
+        };
+
+        match fetched.page {
+            Some(_) => Ok(()),
+            None if fetched.interstitial => {
+                tracing::warn!("{}", PollError::Interstitial(url.to_string()));
+                Ok(())
+            }
+            None => Err(PollError::NotAChannel(url.to_string())),
+        }
+    }
+
+    /// Last page fetched with `debug_capture`, with the raw HTML of
+    /// its first post if `html` is set.
+    pub async fn debug(&self, html: bool) -> anyhow::Result<SourceDebug> {
+        if !self.cfg.read().await.debug_capture {
+            anyhow::bail!("debug_capture is not enabled for this source");
+        }
+        let capture = self.capture.lock().expect("capture lock poisoned");
+        let Some(capture) = capture.as_ref() else {
+            anyhow::bail!("no page was fetched yet");
+        };
+
+        Ok(SourceDebug {
+            url: capture.url.clone(),
+            fetched_at: capture.fetched_at,
+            post_count: capture.post_count,
+            first_post_html: html
+                .then(|| parser::first_post_html(&capture.html))
+                .flatten(),
+        })
+    }
+
+    /// Walk back through older pages using `?before=<id>` pagination.
+    ///
+    /// Posts are stored without sending webhooks. Pages that are already
+    /// stored are walked past, so older posts of a running source are
+    /// still backfilled. Stops early at the first post of the channel.
+    pub async fn backfill(&self, pages: u32) -> anyhow::Result<usize> {
+        let channel_url = self.cfg.read().await.channel_url.clone();
+        let mut before: Option<u64> = None;
+        let mut total = 0;
+
+        for _ in 0..pages {
+            let url = self.cfg.read().await.page_url(before)?;
+
+            let fetched = self.fetch(url.as_str()).await?;
+            tracing::trace!("fetched {} bytes from {url}", fetched.html.len());
+            let Some(page) = fetched.page else {
+                if fetched.interstitial {
+                    anyhow::bail!(PollError::Interstitial(url.to_string()));
+                }
+                break;
+            };
+
+            // Oldest post id is the cursor for the next page
+            let oldest = page
+                .posts
+                .iter()
+                .filter_map(|p| p.id.rsplit_once('/')?.1.parse::<u64>().ok())
+                .min();
+
+            let (tx, rx) = oneshot::channel();
+            self.tx.send(Event::Backfill(Box::new(page), tx)).await?;
+            total += rx.await?;
+
+            match oldest {
+                Some(id) if id > 1 && before.is_none_or(|b| id < b) => before = Some(id),
+                _ => break,
+            }
+            sleep(Duration::from_secs(1)).await;
+        }
+
+        tracing::info!("backfilled {total} posts from {channel_url}");
+        Ok(total)
+    }
+
+    /// Send a sample post to the webhook urls, even in dry run.
+    pub async fn test_webhook(&self) -> anyhow::Result<Vec<WebhookTest>> {
+        let (webhook, name) = {
+            let cfg = self.cfg.read().await;
+            let name = cfg.channel_url.trim_start_matches(CHANNEL_URL_PREFIX);
+            (cfg.webhook()?, name.to_string())
+        };
+
+        let channel = Channel {
+            id: name.clone(),
+            name: Some(name.clone()),
+            image: None,
+            counters: ChannelCounters::default(),
+            description: None,
+        };
+        let id = format!("{name}/0");
+        let post = Post {
+            url: post_url(&id),
+            id,
+            text: Some("Test webhook from litehook".to_string()),
+            date: Some(Utc::now().to_rfc3339()),
+            ..Default::default()
+        };
+        let payload = webhook
+            .payloads(&channel, &[post])?
+            .pop()
+            .ok_or_else(|| anyhow::anyhow!("no payload to send"))?;
+
+        let (tx, rx) = oneshot::channel();
+        self.tx
+            .send(Event::TestWebhook(webhook, payload, tx))
+            .await?;
+        Ok(rx.await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Db;
+    use crate::events::EventHandler;
+    use axum::extract::Query;
+    use std::collections::HashMap;
+    use tokio::sync::broadcast;
+
+    const CHANNEL: &str = include_str!("fixtures/channel.html");
+
+    /// Serve the fixture as the newest page, and the same posts
+    /// numbered 7 to 9 before post 10.
+    async fn serve_channel() -> String {
+        let app = axum::Router::new().route(
+            "/s/litehook_test",
+            axum::routing::get(|Query(q): Query<HashMap<String, u64>>| async move {
+                let html = match q.get("before") {
+                    None => CHANNEL.to_string(),
+                    Some(10) => CHANNEL
+                        .replace("litehook_test/10", "litehook_test/7")
+                        .replace("litehook_test/11", "litehook_test/8")
+                        .replace("litehook_test/12", "litehook_test/9"),
+                    Some(_) => include_str!("fixtures/empty_channel.html").to_string(),
+                };
+                axum::response::Html(html)
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        format!("http://{addr}/s/litehook_test")
+    }
+
+    #[tokio::test]
+    async fn test_backfill_past_stored_page() {
+        let db = Db::new(":memory:").await.unwrap();
+        for post in parser::parse_page(CHANNEL).unwrap().unwrap().posts {
+            db.insert_post(&post).await.unwrap();
+        }
+        let (tx, rx) = mpsc::channel(10);
+        let handler = EventHandler::new(
+            rx,
+            db.clone(),
+            Arc::default(),
+            broadcast::channel(10).0,
+            Arc::default(),
+            EnvHandle::default(),
+        );
+        tokio::spawn(handler.run());
+
+        let cfg: TelegramScraperConfig = serde_json::from_value(serde_json::json!({
+            "id": "litehook_test",
+            "channel_url": serve_channel().await,
+            "poll_interval": 60,
+            "webhook_url": "https://example.com/hook",
+        }))
+        .unwrap();
+        let scraper = TelegramScraper::new(cfg, tx, Arc::default(), EnvHandle::default())
+            .await
+            .unwrap();
+
+        assert_eq!(scraper.backfill(5).await.unwrap(), 3);
+        assert!(db.get_posts("litehook_test/7").await.unwrap().is_some());
+    }
+
+    #[test]
+    fn test_poll_error_from_fetch() {
+        let retry_after = Some(Duration::from_secs(30));
+        assert!(matches!(
+            PollError::from(FetchError::RateLimited(retry_after)),
+            PollError::RateLimited(d) if d == retry_after
+        ));
+        assert!(matches!(
+            PollError::from(FetchError::Status(reqwest::StatusCode::BAD_GATEWAY)),
+            PollError::Transient(_)
+        ));
+    }
 }
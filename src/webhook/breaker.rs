@@ -0,0 +1,155 @@
+//! Circuit breaker for webhook targets
+//!
+//! After [FAILURE_THRESHOLD] consecutive failed requests to a url, requests
+//! to it are short-circuited for [COOLDOWN]. After the cooldown a single
+//! trial request is let through, success closes the circuit again.
+
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Consecutive failures before the circuit opens
+const FAILURE_THRESHOLD: u32 = 5;
+/// Time to wait before trying an open target again
+const COOLDOWN: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+/// Status of a single webhook target
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct TargetStatus {
+    pub url: String,
+    pub state: CircuitState,
+    pub failures: u32,
+}
+
+#[derive(Debug, Default)]
+struct Target {
+    failures: u32,
+    opened_at: Option<Instant>,
+    trial: bool,
+}
+
+impl Target {
+    fn state(&self) -> CircuitState {
+        match self.opened_at {
+            None => CircuitState::Closed,
+            Some(_) if self.trial => CircuitState::HalfOpen,
+            Some(_) => CircuitState::Open,
+        }
+    }
+}
+
+/// Circuit breakers of all webhook targets, shared across sources
+#[derive(Debug, Default)]
+pub struct CircuitBreakers {
+    targets: Mutex<HashMap<String, Target>>,
+}
+
+impl CircuitBreakers {
+    /// Check if a request to url is allowed.
+    ///
+    /// Lets a single trial request through once the cooldown has passed.
+    pub fn allow(&self, url: &str) -> bool {
+        let mut targets = self.targets.lock().expect("breaker lock poisoned");
+        let Some(target) = targets.get_mut(url) else {
+            return true;
+        };
+
+        match target.opened_at {
+            Some(opened) if opened.elapsed() >= COOLDOWN => {
+                // Restart the cooldown so only one trial runs at a time
+                target.opened_at = Some(Instant::now());
+                target.trial = true;
+                true
+            }
+            Some(_) => false,
+            None => true,
+        }
+    }
+
+    /// Check if requests to url are paused, without starting a trial.
+    pub fn is_open(&self, url: &str) -> bool {
+        let targets = self.targets.lock().expect("breaker lock poisoned");
+        targets
+            .get(url)
+            .and_then(|t| t.opened_at)
+            .is_some_and(|opened| opened.elapsed() < COOLDOWN)
+    }
+
+    pub fn record_success(&self, url: &str) {
+        let mut targets = self.targets.lock().expect("breaker lock poisoned");
+        if let Some(target) = targets.remove(url)
+            && target.opened_at.is_some()
+        {
+            tracing::info!("webhook {url} recovered, closing circuit");
+        }
+    }
+
+    pub fn record_failure(&self, url: &str) {
+        let mut targets = self.targets.lock().expect("breaker lock poisoned");
+        let target = targets.entry(url.to_string()).or_default();
+        target.failures += 1;
+
+        if target.trial || (target.opened_at.is_none() && target.failures >= FAILURE_THRESHOLD) {
+            tracing::warn!(
+                "webhook {url} failed {} times, pausing for {}s",
+                target.failures,
+                COOLDOWN.as_secs()
+            );
+            target.opened_at = Some(Instant::now());
+            target.trial = false;
+        }
+    }
+
+    /// Status of all targets with recent failures.
+    pub fn status(&self) -> Vec<TargetStatus> {
+        let targets = self.targets.lock().expect("breaker lock poisoned");
+        targets
+            .iter()
+            .map(|(url, t)| TargetStatus {
+                url: url.clone(),
+                state: t.state(),
+                failures: t.failures,
+            })
+            .collect()
+    }
+
+    /// Number of targets that are not closed.
+    pub fn open_count(&self) -> usize {
+        let targets = self.targets.lock().expect("breaker lock poisoned");
+        targets.values().filter(|t| t.opened_at.is_some()).count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_breaker_opens() {
+        let breakers = CircuitBreakers::default();
+        let url = "https://example.com/hook";
+
+        for _ in 0..FAILURE_THRESHOLD {
+            assert!(breakers.allow(url));
+            breakers.record_failure(url);
+        }
+
+        assert!(!breakers.allow(url));
+        assert!(breakers.is_open(url));
+        assert_eq!(breakers.open_count(), 1);
+
+        breakers.record_success(url);
+        assert!(breakers.allow(url));
+        assert_eq!(breakers.open_count(), 0);
+    }
+}
@@ -0,0 +1,92 @@
+//! Rolling webhook delivery latency of a source
+//!
+//! Only the latest successful deliveries are kept, so the summary
+//! follows recent changes of the receiving servers.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Latency of recent deliveries, shown in the source status
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct LatencySummary {
+    /// Number of deliveries the summary is based on
+    pub samples: usize,
+    pub avg_ms: u64,
+    pub p95_ms: u64,
+    /// Average attempts per delivery, above 1 when requests were retried
+    pub avg_attempts: f64,
+    pub max_attempts: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct DeliveryLatency {
+    /// Duration of the successful request and the number of attempts
+    samples: Mutex<VecDeque<(Duration, u64)>>,
+}
+
+impl DeliveryLatency {
+    /// Most deliveries kept
+    const SAMPLES: usize = 100;
+
+    /// Record a successful delivery.
+    pub fn record(&self, latency: Duration, attempts: u64) {
+        let mut samples = self.samples.lock().expect("latency lock poisoned");
+        if samples.len() == Self::SAMPLES {
+            samples.pop_front();
+        }
+        samples.push_back((latency, attempts));
+    }
+
+    /// Summary of the kept deliveries, `None` before the first one.
+    pub fn summary(&self) -> Option<LatencySummary> {
+        let samples = self.samples.lock().expect("latency lock poisoned");
+        if samples.is_empty() {
+            return None;
+        }
+
+        let mut latencies: Vec<Duration> = samples.iter().map(|(latency, _)| *latency).collect();
+        latencies.sort();
+        let p95 = latencies[(latencies.len() * 95).div_ceil(100) - 1];
+        let total: Duration = latencies.iter().sum();
+        let attempts: u64 = samples.iter().map(|(_, attempts)| attempts).sum();
+
+        Some(LatencySummary {
+            samples: samples.len(),
+            avg_ms: (total / samples.len() as u32).as_millis() as u64,
+            p95_ms: p95.as_millis() as u64,
+            avg_attempts: attempts as f64 / samples.len() as f64,
+            max_attempts: samples
+                .iter()
+                .map(|(_, attempts)| *attempts)
+                .max()
+                .unwrap_or_default(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary() {
+        let latency = DeliveryLatency::default();
+        assert!(latency.summary().is_none());
+
+        for ms in 1..=100 {
+            latency.record(Duration::from_millis(ms), 1);
+        }
+        latency.record(Duration::from_millis(1000), 3);
+
+        // The first sample was dropped
+        let summary = latency.summary().unwrap();
+        assert_eq!(summary.samples, 100);
+        assert_eq!(summary.p95_ms, 96);
+        assert_eq!(summary.max_attempts, 3);
+        assert!((summary.avg_attempts - 1.02).abs() < 1e-9);
+    }
+}
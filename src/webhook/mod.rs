@@ -0,0 +1,356 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::model::{
+    Channel, ChannelChangedPayload, ChannelInfo, Post, StatsPayload, WebhookEvent, WebhookPayload,
+};
+
+pub mod breaker;
+pub mod latency;
+pub mod quiet_hours;
+pub mod rate_limit;
+pub mod slack;
+pub mod telegram_bot;
+pub mod template;
+
+use self::latency::DeliveryLatency;
+use self::quiet_hours::QuietHours;
+use self::rate_limit::RateLimiter;
+use self::template::Template;
+
+/// Format of the webhook payload
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookFormat {
+    /// Litehook JSON payload, see [WebhookPayload]
+    #[default]
+    Json,
+    /// Slack incoming webhook with blocks
+    Slack,
+    /// Telegram Bot API, posts are sent to a chat, see [telegram_bot]
+    TelegramBot,
+}
+
+/// Authentication of webhook requests, replaces the `x-secret` header
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum WebhookAuth {
+    /// Send the global `webhook_secret` as `x-secret` header
+    #[default]
+    None,
+    /// Custom header
+    Header { name: String, value: String },
+    /// HTTP basic auth
+    Basic { user: String, pass: String },
+    /// `Authorization: Bearer <token>` header
+    Bearer { token: String },
+}
+
+impl WebhookAuth {
+    /// Check that the header name and values can be sent.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        match self {
+            Self::None => {}
+            Self::Header { name, value } => {
+                reqwest::header::HeaderName::from_bytes(name.as_bytes())
+                    .map_err(|_| anyhow::anyhow!("invalid webhook_auth header name {name:?}"))?;
+                reqwest::header::HeaderValue::from_str(value)
+                    .map_err(|_| anyhow::anyhow!("invalid webhook_auth header value"))?;
+            }
+            Self::Basic { user, .. } => {
+                if user.is_empty() {
+                    anyhow::bail!("webhook_auth user must not be empty");
+                }
+            }
+            Self::Bearer { token } => {
+                if token.is_empty() {
+                    anyhow::bail!("webhook_auth token must not be empty");
+                }
+                reqwest::header::HeaderValue::from_str(&format!("Bearer {token}"))
+                    .map_err(|_| anyhow::anyhow!("invalid webhook_auth token"))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Add the auth to a request, or `secret` as `x-secret` without one.
+    pub fn apply(&self, req: reqwest::RequestBuilder, secret: &str) -> reqwest::RequestBuilder {
+        match self {
+            Self::None => req.header("x-secret", secret),
+            Self::Header { name, value } => req.header(name.as_str(), value.as_str()),
+            Self::Basic { user, pass } => req.basic_auth(user, Some(pass)),
+            Self::Bearer { token } => req.bearer_auth(token),
+        }
+    }
+}
+
+/// Body of a webhook request, serialized when queued during quiet hours
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Payload {
+    Json(serde_json::Value),
+    /// Rendered raw template, sent as is
+    Raw {
+        body: String,
+        content_type: String,
+    },
+    /// Telegram Bot API call, sent to the `method` url of the bot
+    Bot {
+        method: Cow<'static, str>,
+        params: serde_json::Value,
+    },
+}
+
+impl fmt::Display for Payload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Json(value) => write!(f, "{value}"),
+            Self::Raw { body, content_type } => write!(f, "({content_type}) {body}"),
+            Self::Bot { method, params } => write!(f, "({method}) {params}"),
+        }
+    }
+}
+
+/// Bodies at least this large are gzipped with `webhook_compress`
+pub const COMPRESS_MIN_LEN: usize = 8 * 1024;
+
+/// Gzip a request body.
+pub fn gzip(body: &[u8]) -> std::io::Result<Vec<u8>> {
+    use flate2::{Compression, write::GzEncoder};
+    use std::io::Write;
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(body)?;
+    encoder.finish()
+}
+
+/// Webhook delivery settings of a source
+#[derive(Debug, Clone, Default)]
+pub struct WebhookConfig {
+    /// Id of the source, payloads queued during quiet hours are stored under it
+    pub source: String,
+    pub urls: Vec<String>,
+    pub format: WebhookFormat,
+    /// Post fields kept in litehook JSON payloads, all when `None`
+    pub fields: Option<Vec<String>>,
+    /// Authentication of JSON and raw payload requests
+    pub auth: WebhookAuth,
+    /// Gzip large JSON and raw payload bodies
+    pub compress: bool,
+    /// Overrides `format`, rendered once per post
+    pub template: Option<Template>,
+    /// Don't send sponsored posts
+    pub skip_sponsored: bool,
+    /// Send stats updates when views or reactions of known posts change
+    pub track_stats: bool,
+    /// Only store posts of a channel that was never polled before
+    pub seed_silently: bool,
+    /// Don't send posts with the content of a recent post under another id
+    pub dedup_by_content: bool,
+    /// Most new posts of a single poll sent, older ones are only stored
+    pub catchup_limit: Option<usize>,
+    /// Log payloads instead of sending them
+    pub dry_run: bool,
+    /// Queue payloads during this daily window and send them once it ends
+    pub quiet_hours: Option<QuietHours>,
+    /// Limiter shared by all deliveries of the source
+    pub rate_limit: Option<Arc<RateLimiter>>,
+    /// Latency of successful deliveries of the source
+    pub latency: Option<Arc<DeliveryLatency>>,
+    /// Wait between retries of a failed request, 1 second when `None`
+    pub retry_delay: Option<Duration>,
+}
+
+impl WebhookConfig {
+    /// Build payloads for new posts of the channel.
+    ///
+    /// Returns one payload for all posts, one per post with a template,
+    /// or the Bot API calls of every post.
+    pub fn payloads(&self, channel: &Channel, new_posts: &[Post]) -> anyhow::Result<Vec<Payload>> {
+        if let Some(template) = &self.template {
+            return new_posts
+                .iter()
+                .map(|post| template.render(Some(channel), post))
+                .collect();
+        }
+
+        Ok(vec![Payload::Json(match self.format {
+            WebhookFormat::Json => {
+                let mut payload = serde_json::to_value(WebhookPayload {
+                    event: WebhookEvent::NewPosts,
+                    channel,
+                    new_posts,
+                })?;
+                if let Some(posts) = payload["new_posts"].as_array_mut() {
+                    posts.iter_mut().for_each(|post| self.select_fields(post));
+                }
+                payload
+            }
+            WebhookFormat::Slack => {
+                slack::payload(channel.name.as_deref().unwrap_or(&channel.id), new_posts)
+            }
+            WebhookFormat::TelegramBot => {
+                return Ok(new_posts.iter().flat_map(bot_payloads).collect());
+            }
+        })])
+    }
+
+    /// Build payloads for a single post without a channel page.
+    pub fn post_payloads(&self, post: &Post) -> anyhow::Result<Vec<Payload>> {
+        if let Some(template) = &self.template {
+            return Ok(vec![template.render(None, post)?]);
+        }
+
+        Ok(vec![Payload::Json(match self.format {
+            WebhookFormat::Json => {
+                let mut payload = serde_json::to_value(post)?;
+                self.select_fields(&mut payload);
+                payload
+            }
+            WebhookFormat::Slack => slack::payload(&post.id, std::slice::from_ref(post)),
+            WebhookFormat::TelegramBot => return Ok(bot_payloads(post).collect()),
+        })])
+    }
+
+    /// Build channel info change payload, a plain message for Slack and
+    /// Telegram bots and litehook JSON otherwise.
+    pub fn channel_payload(
+        &self,
+        channel: &str,
+        old: &ChannelInfo,
+        new: &ChannelInfo,
+    ) -> anyhow::Result<Payload> {
+        match self.format {
+            _ if self.template.is_some() => {}
+            WebhookFormat::Slack => {
+                return Ok(Payload::Json(slack::channel_changed(channel, old, new)));
+            }
+            WebhookFormat::TelegramBot => {
+                let (method, params) = telegram_bot::channel_changed(channel, old, new);
+                return Ok(Payload::Bot {
+                    method: method.into(),
+                    params,
+                });
+            }
+            WebhookFormat::Json => {}
+        }
+
+        Ok(Payload::Json(serde_json::to_value(
+            ChannelChangedPayload {
+                event: WebhookEvent::ChannelInfoChanged,
+                channel,
+                old,
+                new,
+            },
+        )?))
+    }
+
+    /// Remove the fields of a serialized [Post] that are not in `fields`.
+    fn select_fields(&self, post: &mut serde_json::Value) {
+        if let (Some(fields), Some(post)) = (&self.fields, post.as_object_mut()) {
+            post.retain(|key, _| fields.contains(key));
+        }
+    }
+
+    /// Build stats update payload, always in litehook JSON format.
+    pub fn stats_payload(&self, channel: &str, posts: &[Post]) -> anyhow::Result<Payload> {
+        Ok(Payload::Json(serde_json::to_value(StatsPayload {
+            event: WebhookEvent::StatsUpdate,
+            channel,
+            updated_posts: posts.iter().map(Into::into).collect(),
+        })?))
+    }
+}
+
+/// Check that `fields` are names of serialized [Post] fields.
+pub fn validate_fields(fields: &[String]) -> anyhow::Result<()> {
+    let post = serde_json::to_value(Post::default())?;
+    for field in fields {
+        if post.get(field).is_none() {
+            anyhow::bail!("unknown webhook field {field:?}");
+        }
+    }
+    Ok(())
+}
+
+fn bot_payloads(post: &Post) -> impl Iterator<Item = Payload> {
+    telegram_bot::post_calls(post)
+        .into_iter()
+        .map(|(method, params)| Payload::Bot {
+            method: method.into(),
+            params,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_select_fields() {
+        let fields = vec!["id".to_string(), "text".to_string()];
+        validate_fields(&fields).unwrap();
+        assert!(validate_fields(&["body".to_string()]).is_err());
+
+        let webhook = WebhookConfig {
+            urls: Vec::new(),
+            format: WebhookFormat::Json,
+            fields: Some(fields),
+            ..Default::default()
+        };
+        let post = Post {
+            id: "test/1".to_string(),
+            text: Some("Hello".to_string()),
+            views: Some("1K".to_string()),
+            ..Default::default()
+        };
+
+        let payloads = webhook.post_payloads(&post).unwrap();
+        assert_eq!(
+            payloads,
+            [Payload::Json(
+                serde_json::json!({ "id": "test/1", "text": "Hello" })
+            )]
+        );
+    }
+
+    #[test]
+    fn test_gzip() {
+        use flate2::read::GzDecoder;
+        use std::io::Read;
+
+        let body = "a".repeat(COMPRESS_MIN_LEN);
+        let compressed = gzip(body.as_bytes()).unwrap();
+        assert!(compressed.len() < body.len());
+
+        let mut decoded = String::new();
+        GzDecoder::new(&compressed[..])
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, body);
+    }
+
+    #[test]
+    fn test_auth() {
+        let auth: WebhookAuth =
+            serde_json::from_str(r#"{"type": "header", "name": "x-api-key", "value": "key"}"#)
+                .unwrap();
+        auth.validate().unwrap();
+
+        let req = auth
+            .apply(reqwest::Client::new().post("http://localhost/"), "secret")
+            .build()
+            .unwrap();
+        assert_eq!(req.headers()["x-api-key"], "key");
+        assert!(req.headers().get("x-secret").is_none());
+
+        let bearer = WebhookAuth::Bearer {
+            token: String::new(),
+        };
+        assert!(bearer.validate().is_err());
+    }
+}
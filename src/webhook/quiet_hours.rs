@@ -0,0 +1,104 @@
+//! Daily window in which webhooks of a source are held back
+//!
+//! Posts found during quiet hours are stored as usual, their payloads
+//! are queued in the database and sent as a batch once the window ends.
+
+use chrono::{DateTime, Days, FixedOffset, NaiveTime, TimeZone, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+fn default_utc_offset() -> String {
+    "+00:00".to_string()
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub struct QuietHours {
+    /// Start of the window, like `22:00`
+    pub start: String,
+    /// End of the window, like `07:00`, windows may span midnight
+    pub end: String,
+    /// Offset of the times from UTC, like `+03:00`
+    #[serde(default = "default_utc_offset")]
+    pub utc_offset: String,
+}
+
+impl QuietHours {
+    /// Check that the times and the offset can be parsed.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let (start, end, _) = self.parse()?;
+        if start == end {
+            anyhow::bail!("quiet_hours start and end must differ");
+        }
+        Ok(())
+    }
+
+    /// End of the window `now` is in, `None` outside of quiet hours.
+    pub fn window_end(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let (start, end, offset) = self.parse().ok()?;
+        let local = now.with_timezone(&offset);
+        let (time, date) = (local.time(), local.date_naive());
+
+        let end_date = if start < end {
+            (start..end).contains(&time).then_some(date)?
+        } else if time >= start {
+            date.checked_add_days(Days::new(1))?
+        } else if time < end {
+            date
+        } else {
+            return None;
+        };
+        let end = offset
+            .from_local_datetime(&end_date.and_time(end))
+            .single()?;
+        Some(end.with_timezone(&Utc))
+    }
+
+    fn parse(&self) -> anyhow::Result<(NaiveTime, NaiveTime, FixedOffset)> {
+        let time = |value: &str| {
+            NaiveTime::parse_from_str(value, "%H:%M")
+                .map_err(|_| anyhow::anyhow!("invalid quiet_hours time {value:?}, expected HH:MM"))
+        };
+        let offset = self.utc_offset.parse::<FixedOffset>().map_err(|_| {
+            anyhow::anyhow!(
+                "invalid quiet_hours utc_offset {:?}, expected +HH:MM",
+                self.utc_offset
+            )
+        })?;
+        Ok((time(&self.start)?, time(&self.end)?, offset))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(time: &str) -> DateTime<Utc> {
+        format!("2026-03-04T{time}:00Z").parse().unwrap()
+    }
+
+    #[test]
+    fn test_window_end() {
+        let quiet = QuietHours {
+            start: "22:00".to_string(),
+            end: "07:00".to_string(),
+            utc_offset: "+03:00".to_string(),
+        };
+        quiet.validate().unwrap();
+
+        // 04:00 local, window ends the same day
+        assert_eq!(quiet.window_end(at("01:00")), Some(at("04:00")));
+        // 23:00 local, window ends the next day
+        assert_eq!(
+            quiet.window_end(at("20:00")),
+            Some("2026-03-05T04:00:00Z".parse().unwrap())
+        );
+        // 12:00 local
+        assert_eq!(quiet.window_end(at("09:00")), None);
+
+        let invalid = QuietHours {
+            utc_offset: "Europe/Berlin".to_string(),
+            ..quiet
+        };
+        assert!(invalid.validate().is_err());
+    }
+}
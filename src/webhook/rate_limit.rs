@@ -0,0 +1,73 @@
+//! Token bucket limiting how often a source sends webhooks
+//!
+//! The bucket holds a single token, so deliveries are spaced evenly
+//! instead of being sent in bursts. Waiting callers are served in order.
+
+use tokio::sync::Mutex;
+use tokio::time::{Duration, Instant, sleep};
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+#[derive(Debug)]
+pub struct RateLimiter {
+    /// Time to refill one token
+    interval: Duration,
+    bucket: Mutex<Bucket>,
+}
+
+impl RateLimiter {
+    /// Most tokens the bucket can hold
+    const BURST: f64 = 1.0;
+
+    /// Create a limiter allowing `per_minute` deliveries per minute.
+    pub fn new(per_minute: u32) -> Self {
+        Self {
+            interval: Duration::from_secs(60) / per_minute.max(1),
+            bucket: Mutex::new(Bucket {
+                tokens: Self::BURST,
+                updated: Instant::now(),
+            }),
+        }
+    }
+
+    /// Take a token, waiting until one is available.
+    pub async fn acquire(&self) {
+        // The lock is held while sleeping, so waiters keep their order
+        let mut bucket = self.bucket.lock().await;
+        let now = Instant::now();
+        let refilled =
+            now.duration_since(bucket.updated).as_secs_f64() / self.interval.as_secs_f64();
+        bucket.tokens = (bucket.tokens + refilled).min(Self::BURST);
+        bucket.updated = now;
+
+        if bucket.tokens < 1.0 {
+            sleep(self.interval.mul_f64(1.0 - bucket.tokens)).await;
+            bucket.tokens = 1.0;
+            bucket.updated = Instant::now();
+        }
+        bucket.tokens -= 1.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_spacing() {
+        // One token every 100ms
+        let limiter = RateLimiter::new(600);
+        let start = Instant::now();
+
+        for _ in 0..3 {
+            limiter.acquire().await;
+        }
+
+        // First token is available right away
+        assert!(start.elapsed() >= Duration::from_millis(200));
+    }
+}
@@ -0,0 +1,153 @@
+//! Slack incoming webhook formatter
+//!
+//! See <https://api.slack.com/reference/block-kit/blocks>
+
+use regex::Regex;
+use serde_json::json;
+use std::sync::LazyLock as Lazy;
+
+use crate::model::{ChannelInfo, Post};
+
+/// Slack limits section text to 3000 characters
+const MAX_TEXT_LEN: usize = 3000;
+
+static LINK_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\[([^\]]*)\]\(([^)\s]+)\)").unwrap());
+static BOLD_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\*\*(.+?)\*\*").unwrap());
+static ITALIC_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\*([^*\n]+)\*").unwrap());
+static STRIKE_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"~~(.+?)~~").unwrap());
+
+/// Convert markdown to Slack mrkdwn
+///
+/// `**bold**` becomes `*bold*`, `*italic*` becomes `_italic_`,
+/// `~~strike~~` becomes `~strike~` and `[text](url)` becomes `<url|text>`.
+pub fn to_mrkdwn(text: &str) -> String {
+    let text = text
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;");
+
+    // Bold is replaced with a placeholder first, so italic does not match it
+    let text = BOLD_RE.replace_all(&text, "\u{0}$1\u{0}");
+    let text = ITALIC_RE.replace_all(&text, "_${1}_");
+    let text = text.replace('\u{0}', "*");
+    let text = STRIKE_RE.replace_all(&text, "~$1~");
+    let text = LINK_RE.replace_all(&text, "<$2|$1>");
+
+    text.into_owned()
+}
+
+/// Text of the post section, falls back to media links or a placeholder.
+fn post_text(post: &Post) -> String {
+    let text = match post.text.as_deref().map(str::trim) {
+        Some(t) if !t.is_empty() => to_mrkdwn(t),
+        _ => match &post.media {
+            Some(media) if !media.is_empty() => media
+                .iter()
+                .enumerate()
+                .map(|(i, url)| format!("<{url}|media {}>", i + 1))
+                .collect::<Vec<_>>()
+                .join(" "),
+            _ => "_no text_".to_string(),
+        },
+    };
+
+    match text.char_indices().nth(MAX_TEXT_LEN - 1) {
+        Some((i, _)) => format!("{}…", &text[..i]),
+        None => text,
+    }
+}
+
+/// Build Slack payload with a context and a section block per post.
+pub fn payload(channel_name: &str, posts: &[Post]) -> serde_json::Value {
+    let blocks: Vec<serde_json::Value> = posts
+        .iter()
+        .flat_map(|post| {
+            [
+                json!({
+                    "type": "context",
+                    "elements": [{ "type": "mrkdwn", "text": format!("*{}*", to_mrkdwn(channel_name)) }],
+                }),
+                json!({
+                    "type": "section",
+                    "text": { "type": "mrkdwn", "text": post_text(post) },
+                }),
+            ]
+        })
+        .collect();
+
+    json!({
+        "text": format!("{} new post(s) in {}", posts.len(), channel_name),
+        "blocks": blocks,
+    })
+}
+
+/// Build Slack message listing changed channel fields.
+pub fn channel_changed(channel: &str, old: &ChannelInfo, new: &ChannelInfo) -> serde_json::Value {
+    let fields = [
+        ("name", &old.name, &new.name),
+        ("description", &old.description, &new.description),
+        ("photo", &old.image, &new.image),
+    ];
+    let changed: Vec<_> = fields
+        .iter()
+        .filter(|(_, old, new)| old != new)
+        .map(|(field, _, new)| match new {
+            Some(value) if *field != "photo" => format!("{field}: {}", to_mrkdwn(value)),
+            _ => format!("{field} changed"),
+        })
+        .collect();
+
+    json!({
+        "text": format!("Channel {} was updated\n{}", to_mrkdwn(channel), changed.join("\n")),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_link_conversion() {
+        assert_eq!(
+            to_mrkdwn("see [docs](https://example.com/a?b=1)"),
+            "see <https://example.com/a?b=1|docs>"
+        );
+    }
+
+    #[test]
+    fn test_bold_conversion() {
+        assert_eq!(to_mrkdwn("**bold** and *italic*"), "*bold* and _italic_");
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(to_mrkdwn("a < b & c"), "a &lt; b &amp; c");
+    }
+
+    #[test]
+    fn test_post_without_text() {
+        let post = Post {
+            id: "test/1".to_string(),
+            ..Default::default()
+        };
+
+        let payload = payload("Test", &[post]);
+        assert_eq!(payload["blocks"][1]["text"]["text"], "_no text_");
+    }
+
+    #[test]
+    fn test_channel_changed() {
+        let old = ChannelInfo {
+            name: Some("Old".to_string()),
+            description: Some("Same".to_string()),
+            image: None,
+        };
+        let new = ChannelInfo {
+            name: Some("New".to_string()),
+            ..old.clone()
+        };
+
+        let payload = channel_changed("test", &old, &new);
+        assert_eq!(payload["text"], "Channel test was updated\nname: New");
+    }
+}
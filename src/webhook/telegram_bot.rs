@@ -0,0 +1,251 @@
+//! Telegram Bot API formatter
+//!
+//! The webhook url is the `sendMessage` url of a bot with the target chat,
+//! like `https://api.telegram.org/bot<token>/sendMessage?chat_id=<chat_id>`.
+//! Posts with media are sent with `sendPhoto` or `sendMediaGroup` instead.
+//!
+//! See <https://core.telegram.org/bots/api#available-methods>
+
+use regex::Regex;
+use serde_json::{Map, Value, json};
+use std::sync::LazyLock as Lazy;
+
+use crate::model::{ChannelInfo, Post};
+
+/// Telegram limits messages to 4096 and captions to 1024 UTF-16 units
+const MAX_MESSAGE_LEN: usize = 4096;
+const MAX_CAPTION_LEN: usize = 1024;
+/// Most photos in a single `sendMediaGroup`
+const MAX_MEDIA_GROUP: usize = 10;
+
+static LINK_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\[([^\]]*)\]\(([^)\s]+)\)").unwrap());
+static BOLD_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\*\*(.+?)\*\*").unwrap());
+static ITALIC_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\*([^*\n]+)\*").unwrap());
+static STRIKE_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"~~(.+?)~~").unwrap());
+static CODE_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"`([^`\n]+)`").unwrap());
+
+/// Bot API method with its parameters, `chat_id` is added when sending
+pub type Call = (&'static str, Value);
+
+/// Convert markdown to Telegram HTML
+///
+/// `**bold**`, `*italic*`, `~~strike~~`, `` `code` `` and `[text](url)`
+/// become `<b>`, `<i>`, `<s>`, `<code>` and `<a>` tags.
+pub fn to_html(text: &str) -> String {
+    let text = text
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;");
+
+    let text = CODE_RE.replace_all(&text, "<code>$1</code>");
+    let text = BOLD_RE.replace_all(&text, "<b>$1</b>");
+    let text = ITALIC_RE.replace_all(&text, "<i>$1</i>");
+    let text = STRIKE_RE.replace_all(&text, "<s>$1</s>");
+    let text = LINK_RE.replace_all(&text, "<a href=\"$2\">$1</a>");
+
+    text.into_owned()
+}
+
+/// Url of a Bot API `method` and the chat id, taken from a `sendMessage` url.
+pub fn method_url(url: &str, method: &str) -> anyhow::Result<(url::Url, String)> {
+    let invalid = || {
+        anyhow::anyhow!(
+            "invalid telegram bot url {url:?}, expected .../bot<token>/sendMessage?chat_id=<chat_id>"
+        )
+    };
+    let mut url = url::Url::parse(url).map_err(|_| invalid())?;
+    let chat_id = url
+        .query_pairs()
+        .find(|(key, _)| key == "chat_id")
+        .map(|(_, value)| value.into_owned())
+        .filter(|id| !id.is_empty())
+        .ok_or_else(invalid)?;
+
+    url.path_segments_mut()
+        .map_err(|_| invalid())?
+        .pop()
+        .push(method);
+    url.set_query(None);
+    Ok((url, chat_id))
+}
+
+/// Bot API calls for a post, the photos first and then the text.
+///
+/// Text that fits is sent as the photo caption, longer text is split
+/// into several messages.
+pub fn post_calls(post: &Post) -> Vec<Call> {
+    let text = post
+        .text
+        .as_deref()
+        .map(str::trim)
+        .filter(|t| !t.is_empty());
+    let photos: Vec<&str> = post
+        .image_urls()
+        .into_iter()
+        .take(MAX_MEDIA_GROUP)
+        .collect();
+    let caption = text.filter(|t| !photos.is_empty() && utf16_len(t) <= MAX_CAPTION_LEN);
+
+    let mut calls = Vec::new();
+    match photos.as_slice() {
+        [] => {}
+        [photo] => {
+            let mut params = Map::new();
+            params.insert("photo".to_string(), json!(photo));
+            insert_caption(&mut params, caption);
+            calls.push(("sendPhoto", Value::Object(params)));
+        }
+        _ => {
+            let media: Vec<Value> = photos
+                .iter()
+                .enumerate()
+                .map(|(i, photo)| {
+                    let mut item = Map::new();
+                    item.insert("type".to_string(), json!("photo"));
+                    item.insert("media".to_string(), json!(photo));
+                    insert_caption(&mut item, caption.filter(|_| i == 0));
+                    Value::Object(item)
+                })
+                .collect();
+            calls.push(("sendMediaGroup", json!({ "media": media })));
+        }
+    }
+
+    if caption.is_none()
+        && let Some(text) = text
+    {
+        for chunk in split_text(text, MAX_MESSAGE_LEN) {
+            calls.push((
+                "sendMessage",
+                json!({ "text": to_html(&chunk), "parse_mode": "HTML" }),
+            ));
+        }
+    }
+
+    calls
+}
+
+/// Build message listing changed channel fields.
+pub fn channel_changed(channel: &str, old: &ChannelInfo, new: &ChannelInfo) -> Call {
+    let fields = [
+        ("name", &old.name, &new.name),
+        ("description", &old.description, &new.description),
+        ("photo", &old.image, &new.image),
+    ];
+    let changed: Vec<_> = fields
+        .iter()
+        .filter(|(_, old, new)| old != new)
+        .map(|(field, _, new)| match new {
+            Some(value) if *field != "photo" => format!("{field}: {}", to_html(value)),
+            _ => format!("{field} changed"),
+        })
+        .collect();
+
+    (
+        "sendMessage",
+        json!({
+            "text": format!("Channel <b>{}</b> was updated\n{}", to_html(channel), changed.join("\n")),
+            "parse_mode": "HTML",
+        }),
+    )
+}
+
+fn insert_caption(params: &mut Map<String, Value>, caption: Option<&str>) {
+    if let Some(caption) = caption {
+        params.insert("caption".to_string(), json!(to_html(caption)));
+        params.insert("parse_mode".to_string(), json!("HTML"));
+    }
+}
+
+fn utf16_len(text: &str) -> usize {
+    text.chars().map(char::len_utf16).sum()
+}
+
+/// Split text into chunks of at most `max` UTF-16 units, at line breaks
+/// where possible.
+fn split_text(text: &str, max: usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    let mut len = 0;
+
+    for line in text.split_inclusive('\n') {
+        let line_len = utf16_len(line);
+        if len + line_len > max && !current.is_empty() {
+            chunks.push(std::mem::take(&mut current));
+            len = 0;
+        }
+        if line_len <= max {
+            current.push_str(line);
+            len += line_len;
+            continue;
+        }
+
+        // Line alone is too long, split it anywhere
+        for c in line.chars() {
+            if len + c.len_utf16() > max {
+                chunks.push(std::mem::take(&mut current));
+                len = 0;
+            }
+            current.push(c);
+            len += c.len_utf16();
+        }
+    }
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+
+    chunks
+        .into_iter()
+        .map(|c| c.trim_end().to_string())
+        .filter(|c| !c.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_html() {
+        assert_eq!(
+            to_html("**bold** *italic* a < b [docs](https://example.com/?a=1&b=2)"),
+            "<b>bold</b> <i>italic</i> a &lt; b <a href=\"https://example.com/?a=1&amp;b=2\">docs</a>"
+        );
+    }
+
+    #[test]
+    fn test_method_url() {
+        let (url, chat_id) = method_url(
+            "https://api.telegram.org/bot123:abc/sendMessage?chat_id=-100123",
+            "sendPhoto",
+        )
+        .unwrap();
+
+        assert_eq!(
+            url.as_str(),
+            "https://api.telegram.org/bot123:abc/sendPhoto"
+        );
+        assert_eq!(chat_id, "-100123");
+        assert!(
+            method_url(
+                "https://api.telegram.org/bot123:abc/sendMessage",
+                "sendMessage"
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_long_post() {
+        let post = Post {
+            id: "test/1".to_string(),
+            text: Some("a".repeat(MAX_MESSAGE_LEN + 10)),
+            media: Some(vec!["https://example.com/1.jpg".to_string()]),
+            ..Default::default()
+        };
+
+        let methods: Vec<_> = post_calls(&post).into_iter().map(|(m, _)| m).collect();
+        assert_eq!(methods, ["sendPhoto", "sendMessage", "sendMessage"]);
+    }
+}
@@ -0,0 +1,195 @@
+//! User-supplied webhook payload templates
+//!
+//! Templates are JSON documents with `{{placeholder}}` markers, for example
+//! `{"content": "{{channel.name}}: {{post.text}}"}`. Values are JSON-escaped
+//! and missing values render as an empty string.
+//!
+//! Raw templates are sent as is with a custom content type, values are
+//! percent-encoded for `application/x-www-form-urlencoded` and inserted
+//! unchanged otherwise.
+
+use anyhow::{anyhow, bail};
+
+use super::Payload;
+use crate::model::{Channel, Post};
+
+const FORM_CONTENT_TYPE: &str = "application/x-www-form-urlencoded";
+
+/// Placeholders that can be used in a template
+const FIELDS: &[&str] = &[
+    "post.id",
+    "post.author",
+    "post.text",
+    "post.views",
+    "post.date",
+    "post.url",
+    "channel.id",
+    "channel.name",
+    "channel.description",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Text(String),
+    Field(&'static str),
+}
+
+/// Compiled webhook template
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    parts: Vec<Part>,
+    /// Content type of raw templates, `None` for JSON
+    content_type: Option<String>,
+}
+
+impl Template {
+    /// Compile JSON template, fails on unknown placeholders or when
+    /// the rendered template is not valid JSON.
+    pub fn parse(source: &str) -> anyhow::Result<Self> {
+        let template = Self::compile(source, None)?;
+        if serde_json::from_str::<serde_json::Value>(&template.render_str(None, &Post::default()))
+            .is_err()
+        {
+            bail!("webhook_template is not valid JSON");
+        }
+
+        Ok(template)
+    }
+
+    /// Compile template sent as is with the given content type.
+    pub fn parse_raw(source: &str, content_type: &str) -> anyhow::Result<Self> {
+        Self::compile(source, Some(content_type.to_string()))
+    }
+
+    fn compile(source: &str, content_type: Option<String>) -> anyhow::Result<Self> {
+        let mut parts = Vec::new();
+        let mut rest = source;
+
+        while let Some(start) = rest.find("{{") {
+            let end = rest[start..]
+                .find("}}")
+                .ok_or_else(|| anyhow!("unclosed placeholder in webhook_template"))?;
+            let name = rest[start + 2..start + end].trim();
+            let field = FIELDS
+                .iter()
+                .find(|f| **f == name)
+                .ok_or_else(|| anyhow!("unknown placeholder in webhook_template: {name}"))?;
+
+            parts.push(Part::Text(rest[..start].to_string()));
+            parts.push(Part::Field(field));
+            rest = &rest[start + end + 2..];
+        }
+        parts.push(Part::Text(rest.to_string()));
+
+        Ok(Self {
+            parts,
+            content_type,
+        })
+    }
+
+    /// Render template for a single post.
+    pub fn render(&self, channel: Option<&Channel>, post: &Post) -> anyhow::Result<Payload> {
+        let body = self.render_str(channel, post);
+        Ok(match &self.content_type {
+            Some(content_type) => Payload::Raw {
+                body,
+                content_type: content_type.clone(),
+            },
+            None => Payload::Json(serde_json::from_str(&body)?),
+        })
+    }
+
+    fn render_str(&self, channel: Option<&Channel>, post: &Post) -> String {
+        self.parts
+            .iter()
+            .map(|part| match part {
+                Part::Text(text) => text.clone(),
+                Part::Field(field) => {
+                    let value = field_value(field, channel, post).unwrap_or_default();
+                    match self.content_type.as_deref() {
+                        None => escape(&value),
+                        Some(content_type) if is_form(content_type) => {
+                            url::form_urlencoded::byte_serialize(value.as_bytes()).collect()
+                        }
+                        Some(_) => value,
+                    }
+                }
+            })
+            .collect()
+    }
+}
+
+fn field_value(field: &str, channel: Option<&Channel>, post: &Post) -> Option<String> {
+    match field {
+        "post.id" => Some(post.id.clone()),
+        "post.author" => post.author.clone(),
+        "post.text" => post.text.clone(),
+        "post.views" => post.views.clone(),
+        "post.date" => post.date.clone(),
+        "post.url" => (!post.url.is_empty()).then(|| post.url.clone()),
+        "channel.id" => channel.map(|c| c.id.clone()),
+        "channel.name" => channel.and_then(|c| c.name.clone()),
+        "channel.description" => channel.and_then(|c| c.description.clone()),
+        _ => None,
+    }
+}
+
+/// Check if content type is `application/x-www-form-urlencoded`, ignoring parameters
+fn is_form(content_type: &str) -> bool {
+    content_type
+        .split(';')
+        .next()
+        .is_some_and(|mime| mime.trim().eq_ignore_ascii_case(FORM_CONTENT_TYPE))
+}
+
+/// Escape value for use inside a JSON string
+fn escape(value: &str) -> String {
+    let quoted = serde_json::to_string(value).unwrap_or_default();
+    quoted[1..quoted.len() - 1].to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let template = Template::parse(r#"{"content": "{{ post.text }} ({{post.id}})"}"#).unwrap();
+        let post = Post {
+            id: "test/1".to_string(),
+            text: Some("say \"hi\"".to_string()),
+            ..Default::default()
+        };
+
+        let Payload::Json(payload) = template.render(None, &post).unwrap() else {
+            panic!("expected JSON payload");
+        };
+        assert_eq!(payload["content"], "say \"hi\" (test/1)");
+    }
+
+    #[test]
+    fn test_render_form() {
+        let template =
+            Template::parse_raw("text={{post.text}}&id={{post.id}}", FORM_CONTENT_TYPE).unwrap();
+        let post = Post {
+            id: "test/1".to_string(),
+            text: Some("a & b".to_string()),
+            ..Default::default()
+        };
+
+        let Payload::Raw { body, .. } = template.render(None, &post).unwrap() else {
+            panic!("expected raw payload");
+        };
+        assert_eq!(body, "text=a+%26+b&id=test%2F1");
+    }
+
+    #[test]
+    fn test_unknown_placeholder() {
+        assert!(Template::parse(r#"{"content": "{{post.secret}}"}"#).is_err());
+    }
+
+    #[test]
+    fn test_invalid_json() {
+        assert!(Template::parse(r#"{"content": {{post.text}}}"#).is_err());
+    }
+}
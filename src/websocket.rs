@@ -0,0 +1,198 @@
+//! Minimal server side WebSocket implementation
+//!
+//! Supports text, ping and close frames over an upgraded HTTP connection,
+//! see <https://datatracker.ietf.org/doc/html/rfc6455>.
+
+use axum::http::{HeaderMap, header};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use hyper::upgrade::Upgraded;
+use hyper_util::rt::TokioIo;
+use sha1::{Digest, Sha1};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadHalf, WriteHalf};
+
+/// Magic value for the `Sec-WebSocket-Accept` header
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+/// Largest accepted message, commands are small JSON objects
+const MAX_MESSAGE_LEN: usize = 1 << 20;
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xA;
+
+/// Message received from the client
+#[derive(Debug, PartialEq)]
+pub enum Message {
+    Text(String),
+    Ping(Vec<u8>),
+    Close,
+}
+
+/// Check upgrade headers and compute `Sec-WebSocket-Accept` value.
+pub fn accept_key(headers: &HeaderMap) -> Option<String> {
+    let upgrade = headers.get(header::UPGRADE)?.to_str().ok()?;
+    if !upgrade.eq_ignore_ascii_case("websocket") {
+        return None;
+    }
+    let key = headers.get(header::SEC_WEBSOCKET_KEY)?.to_str().ok()?;
+
+    let mut hasher = Sha1::new();
+    hasher.update(key.as_bytes());
+    hasher.update(ACCEPT_GUID.as_bytes());
+    Some(STANDARD.encode(hasher.finalize()))
+}
+
+type Io = TokioIo<Upgraded>;
+
+/// Split upgraded connection into reader and writer.
+pub fn split(upgraded: Upgraded) -> (Reader<ReadHalf<Io>>, Writer<WriteHalf<Io>>) {
+    let (rd, wr) = tokio::io::split(TokioIo::new(upgraded));
+    (Reader { io: rd }, Writer { io: wr })
+}
+
+pub struct Reader<R> {
+    io: R,
+}
+
+impl<R: AsyncRead + Unpin> Reader<R> {
+    /// Read the next message, joining fragmented frames.
+    ///
+    /// Binary messages and pongs are skipped.
+    pub async fn recv(&mut self) -> anyhow::Result<Message> {
+        let mut message = Vec::new();
+        let mut message_op = None;
+
+        loop {
+            let (fin, op, payload) = self.read_frame().await?;
+            match op {
+                OP_CLOSE => return Ok(Message::Close),
+                OP_PING => return Ok(Message::Ping(payload)),
+                OP_PONG => continue,
+                OP_TEXT | OP_BINARY => message_op = Some(op),
+                OP_CONTINUATION if message_op.is_some() => {}
+                _ => anyhow::bail!("unexpected websocket opcode {op:#x}"),
+            }
+
+            message.extend_from_slice(&payload);
+            if message.len() > MAX_MESSAGE_LEN {
+                anyhow::bail!("websocket message too large");
+            }
+            if !fin {
+                continue;
+            }
+
+            if message_op == Some(OP_TEXT) {
+                return Ok(Message::Text(String::from_utf8(message)?));
+            }
+            message.clear();
+            message_op = None;
+        }
+    }
+
+    async fn read_frame(&mut self) -> anyhow::Result<(bool, u8, Vec<u8>)> {
+        let mut head = [0u8; 2];
+        self.io.read_exact(&mut head).await?;
+        let fin = head[0] & 0x80 != 0;
+        let op = head[0] & 0x0F;
+        let masked = head[1] & 0x80 != 0;
+
+        let len = match head[1] & 0x7F {
+            126 => self.io.read_u16().await? as usize,
+            127 => usize::try_from(self.io.read_u64().await?)?,
+            n => n as usize,
+        };
+        if len > MAX_MESSAGE_LEN {
+            anyhow::bail!("websocket frame too large");
+        }
+        if !masked {
+            anyhow::bail!("client frames must be masked");
+        }
+
+        let mut mask = [0u8; 4];
+        self.io.read_exact(&mut mask).await?;
+        let mut payload = vec![0u8; len];
+        self.io.read_exact(&mut payload).await?;
+        for (i, b) in payload.iter_mut().enumerate() {
+            *b ^= mask[i % 4];
+        }
+
+        Ok((fin, op, payload))
+    }
+}
+
+pub struct Writer<W> {
+    io: W,
+}
+
+impl<W: AsyncWrite + Unpin> Writer<W> {
+    pub async fn send_text(&mut self, text: &str) -> anyhow::Result<()> {
+        self.write_frame(OP_TEXT, text.as_bytes()).await
+    }
+
+    pub async fn pong(&mut self, payload: &[u8]) -> anyhow::Result<()> {
+        self.write_frame(OP_PONG, payload).await
+    }
+
+    pub async fn close(&mut self) -> anyhow::Result<()> {
+        self.write_frame(OP_CLOSE, &[]).await
+    }
+
+    async fn write_frame(&mut self, op: u8, payload: &[u8]) -> anyhow::Result<()> {
+        let mut frame = Vec::with_capacity(payload.len() + 10);
+        frame.push(0x80 | op);
+        match payload.len() {
+            n if n < 126 => frame.push(n as u8),
+            n if n <= u16::MAX as usize => {
+                frame.push(126);
+                frame.extend_from_slice(&(n as u16).to_be_bytes());
+            }
+            n => {
+                frame.push(127);
+                frame.extend_from_slice(&(n as u64).to_be_bytes());
+            }
+        }
+        frame.extend_from_slice(payload);
+
+        self.io.write_all(&frame).await?;
+        self.io.flush().await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accept_key() {
+        // Example from RFC 6455
+        let mut headers = HeaderMap::new();
+        headers.insert(header::UPGRADE, "websocket".parse().unwrap());
+        headers.insert(
+            header::SEC_WEBSOCKET_KEY,
+            "dGhlIHNhbXBsZSBub25jZQ==".parse().unwrap(),
+        );
+
+        assert_eq!(
+            accept_key(&headers).as_deref(),
+            Some("s3pPLMBiTxaQ9kYGzzhZRbK+xOo=")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_masked_text() {
+        // Masked "Hello" frame from RFC 6455
+        let frame: &[u8] = &[
+            0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58,
+        ];
+        let mut reader = Reader { io: frame };
+
+        assert_eq!(
+            reader.recv().await.unwrap(),
+            Message::Text("Hello".to_string())
+        );
+    }
+}
@@ -6,12 +6,59 @@ const accentColor = style.getPropertyValue('--accent-color').trim();
 
 const activeNotifications = new Set();
 let SOURCE_TYPES = [];
+let tokenPrompt = null;
+
+// Fetch wrapper that sends the API token, asks for it on 401 and retries once
+async function apiFetch(url, options = {}, retry = true) {
+    const token = localStorage.getItem('apiToken');
+    const headers = { ...options.headers };
+    if (token) headers['Authorization'] = `Bearer ${token}`;
+
+    const res = await fetch(url, { ...options, headers });
+    if (res.status === 401 && retry) {
+        await askToken();
+        return apiFetch(url, options, false);
+    }
+    return res;
+}
+
+async function askToken() {
+    // Only show one prompt at a time
+    if (!tokenPrompt) {
+        tokenPrompt = Swal.fire({
+            customClass: { confirmButton: 'swal-confirm' },
+            title: 'API Token Required',
+            background: mantle,
+            color: textColor,
+            confirmButtonColor: accentColor,
+            confirmButtonText: 'Save',
+            input: 'password',
+            inputPlaceholder: 'Enter API token',
+            allowOutsideClick: false,
+        }).then(result => {
+            if (result.isConfirmed) {
+                localStorage.setItem('apiToken', result.value);
+            }
+            tokenPrompt = null;
+        });
+    }
+    await tokenPrompt;
+}
 
 async function loadSourceTypes() {
-    const res = await fetch('/sources/types');
+    const res = await apiFetch('/sources/types');
     SOURCE_TYPES = await res.json();
 }
 
+function fieldType(def) {
+    // Optional fields have type like ["string", "null"]
+    const types = [].concat(def.type ?? []);
+    if (types.includes('integer')) return 'number';
+    if (types.includes('boolean')) return 'checkbox';
+    if (types.includes('object') || types.includes('array')) return 'json';
+    return 'text';
+}
+
 function schemaToFields(schema) {
     if (!schema) return [];
     const props = schema.properties ?? {};
@@ -19,24 +66,72 @@ function schemaToFields(schema) {
     return Object.entries(props).map(([id, def]) => ({
         id,
         label: def.title ?? id,
-        type: def.type === 'integer' ? 'number' : 'text',
+        type: fieldType(def),
         required: required.includes(id),
     }));
 }
 
+function formatValue(value) {
+    if (value === null || value === undefined) return '';
+    return typeof value === 'object' ? JSON.stringify(value) : value;
+}
+
+function escapeAttr(value) {
+    return String(value).replace(/&/g, '&amp;').replace(/"/g, '&quot;');
+}
+
 function buildSwalFields(fields, existing = null) {
-    return fields.map(f => `
-        <div class="swal-field">
-            <h4>${f.label}</h4>
-            <input 
-                id="swal-${f.id}" 
-                class="swal2-input" 
-                type="${f.type}" 
-                placeholder="${f.label} (${f.type})" 
-                value="${existing?.raw?.[f.id] ?? ''}"
-            >
-        </div>
-    `).join('');
+    return fields.map(f => {
+        const value = existing?.raw?.[f.id];
+        if (f.type === 'checkbox') {
+            return `
+                <div class="swal-field">
+                    <h4>${f.label}</h4>
+                    <input id="swal-${f.id}" type="checkbox" ${value ? 'checked' : ''}>
+                </div>
+            `;
+        }
+        return `
+            <div class="swal-field">
+                <h4>${f.label}</h4>
+                <input 
+                    id="swal-${f.id}" 
+                    class="swal2-input" 
+                    type="${f.type === 'number' ? 'number' : 'text'}" 
+                    placeholder="${f.label} (${f.type})" 
+                    value="${escapeAttr(formatValue(value))}"
+                >
+            </div>
+        `;
+    }).join('');
+}
+
+// Read field value from the form, empty optional fields are omitted
+function readField(f) {
+    const el = document.getElementById(`swal-${f.id}`);
+    if (f.type === 'checkbox') return el.checked;
+    if (el.value === '' && !f.required) return undefined;
+    if (f.type === 'number') return parseInt(el.value);
+    if (f.type === 'json') return JSON.parse(el.value);
+    return el.value;
+}
+
+// Read all fields, shows validation message and returns null on invalid input
+function readFields(fields) {
+    const raw = {};
+    for (const f of fields) {
+        try {
+            raw[f.id] = readField(f);
+        } catch {
+            Swal.showValidationMessage(`${f.label} must be valid JSON`);
+            return null;
+        }
+        if (f.required && !raw[f.id] && raw[f.id] !== 0) {
+            Swal.showValidationMessage(`${f.label} is required`);
+            return null;
+        }
+    }
+    return raw;
 }
 
 async function health() {
@@ -56,7 +151,7 @@ async function health() {
 
 async function fetchSources() {
     try {
-        const res = await fetch('/sources');
+        const res = await apiFetch('/sources');
         const data = await res.json();
         const container = document.getElementById('sources-list');
         container.innerHTML = '';
@@ -76,15 +171,17 @@ async function fetchSources() {
                     <div class="source-tags">
                         <span class="source-tag">${source.active ? 'Running' : 'Not Running'}</span>
                         <span class="source-tag">${def?.name ?? source.kind}</span>
+                        ${source.dry_run ? '<span class="source-tag">Dry Run</span>' : ''}
                     </div>
                 </div>
+                ${source.error ? `<p class="source-error">Stopped: ${source.error}</p>` : ''}
                 <hr>
 
                 <div class="card-body">
                     ${fields.map(f => `
                         <div>
                             <span class="source-attribute">${f.label}:</span>
-                            <span class="spoiler">${raw[f.id] ?? '—'}</span>
+                            <span class="spoiler">${formatValue(raw[f.id]) || '—'}</span>
                         </div>
                     `).join('')}
                 </div>
@@ -95,6 +192,10 @@ async function fetchSources() {
                         <button class="source-control info" data-action="edit" data-id="${source.id}">
                             <i data-lucide="pencil"></i> Edit
                         </button>
+                        ${source.error ? `
+                        <button class="source-control success" data-action="resume" data-id="${source.id}">
+                            <i data-lucide="play"></i> Resume
+                        </button>` : ''}
                         <button class="source-control error" data-action="delete" data-id="${source.id}">
                             <i data-lucide="trash"></i> Delete
                         </button>
@@ -117,7 +218,7 @@ async function fetchSources() {
 }
 
 async function editSource(id) {
-    const res = await fetch(`/sources/${id}`);
+    const res = await apiFetch(`/sources/${id}`);
     const source = await res.json();
     const def = SOURCE_TYPES.find(t => t.kind === source.kind);
     const fields = schemaToFields(def?.fields).filter(f => f.id !== 'id');
@@ -135,25 +236,41 @@ async function editSource(id) {
         html: `<div class="swal2-html-container">${buildSwalFields(fields, source)}</div>`,
         // Prepare form
         preConfirm: () => {
-            const raw = {};
-            for (const f of fields) {
-                const el = document.getElementById(`swal-${f.id}`);
-                raw[f.id] = f.type === 'number' ? parseInt(el.value) : el.value;
-            }
+            const raw = readFields(fields);
+            if (!raw) return false;
             raw.id = id;
             return { id, kind: source.kind, raw };
         }
     }).then(result => {
         if (result.isConfirmed) {
-            fetch(`/sources/${id}`, {
+            apiFetch(`/sources/${id}`, {
                 method: 'PUT',
                 headers: { 'Content-Type': 'application/json' },
                 body: JSON.stringify(result.value)
-            }).then(() => fetchSources());
+            }).then(res => {
+                if (!res.ok) res.text().then(detail => showSourceError(res.status, id, detail));
+                fetchSources();
+            });
         }
     });
 }
 
+function showSourceError(status, id, detail) {
+    const messages = {
+        400: 'The source config is invalid.',
+        409: `Source ${id} already exists, use Edit to change it.`,
+        502: 'The source could not be started, check the server logs.',
+    };
+    Swal.fire({
+        customClass: { confirmButton: 'swal-confirm' },
+        title: 'Failed to save source',
+        background: mantle,
+        color: textColor,
+        confirmButtonColor: accentColor,
+        text: detail || (messages[status] ?? `Unexpected error (${status}).`),
+    });
+}
+
 async function addSource() {
     Swal.fire({
         customClass: {
@@ -186,33 +303,28 @@ async function addSource() {
             const kind = document.getElementById('swal-kind').value;
             const def = SOURCE_TYPES.find(t => t.kind === kind);
             const fields = schemaToFields(def?.fields);
-            const raw = {};
-
-            for (const f of fields) {
-                const el = document.getElementById(`swal-${f.id}`);
-                raw[f.id] = f.type === 'number' ? parseInt(el.value) : el.value;
-                if (f.required && !raw[f.id] && raw[f.id] !== 0) {
-                    Swal.showValidationMessage(`${f.label} is required`);
-                    return false;
-                }
-            }
+            const raw = readFields(fields);
+            if (!raw) return false;
 
-            return { id: raw.id, kind, raw };
+            return { id: raw.id ?? '', kind, raw };
         }
     }).then(result => {
         if (result.isConfirmed) {
-            fetch(`/sources`, {
+            apiFetch(`/sources`, {
                 method: 'POST',
                 headers: { 'Content-Type': 'application/json' },
                 body: JSON.stringify(result.value)
-            }).then(() => fetchSources());
+            }).then(res => {
+                if (!res.ok) res.text().then(detail => showSourceError(res.status, result.value.id, detail));
+                fetchSources();
+            });
         }
     });
 }
 
 async function fetchNotifications() {
     try {
-        const res = await fetch('/notifications');
+        const res = await apiFetch('/notifications');
         const notifications = await res.json();
 
         for (const ntf of notifications) {
@@ -232,7 +344,7 @@ async function fetchNotifications() {
 
 async function replyNotification(id, value) {
     console.log('replying to', id, 'with', value);
-    const res = await fetch(`/notifications/${id}`, {
+    const res = await apiFetch(`/notifications/${id}`, {
         method: 'POST',
         headers: { 'Content-Type': 'application/json' },
         body: JSON.stringify(value)
@@ -296,7 +408,10 @@ document.getElementById('sources-container').addEventListener('click', async (e)
     const id = btn.dataset.id;
 
     if (action === 'delete') {
-        await fetch(`/sources/${id}`, { method: 'DELETE' });
+        await apiFetch(`/sources/${id}`, { method: 'DELETE' });
+        fetchSources();
+    } else if (action === 'resume') {
+        await apiFetch(`/sources/${id}/resume`, { method: 'POST' });
         fetchSources();
     } else if (action === 'edit') {
         await editSource(id);